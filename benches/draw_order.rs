@@ -0,0 +1,63 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+use webgl_game::draw_order::{self, DrawKey};
+
+const SPRITES: u32 = 50_000;
+
+/// Sprites spread over a few layers, z indices and images, in entity order
+/// like `RenderSystem` joins them.
+fn draw_list() -> Vec<(DrawKey, u32)> {
+    let mut rng = Pcg32::seed_from_u64(1);
+    (0..SPRITES)
+        .map(|entity| {
+            let key = DrawKey {
+                layer: rng.gen_range(0, 4),
+                z: rng.gen_range(0, 16),
+                image: rng.gen_range(0, 8),
+                entity,
+            };
+            (key, entity)
+        })
+        .collect()
+}
+
+fn sort(c: &mut Criterion) {
+    let items = draw_list();
+    c.bench_function("sort 50k sprites", |b| {
+        b.iter_batched_ref(
+            || items.clone(),
+            |items| draw_order::sort(items),
+            BatchSize::LargeInput,
+        )
+    });
+
+    let mut shuffled = items.clone();
+    shuffled.shuffle(&mut Pcg32::seed_from_u64(2));
+    c.bench_function("sort 50k shuffled sprites", |b| {
+        b.iter_batched_ref(
+            || shuffled.clone(),
+            |items| draw_order::sort(items),
+            BatchSize::LargeInput,
+        )
+    });
+
+    // Frame to frame the list is mostly sorted already.
+    let mut sorted = items.clone();
+    draw_order::sort(&mut sorted);
+    c.bench_function("sort 50k sorted sprites", |b| {
+        b.iter_batched_ref(
+            || sorted.clone(),
+            |items| draw_order::sort(items),
+            BatchSize::LargeInput,
+        )
+    });
+
+    c.bench_function("texture runs of 50k sprites", |b| {
+        b.iter(|| draw_order::texture_runs(&sorted).count())
+    });
+}
+
+criterion_group!(benches, sort);
+criterion_main!(benches);
@@ -0,0 +1,54 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use vek::Vec2;
+use webgl_game::benchmark::Simulation;
+use webgl_game::physics::{Bounds, PhysicsConfig};
+
+/// A frame of `InputSystem` and `MoveSystem` with that many bunnies
+/// bouncing around, without rendering.
+fn simulation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("simulation");
+    for &bunnies in &[1_000, 10_000, 50_000] {
+        let mut simulation = Simulation::new(1);
+        simulation.spawn(bunnies);
+        simulation.step();
+        group.throughput(Throughput::Elements(bunnies.into()));
+        group.bench_function(BenchmarkId::from_parameter(bunnies), |b| {
+            b.iter(|| simulation.step())
+        });
+    }
+    group.finish();
+}
+
+/// The same with `body_collisions`, so `CollisionSystem` does its work too.
+/// The playfield is 10 times the canvas each way, so 10k bunnies have room to
+/// spread out instead of piling up 20 deep. They come in 500 a frame and
+/// scatter for a while before measuring.
+fn collisions(c: &mut Criterion) {
+    let mut group = c.benchmark_group("collisions");
+    let physics = PhysicsConfig {
+        body_collisions: true,
+        bounds: Some(Bounds {
+            min: Vec2::zero(),
+            max: Vec2::new(8000., 6000.),
+        }),
+        ..PhysicsConfig::default()
+    };
+    for &bunnies in &[1_000, 10_000] {
+        let mut simulation = Simulation::with_physics(1, physics);
+        while simulation.bunnies() < bunnies {
+            simulation.spawn(500);
+            simulation.step();
+        }
+        for _ in 0..200 {
+            simulation.step();
+        }
+        group.throughput(Throughput::Elements(bunnies.into()));
+        group.bench_function(BenchmarkId::from_parameter(bunnies), |b| {
+            b.iter(|| simulation.step())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, simulation, collisions);
+criterion_main!(benches);
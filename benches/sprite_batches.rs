@@ -0,0 +1,231 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+use specs::prelude::*;
+use specs::storage::ComponentEvent;
+use specs::{Component, DenseVecStorage, FlaggedStorage};
+use vek::{Rgba, Vec2};
+use webgl_game::camera::RenderLayers;
+use webgl_game::draw_order::DrawKey;
+use webgl_game::sprite_batch::{BatchItem, SpriteBatch, SpriteQuad, VertexData};
+
+const SPRITES: u32 = 20_000;
+/// Every tenth sprite moves, like a level full of scenery.
+const DYNAMIC_EVERY: u32 = 10;
+
+struct Sprite {
+    key: DrawKey,
+    position: Vec2<f32>,
+}
+
+fn scene() -> Vec<Sprite> {
+    let mut rng = Pcg32::seed_from_u64(1);
+    (0..SPRITES)
+        .map(|entity| Sprite {
+            key: DrawKey {
+                layer: rng.gen_range(0, 4),
+                z: 0,
+                image: rng.gen_range(0, 8),
+                entity,
+            },
+            position: Vec2::new(rng.gen_range(0., 4000.), rng.gen_range(0., 4000.)),
+        })
+        .collect()
+}
+
+// `is_multiple_of` needs Rust 1.87.
+#[allow(clippy::manual_is_multiple_of)]
+fn is_dynamic(sprite: &Sprite) -> bool {
+    sprite.key.entity % DYNAMIC_EVERY == 0
+}
+
+fn quad(sprite: &Sprite, offset: f32) -> (DrawKey, SpriteQuad) {
+    let min = sprite.position + offset;
+    let max = min + Vec2::new(26., 37.);
+    let corners = [Vec2::new(min.x, max.y), max, min, Vec2::new(max.x, min.y)];
+    let quad = SpriteQuad {
+        vertices: VertexData::new(corners, Rgba::white()),
+        item: BatchItem {
+            layers: RenderLayers::DEFAULT,
+            min,
+            max,
+            material: 0,
+        },
+    };
+    (sprite.key, quad)
+}
+
+/// Stands in for `bufferSubData`, copies the dirty ranges to the "GPU".
+fn upload(batch: &mut SpriteBatch, gpu: &mut Vec<VertexData>) -> usize {
+    let dirty = batch.take_dirty();
+    if gpu.len() != batch.len() {
+        gpu.clear();
+        gpu.extend_from_slice(batch.vertex_data());
+        return batch.len();
+    }
+    let mut uploaded = 0;
+    for range in dirty {
+        uploaded += range.len();
+        gpu[range.clone()].copy_from_slice(&batch.vertex_data()[range]);
+    }
+    uploaded
+}
+
+fn frames(c: &mut Criterion) {
+    let scene = scene();
+
+    let mut frame = 0;
+    let mut batch = SpriteBatch::default();
+    let mut gpu = Vec::new();
+    c.bench_function("frame of 20k sprites in one batch", |b| {
+        b.iter(|| {
+            frame += 1;
+            batch.rebuild(scene.iter().map(|sprite| {
+                let offset = if is_dynamic(sprite) { frame as f32 } else { 0. };
+                quad(sprite, offset)
+            }));
+            upload(&mut batch, &mut gpu)
+        })
+    });
+
+    let mut frame = 0;
+    let mut static_batch = SpriteBatch::default();
+    let mut dynamic_batch = SpriteBatch::default();
+    let mut static_gpu = Vec::new();
+    let mut dynamic_gpu = Vec::new();
+    static_batch.rebuild(
+        scene
+            .iter()
+            .filter(|sprite| !is_dynamic(sprite))
+            .map(|sprite| quad(sprite, 0.)),
+    );
+    upload(&mut static_batch, &mut static_gpu);
+    c.bench_function("frame of 20k sprites, 90% static", |b| {
+        b.iter(|| {
+            frame += 1;
+            dynamic_batch.rebuild(
+                scene
+                    .iter()
+                    .filter(|sprite| is_dynamic(sprite))
+                    .map(|sprite| quad(sprite, frame as f32)),
+            );
+            upload(&mut static_batch, &mut static_gpu)
+                + upload(&mut dynamic_batch, &mut dynamic_gpu)
+        })
+    });
+
+    // A hundred static sprites nudged every frame, scattered over the batch.
+    let mut frame = 0;
+    c.bench_function("frame of 20k sprites, 90% static, 100 moved", |b| {
+        b.iter(|| {
+            frame += 1;
+            for sprite in scene
+                .iter()
+                .filter(|sprite| !is_dynamic(sprite))
+                .step_by(180)
+            {
+                let (key, quad) = quad(sprite, (frame % 2) as f32);
+                static_batch.update(key, quad);
+            }
+            dynamic_batch.rebuild(
+                scene
+                    .iter()
+                    .filter(|sprite| is_dynamic(sprite))
+                    .map(|sprite| quad(sprite, frame as f32)),
+            );
+            upload(&mut static_batch, &mut static_gpu)
+                + upload(&mut dynamic_batch, &mut dynamic_gpu)
+        })
+    });
+
+    // The other way round, a crowd in front of a bit of scenery.
+    let mut frame = 0;
+    static_batch.rebuild(
+        scene
+            .iter()
+            .filter(|sprite| is_dynamic(sprite))
+            .map(|sprite| quad(sprite, 0.)),
+    );
+    upload(&mut static_batch, &mut static_gpu);
+    c.bench_function("frame of 20k sprites, 10% static", |b| {
+        b.iter(|| {
+            frame += 1;
+            dynamic_batch.rebuild(
+                scene
+                    .iter()
+                    .filter(|sprite| !is_dynamic(sprite))
+                    .map(|sprite| quad(sprite, frame as f32)),
+            );
+            upload(&mut static_batch, &mut static_gpu)
+                + upload(&mut dynamic_batch, &mut dynamic_gpu)
+        })
+    });
+}
+
+/// `Position` is flagged so `RenderSystem` can tell which static sprites
+/// moved. These stand in for it with and without the flag.
+#[derive(Clone, Copy)]
+struct FlaggedPosition(Vec2<f32>);
+
+impl Component for FlaggedPosition {
+    type Storage = FlaggedStorage<Self, DenseVecStorage<Self>>;
+}
+
+#[derive(Clone, Copy)]
+struct PlainPosition(Vec2<f32>);
+
+impl Component for PlainPosition {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// The cost of the flag in a mostly dynamic scene: every moving sprite sends
+/// an event each frame, which `RenderSystem::update_static` reads only to
+/// find the few that are static.
+fn position_events(c: &mut Criterion) {
+    let scene = scene();
+    let mut world = World::new();
+    world.register::<FlaggedPosition>();
+    world.register::<PlainPosition>();
+    let mut statics = BitSet::new();
+    for sprite in scene.iter() {
+        let entity = world
+            .create_entity()
+            .with(FlaggedPosition(sprite.position))
+            .with(PlainPosition(sprite.position))
+            .build();
+        // The other way round from the batches: only every tenth is static.
+        if is_dynamic(sprite) {
+            statics.add(entity.id());
+        }
+    }
+    let mut reader = world.write_storage::<FlaggedPosition>().register_reader();
+    let mut moved = BitSet::new();
+
+    c.bench_function("moving 18k of 20k positions", |b| {
+        b.iter(|| {
+            let mut positions = world.write_storage::<PlainPosition>();
+            for (position, _) in (&mut positions, !&statics).join() {
+                position.0.x += 1.;
+            }
+        })
+    });
+    c.bench_function("moving 18k of 20k flagged positions", |b| {
+        b.iter(|| {
+            let mut positions = world.write_storage::<FlaggedPosition>();
+            for (position, _) in (&mut positions, !&statics).join() {
+                position.0.x += 1.;
+            }
+            for event in positions.channel().read(&mut reader) {
+                if let ComponentEvent::Modified(id) = event {
+                    if statics.contains(*id) {
+                        moved.add(*id);
+                    }
+                }
+            }
+            moved.clear();
+        })
+    });
+}
+
+criterion_group!(benches, frames, position_events);
+criterion_main!(benches);
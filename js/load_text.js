@@ -0,0 +1,8 @@
+export function raw_load_text(source) {
+    return fetch(source).then(response => {
+        if (!response.ok) {
+            throw new Error(`${source}: ${response.status} ${response.statusText}`);
+        }
+        return response.text();
+    });
+}
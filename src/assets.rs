@@ -82,6 +82,18 @@ impl Images {
         Ok(id)
     }
 
+    /// Adds an image made in code under `name`, from RGBA bytes row by row.
+    pub fn add_pixels(&mut self, name: &str, size: Vec2<u16>, pixels: &[u8]) -> ImageId {
+        let id = ImageId {
+            id: self.images.len() as u32,
+        };
+        let image = Image::from_pixels(self.gl.clone(), size, pixels);
+
+        self.images.push(image);
+        self.names_to_id.insert(name.to_string(), id);
+        id
+    }
+
     pub fn size(&self, image_id: ImageId) -> Vec2<u16> {
         self.images
             .get(image_id.id as usize)
@@ -104,4 +116,30 @@ impl Images {
             .map(|(i, image)| (ImageId { id: i as u32 }, image))
             .collect()
     }
+
+    pub fn names(&self) -> ImageNames {
+        let mut names = vec![String::new(); self.images.len()];
+        for (name, id) in self.names_to_id.iter() {
+            names[id.id as usize] = name.clone();
+        }
+        ImageNames(names)
+    }
+}
+
+/// The names images were loaded under, by `ImageId`. `Images` can't go in the
+/// world, this can.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImageNames(pub Vec<String>);
+
+impl ImageNames {
+    pub fn name(&self, image_id: ImageId) -> Option<&str> {
+        self.0.get(image_id.id as usize).map(String::as_str)
+    }
+
+    pub fn find(&self, name: &str) -> Option<ImageId> {
+        self.0
+            .iter()
+            .position(|n| n == name)
+            .map(|id| ImageId { id: id as u32 })
+    }
 }
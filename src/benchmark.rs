@@ -0,0 +1,317 @@
+//! Bunnymark: spawns bunnies a step at a time until frames get too slow, to
+//! compare devices without holding the mouse and squinting at a graph.
+
+use crate::collision_system::CollisionSystem;
+use crate::game::init_world;
+use crate::input_system::{BunnyCount, InputSystem, SpawnRequest};
+use crate::move_system::MoveSystem;
+use crate::physics::PhysicsConfig;
+use crate::prefab::Prefabs;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use specs::prelude::*;
+use vek::Vec2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BenchmarkConfig {
+    /// Bunnies added at each step.
+    pub step: u32,
+    /// Frames left out after each step, while the new bunnies are uploaded
+    /// and the browser catches up.
+    pub settle_frames: u32,
+    /// Frames measured at each step.
+    pub window_frames: u32,
+    /// The benchmark stops once the frames of a step average more than this.
+    /// A little over 1000 / 60 so vsync jitter doesn't end it early.
+    pub threshold_ms: f64,
+    /// Stops here even if frames are still fast.
+    pub max_bunnies: u32,
+}
+
+impl Default for BenchmarkConfig {
+    fn default() -> Self {
+        BenchmarkConfig {
+            step: 1000,
+            settle_frames: 30,
+            window_frames: 60,
+            threshold_ms: 18.,
+            max_bunnies: 1_000_000,
+        }
+    }
+}
+
+impl BenchmarkConfig {
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+/// Frame times are counted in buckets this wide, anything slower than
+/// `HISTOGRAM_BUCKETS` of them goes in the last one.
+const HISTOGRAM_BUCKET_MS: f64 = 1.;
+const HISTOGRAM_BUCKETS: usize = 50;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BenchmarkResult {
+    /// The most bunnies whose frames stayed under `threshold_ms`.
+    pub max_bunnies_at_60fps: u32,
+    /// Bunnies at the step that was too slow, or at `max_bunnies`.
+    pub bunnies: u32,
+    /// Whether it ran into `max_bunnies` before getting too slow.
+    pub reached_limit: bool,
+    /// Frames measured, without the settling ones.
+    pub frames: u32,
+    pub config: BenchmarkConfig,
+    /// Measured frame times, leaving out empty buckets.
+    pub histogram: Vec<HistogramBucket>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct HistogramBucket {
+    pub min_ms: f64,
+    /// `None` for the last bucket.
+    pub max_ms: Option<f64>,
+    pub frames: u32,
+}
+
+/// A running benchmark. `Game` feeds it every frame and spawns what it asks
+/// for.
+#[derive(Clone, Debug)]
+pub struct Benchmark {
+    config: BenchmarkConfig,
+    /// Frames since the last step.
+    frame: u32,
+    /// Frame time measured at the current step.
+    step_ms: f64,
+    passed: u32,
+    histogram: [u32; HISTOGRAM_BUCKETS + 1],
+    result: Option<BenchmarkResult>,
+}
+
+impl Benchmark {
+    pub fn new(config: BenchmarkConfig) -> Self {
+        Benchmark {
+            config,
+            frame: 0,
+            step_ms: 0.,
+            passed: 0,
+            histogram: [0; HISTOGRAM_BUCKETS + 1],
+            result: None,
+        }
+    }
+
+    /// Takes the last frame's time and the current number of bunnies, and
+    /// returns how many bunnies to spawn next.
+    pub fn update(&mut self, frame_ms: f64, bunnies: u32) -> u32 {
+        if self.result.is_some() {
+            return 0;
+        }
+        self.frame += 1;
+        if self.frame <= self.config.settle_frames {
+            return 0;
+        }
+
+        let bucket = (frame_ms / HISTOGRAM_BUCKET_MS) as usize;
+        self.histogram[bucket.min(HISTOGRAM_BUCKETS)] += 1;
+        self.step_ms += frame_ms;
+        if self.frame < self.config.settle_frames + self.config.window_frames {
+            return 0;
+        }
+
+        let average = self.step_ms / f64::from(self.config.window_frames.max(1));
+        self.frame = 0;
+        self.step_ms = 0.;
+        if average > self.config.threshold_ms {
+            self.finish(bunnies, false);
+            return 0;
+        }
+        self.passed = bunnies;
+        if bunnies.saturating_add(self.config.step) > self.config.max_bunnies {
+            self.finish(bunnies, true);
+            return 0;
+        }
+        self.config.step
+    }
+
+    /// `None` while it's still running.
+    pub fn result(&self) -> Option<&BenchmarkResult> {
+        self.result.as_ref()
+    }
+
+    fn finish(&mut self, bunnies: u32, reached_limit: bool) {
+        let histogram = self
+            .histogram
+            .iter()
+            .enumerate()
+            .filter(|(_, frames)| **frames > 0)
+            .map(|(bucket, frames)| HistogramBucket {
+                min_ms: bucket as f64 * HISTOGRAM_BUCKET_MS,
+                max_ms: if bucket < HISTOGRAM_BUCKETS {
+                    Some((bucket + 1) as f64 * HISTOGRAM_BUCKET_MS)
+                } else {
+                    None
+                },
+                frames: *frames,
+            })
+            .collect();
+        self.result = Some(BenchmarkResult {
+            max_bunnies_at_60fps: self.passed,
+            bunnies,
+            reached_limit,
+            frames: self.histogram.iter().sum(),
+            config: self.config,
+            histogram,
+        });
+    }
+}
+
+/// Just the simulation part of the bunnymark, `InputSystem`, `MoveSystem`
+/// and `CollisionSystem` on an 800x600 canvas, for `cargo bench`.
+pub struct Simulation {
+    world: World,
+    input_system: InputSystem,
+    move_system: MoveSystem,
+    collision_system: CollisionSystem,
+}
+
+impl Simulation {
+    pub fn new(seed: u64) -> Self {
+        Simulation::with_physics(seed, PhysicsConfig::default())
+    }
+
+    /// E.g. with `body_collisions`, which are off by default.
+    pub fn with_physics(seed: u64, physics: PhysicsConfig) -> Self {
+        let prefabs = Prefabs::builtin(&[("/assets/images/bunny.png", Vec2::new(26, 37))]);
+        let mut world = init_world(Vec2::new(800, 600), seed, prefabs)
+            .expect("assets/prefabs.json has no bunny");
+        world.insert(physics);
+        let input_system = InputSystem::new(&mut world);
+        Simulation {
+            world,
+            input_system,
+            move_system: MoveSystem,
+            collision_system: CollisionSystem::new(),
+        }
+    }
+
+    /// Spawns `bunnies` more at the next `step`.
+    pub fn spawn(&mut self, bunnies: u32) {
+        self.world.fetch_mut::<SpawnRequest>().0 += bunnies;
+    }
+
+    pub fn bunnies(&self) -> u32 {
+        self.world.fetch::<BunnyCount>().0
+    }
+
+    pub fn step(&mut self) {
+        self.input_system.run_now(&self.world);
+        self.move_system.run_now(&self.world);
+        self.collision_system.run_now(&self.world);
+        self.world.maintain();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> BenchmarkConfig {
+        BenchmarkConfig {
+            step: 100,
+            settle_frames: 2,
+            window_frames: 4,
+            threshold_ms: 18.,
+            max_bunnies: 10_000,
+        }
+    }
+
+    /// Runs `benchmark` with frames taking `frame_ms(bunnies)`.
+    fn run(benchmark: &mut Benchmark, mut frame_ms: impl FnMut(u32) -> f64) -> u32 {
+        let mut bunnies = 0;
+        for _ in 0..1000 {
+            bunnies += benchmark.update(frame_ms(bunnies), bunnies);
+        }
+        bunnies
+    }
+
+    #[test]
+    fn steps_up_until_frames_get_slow() {
+        let mut benchmark = Benchmark::new(config());
+        let bunnies = run(&mut benchmark, |bunnies| 10. + f64::from(bunnies) * 0.03);
+        let result = benchmark.result().unwrap();
+        // 200 bunnies take 16 ms, 300 take 19.
+        assert_eq!(result.max_bunnies_at_60fps, 200);
+        assert_eq!(result.bunnies, 300);
+        assert_eq!(bunnies, 300);
+        assert!(!result.reached_limit);
+        // Four steps of four measured frames each.
+        assert_eq!(result.frames, 16);
+        let buckets: Vec<_> = result
+            .histogram
+            .iter()
+            .map(|bucket| (bucket.min_ms, bucket.frames))
+            .collect();
+        assert_eq!(buckets, vec![(10., 4), (13., 4), (16., 4), (19., 4)]);
+    }
+
+    #[test]
+    fn settling_frames_are_left_out() {
+        let mut benchmark = Benchmark::new(config());
+        let mut frames = 0;
+        run(&mut benchmark, |bunnies| {
+            frames += 1;
+            // A spike right after every step.
+            if frames % 6 == 1 {
+                100.
+            } else if bunnies < 300 {
+                10.
+            } else {
+                20.
+            }
+        });
+        let result = benchmark.result().unwrap();
+        assert_eq!(result.max_bunnies_at_60fps, 200);
+        assert!(result
+            .histogram
+            .iter()
+            .all(|bucket| bucket.max_ms.is_some()));
+    }
+
+    #[test]
+    fn stops_at_the_limit() {
+        let mut benchmark = Benchmark::new(BenchmarkConfig {
+            max_bunnies: 250,
+            ..config()
+        });
+        let bunnies = run(&mut benchmark, |_| 50.5);
+        assert_eq!(bunnies, 0);
+        let result = benchmark.result().unwrap();
+        assert_eq!(result.max_bunnies_at_60fps, 0);
+        let slowest = HistogramBucket {
+            min_ms: 50.,
+            max_ms: None,
+            frames: 4,
+        };
+        assert_eq!(result.histogram, vec![slowest]);
+
+        let mut benchmark = Benchmark::new(BenchmarkConfig {
+            max_bunnies: 250,
+            ..config()
+        });
+        run(&mut benchmark, |_| 1.);
+        let result = benchmark.result().unwrap();
+        assert_eq!(result.max_bunnies_at_60fps, 200);
+        assert!(result.reached_limit);
+    }
+
+    #[test]
+    fn simulation_spawns_requested_bunnies() {
+        let mut simulation = Simulation::new(1);
+        simulation.spawn(50);
+        simulation.step();
+        assert_eq!(simulation.bunnies(), 53);
+        simulation.step();
+        assert_eq!(simulation.bunnies(), 53);
+    }
+}
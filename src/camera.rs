@@ -0,0 +1,485 @@
+use crate::physics::Bounds;
+use specs::{Component, DenseVecStorage, Entity, FlaggedStorage};
+use vek::column_major::Mat4;
+use vek::{Rgba, Vec2, Vec3};
+
+/// Which cameras draw an entity: those whose `layers` share a bit with it.
+/// Entities without one are on `RenderLayers::DEFAULT`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RenderLayers(pub u32);
+
+impl Component for RenderLayers {
+    type Storage = FlaggedStorage<Self, DenseVecStorage<Self>>;
+}
+
+impl Default for RenderLayers {
+    fn default() -> Self {
+        RenderLayers::DEFAULT
+    }
+}
+
+impl RenderLayers {
+    pub const DEFAULT: RenderLayers = RenderLayers(1);
+    /// Small things not worth drawing on overviews like the minimap.
+    pub const DETAIL: RenderLayers = RenderLayers(1 << 1);
+    /// Drawn over everything else in canvas pixels, like the counter.
+    pub const HUD: RenderLayers = RenderLayers(1 << 2);
+    pub const ALL: RenderLayers = RenderLayers(u32::MAX);
+
+    pub fn intersects(self, other: RenderLayers) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn without(self, other: RenderLayers) -> RenderLayers {
+        RenderLayers(self.0 & !other.0)
+    }
+}
+
+/// Part of the canvas a camera draws to, as fractions of the canvas size
+/// from its bottom left corner.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub position: Vec2<f32>,
+    pub size: Vec2<f32>,
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Viewport {
+            position: Vec2::zero(),
+            size: Vec2::one(),
+        }
+    }
+}
+
+impl Viewport {
+    /// The viewport in canvas pixels, bottom left corner and size.
+    pub fn pixels(&self, canvas_size: Vec2<i32>) -> (Vec2<i32>, Vec2<i32>) {
+        let canvas: Vec2<f32> = canvas_size.numcast().unwrap();
+        let min = (self.position * canvas).round();
+        let max = ((self.position + self.size) * canvas).round();
+        (min.numcast().unwrap(), (max - min).numcast().unwrap())
+    }
+
+    /// Whether the canvas pixel `point` (origin top left, y down) is inside
+    /// the viewport.
+    pub fn contains(&self, point: Vec2<f32>, canvas_size: Vec2<i32>) -> bool {
+        let (min, size) = self.pixels(canvas_size);
+        let point = Vec2::new(point.x, canvas_size.y as f32 - point.y);
+        let min: Vec2<f32> = min.numcast().unwrap();
+        let max = min + size.numcast::<f32>().unwrap();
+        point.x >= min.x && point.y >= min.y && point.x < max.x && point.y < max.y
+    }
+}
+
+/// Keeps an entity in view.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Follow {
+    pub target: Entity,
+    /// Half size of the area around the camera position the target can move
+    /// in without the camera moving, in world units.
+    pub deadzone: Vec2<f32>,
+    /// Fraction of the distance to the target covered every frame, 1 snaps
+    /// right to it.
+    pub smoothing: f32,
+}
+
+/// Screen shake driven by `trauma`, which decays back to zero by `decay` every
+/// frame. The shake grows with the square of the trauma.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Shake {
+    pub trauma: f32,
+    pub decay: f32,
+    /// Offset at full trauma, in world units.
+    pub max_offset: f32,
+    /// Rotation at full trauma, in radians.
+    pub max_rotation: f32,
+}
+
+impl Default for Shake {
+    fn default() -> Self {
+        Shake {
+            trauma: 0.,
+            decay: 0.02,
+            max_offset: 12.,
+            max_rotation: 0.05,
+        }
+    }
+}
+
+/// What part of the world is drawn where on the canvas.
+///
+/// `position` is the world point drawn at the center of the viewport. Zoom is
+/// in canvas pixels per world unit, and the view is rotated counter clockwise
+/// by `rotation` radians around the position.
+///
+/// Cameras are drawn in ascending `order`, so later ones end up on top where
+/// their viewports overlap.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera2D {
+    pub position: Vec2<f32>,
+    pub zoom: f32,
+    pub rotation: f32,
+    pub viewport: Viewport,
+    pub order: i32,
+    /// Entities drawn by this camera, see `RenderLayers`.
+    pub layers: RenderLayers,
+    /// Color the viewport is cleared to first, `None` draws over whatever
+    /// is there.
+    pub clear_color: Option<Rgba<f32>>,
+    /// Zooms out so all of the bounds are in view, as for a minimap. Without
+    /// `bounds` it fits the playfield from `PhysicsConfig`.
+    pub fit_bounds: bool,
+    pub follow: Option<Follow>,
+    /// Area the view stays inside, `None` lets it go anywhere.
+    pub bounds: Option<Bounds>,
+    pub shake: Shake,
+    /// Whether pointer input over the viewport goes to this camera. Overlays
+    /// like the HUD turn it off so clicks reach the cameras below.
+    pub interactive: bool,
+    shake_offset: Vec2<f32>,
+    shake_rotation: f32,
+}
+
+impl Component for Camera2D {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl Camera2D {
+    pub fn new(position: Vec2<f32>) -> Self {
+        Camera2D {
+            position,
+            zoom: 1.,
+            rotation: 0.,
+            viewport: Viewport::default(),
+            order: 0,
+            layers: RenderLayers::ALL,
+            clear_color: Some(Rgba::zero()),
+            fit_bounds: false,
+            follow: None,
+            bounds: None,
+            shake: Shake::default(),
+            interactive: true,
+            shake_offset: Vec2::zero(),
+            shake_rotation: 0.,
+        }
+    }
+
+    /// A camera showing the canvas like a plain pixel mapping, world origin
+    /// at the bottom left corner.
+    pub fn for_canvas(canvas_size: Vec2<i32>) -> Self {
+        let size: Vec2<f32> = canvas_size.numcast().unwrap();
+        Camera2D::new(size / 2.)
+    }
+
+    /// Shakes the camera more, up to full trauma.
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.shake.trauma = (self.shake.trauma + amount).min(1.);
+    }
+
+    /// Position and rotation the view is drawn with, shake included.
+    fn eye(&self) -> (Vec2<f32>, f32) {
+        (
+            self.position + self.shake_offset,
+            self.rotation + self.shake_rotation,
+        )
+    }
+
+    /// Half size of the axis aligned box around the visible area, in world
+    /// units. Shake isn't included.
+    pub fn half_extents(&self, canvas_size: Vec2<i32>) -> Vec2<f32> {
+        self.rotated_half_extents(canvas_size, self.rotation)
+    }
+
+    fn rotated_half_extents(&self, canvas_size: Vec2<i32>, rotation: f32) -> Vec2<f32> {
+        let (_, size) = self.viewport.pixels(canvas_size);
+        let half = size.numcast::<f32>().unwrap() / (2. * self.zoom);
+        let (sin, cos) = rotation.sin_cos();
+        Vec2::new(
+            half.x * cos.abs() + half.y * sin.abs(),
+            half.x * sin.abs() + half.y * cos.abs(),
+        )
+    }
+
+    /// Axis aligned box around what's drawn this frame, shake included.
+    pub fn view_bounds(&self, canvas_size: Vec2<i32>) -> Bounds {
+        let (eye, rotation) = self.eye();
+        let half = self.rotated_half_extents(canvas_size, rotation);
+        Bounds {
+            min: eye - half,
+            max: eye + half,
+        }
+    }
+
+    /// Maps world coordinates to clip space for the camera's viewport.
+    pub fn view_projection(&self, canvas_size: Vec2<i32>) -> Mat4<f32> {
+        let (_, size) = self.viewport.pixels(canvas_size);
+        let size: Vec2<f32> = size.numcast().unwrap();
+        let (eye, rotation) = self.eye();
+        let scale: Vec2<f32> = Vec2::broadcast(2. * self.zoom) / size;
+        let scaling = Mat4::<f32>::scaling_3d(Vec3::new(scale.x, scale.y, 1.));
+        let rotation = Mat4::<f32>::rotation_z(-rotation);
+        let translation = Mat4::<f32>::translation_2d(-eye);
+        scaling * rotation * translation
+    }
+
+    /// Moves towards the followed entity at `target`.
+    pub fn follow(&mut self, target: Vec2<f32>) {
+        let follow = match self.follow {
+            Some(follow) => follow,
+            None => return,
+        };
+        let distance = target - self.position;
+        let inside = Vec2::partial_max(
+            -follow.deadzone,
+            Vec2::partial_min(distance, follow.deadzone),
+        );
+        self.position += (distance - inside) * follow.smoothing;
+    }
+
+    /// Zoom at which all of `bounds` fits in the viewport.
+    pub fn fit_zoom(&self, bounds: &Bounds, canvas_size: Vec2<i32>) -> f32 {
+        let (_, size) = self.viewport.pixels(canvas_size);
+        let scale = size.numcast::<f32>().unwrap() / (bounds.max - bounds.min);
+        scale.reduce_partial_min()
+    }
+
+    /// Moves the camera so the view stays inside `bounds`, or centers it on
+    /// them along the axes the view is larger.
+    pub fn clamp(&mut self, bounds: &Bounds, canvas_size: Vec2<i32>) {
+        let half = self.half_extents(canvas_size);
+        let min = bounds.min + half;
+        let max = bounds.max - half;
+        let center = (bounds.min + bounds.max) / 2.;
+        for axis in 0..2 {
+            self.position[axis] = if min[axis] > max[axis] {
+                center[axis]
+            } else {
+                self.position[axis].max(min[axis]).min(max[axis])
+            };
+        }
+    }
+
+    /// Decays the trauma and picks this frame's shake.
+    pub fn update_shake(&mut self, frame: u32) {
+        self.shake.trauma = (self.shake.trauma - self.shake.decay).max(0.);
+        let amount = self.shake.trauma * self.shake.trauma;
+        // Sines of unrelated frequencies wobble enough to pass for noise and
+        // keep runs reproducible.
+        let t = frame as f32;
+        let noise = Vec3::new((t * 1.9).sin(), (t * 2.7 + 1.).sin(), (t * 3.3 + 2.).sin());
+        self.shake_offset = Vec2::new(noise.x, noise.y) * self.shake.max_offset * amount;
+        self.shake_rotation = noise.z * self.shake.max_rotation * amount;
+    }
+}
+
+/// The interactive camera drawn on top at the canvas pixel `point`, for
+/// routing pointer input to the right view.
+pub fn camera_at<'a>(
+    cameras: impl IntoIterator<Item = (Entity, &'a Camera2D)>,
+    point: Vec2<f32>,
+    canvas_size: Vec2<i32>,
+) -> Option<(Entity, &'a Camera2D)> {
+    cameras
+        .into_iter()
+        .filter(|(_, camera)| camera.interactive && camera.viewport.contains(point, canvas_size))
+        .max_by_key(|(entity, camera)| (camera.order, entity.id()))
+}
+
+/// Converts canvas pixel coordinates (origin top left, y down) to world
+/// coordinates as seen by `camera`.
+pub fn screen_to_world(camera: &Camera2D, point: Vec2<f32>, canvas_size: Vec2<i32>) -> Vec2<f32> {
+    let (min, size) = camera.viewport.pixels(canvas_size);
+    let center = min.numcast::<f32>().unwrap() + size.numcast::<f32>().unwrap() / 2.;
+    let point = Vec2::new(point.x, canvas_size.y as f32 - point.y);
+    let (eye, rotation) = camera.eye();
+    ((point - center) / camera.zoom).rotated_z(rotation) + eye
+}
+
+/// Converts world coordinates to canvas pixel coordinates (origin top left,
+/// y down) as seen by `camera`.
+pub fn world_to_screen(camera: &Camera2D, point: Vec2<f32>, canvas_size: Vec2<i32>) -> Vec2<f32> {
+    let (min, size) = camera.viewport.pixels(canvas_size);
+    let center = min.numcast::<f32>().unwrap() + size.numcast::<f32>().unwrap() / 2.;
+    let (eye, rotation) = camera.eye();
+    let point = (point - eye).rotated_z(-rotation) * camera.zoom + center;
+    Vec2::new(point.x, canvas_size.y as f32 - point.y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use specs::{Builder, World, WorldExt};
+    use std::f32::consts::FRAC_PI_2;
+
+    const CANVAS: Vec2<i32> = Vec2 { x: 800, y: 600 };
+
+    fn assert_close(actual: Vec2<f32>, expected: Vec2<f32>) {
+        assert!(
+            (actual - expected).magnitude() < 1e-3,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    fn clip(camera: &Camera2D, point: Vec2<f32>) -> Vec2<f32> {
+        let clip = camera.view_projection(CANVAS) * vek::Vec4::new(point.x, point.y, 0., 1.);
+        Vec2::new(clip.x, clip.y)
+    }
+
+    #[test]
+    fn default_camera_maps_pixels_to_world_units() {
+        let camera = Camera2D::for_canvas(CANVAS);
+        assert_eq!(
+            screen_to_world(&camera, Vec2::new(10., 50.), CANVAS),
+            Vec2::new(10., 550.)
+        );
+        assert_close(clip(&camera, Vec2::zero()), Vec2::new(-1., -1.));
+        assert_close(clip(&camera, Vec2::new(800., 600.)), Vec2::new(1., 1.));
+    }
+
+    #[test]
+    fn conversions_round_trip_through_zoom_rotation_and_viewport() {
+        let mut camera = Camera2D::new(Vec2::new(1000., -200.));
+        camera.zoom = 2.5;
+        camera.rotation = 0.7;
+        camera.viewport = Viewport {
+            position: Vec2::new(0.5, 0.),
+            size: Vec2::new(0.5, 0.5),
+        };
+        for point in [
+            Vec2::new(0., 0.),
+            Vec2::new(612., 413.),
+            Vec2::new(799., 1.),
+        ]
+        .iter()
+        {
+            let world = screen_to_world(&camera, *point, CANVAS);
+            assert_close(world_to_screen(&camera, world, CANVAS), *point);
+        }
+
+        // The viewport's center is the bottom right quarter's center.
+        assert_close(
+            screen_to_world(&camera, Vec2::new(600., 450.), CANVAS),
+            camera.position,
+        );
+    }
+
+    #[test]
+    fn zoom_and_rotation_match_the_projection() {
+        let mut camera = Camera2D::new(Vec2::new(100., 100.));
+        camera.zoom = 2.;
+        camera.rotation = FRAC_PI_2;
+
+        // Turning the camera counter clockwise turns the world clockwise on
+        // screen, so what's right of the camera ends up below it.
+        let right = Vec2::new(175., 100.);
+        assert_close(clip(&camera, right), Vec2::new(0., -0.5));
+        assert_close(
+            world_to_screen(&camera, right, CANVAS),
+            Vec2::new(400., 450.),
+        );
+        assert_close(camera.half_extents(CANVAS), Vec2::new(150., 200.));
+    }
+
+    #[test]
+    fn follow_waits_for_the_target_to_leave_the_deadzone() {
+        let mut world = World::new();
+        let target = world.create_entity().build();
+        let mut camera = Camera2D::new(Vec2::zero());
+        camera.follow = Some(Follow {
+            target,
+            deadzone: Vec2::new(50., 20.),
+            smoothing: 0.5,
+        });
+
+        camera.follow(Vec2::new(40., -10.));
+        assert_eq!(camera.position, Vec2::zero());
+
+        camera.follow(Vec2::new(150., -30.));
+        assert_eq!(camera.position, Vec2::new(50., -5.));
+    }
+
+    #[test]
+    fn clamping_keeps_the_view_inside_the_bounds() {
+        let bounds = Bounds {
+            min: Vec2::zero(),
+            max: Vec2::new(2000., 500.),
+        };
+        let mut camera = Camera2D::new(Vec2::new(-100., 1000.));
+        camera.clamp(&bounds, CANVAS);
+        // Too short to fit vertically, so it's centered.
+        assert_eq!(camera.position, Vec2::new(400., 250.));
+
+        camera.zoom = 2.;
+        camera.position = Vec2::new(1900., 0.);
+        camera.clamp(&bounds, CANVAS);
+        assert_eq!(camera.position, Vec2::new(1800., 150.));
+    }
+
+    #[test]
+    fn pointers_go_to_the_topmost_camera() {
+        let mut world = World::new();
+        let main = world.create_entity().build();
+        let minimap = world.create_entity().build();
+        let main_camera = Camera2D::for_canvas(CANVAS);
+        let mut minimap_camera = Camera2D::new(Vec2::zero());
+        minimap_camera.order = 1;
+        minimap_camera.viewport = Viewport {
+            position: Vec2::new(0.75, 0.75),
+            size: Vec2::new(0.25, 0.25),
+        };
+        let hud = world.create_entity().build();
+        let mut hud_camera = Camera2D::for_canvas(CANVAS);
+        hud_camera.order = 2;
+        hud_camera.interactive = false;
+        let cameras = [
+            (minimap, &minimap_camera),
+            (hud, &hud_camera),
+            (main, &main_camera),
+        ];
+
+        let at =
+            |point| camera_at(cameras.iter().copied(), point, CANVAS).map(|(entity, _)| entity);
+        assert_eq!(at(Vec2::new(790., 10.)), Some(minimap));
+        assert_eq!(at(Vec2::new(790., 200.)), Some(main));
+        assert_eq!(at(Vec2::new(900., 10.)), None);
+    }
+
+    #[test]
+    fn fitting_shows_all_of_the_bounds() {
+        let bounds = Bounds {
+            min: Vec2::new(-1000., 0.),
+            max: Vec2::new(1000., 500.),
+        };
+        let mut camera = Camera2D::new(Vec2::zero());
+        camera.viewport.size = Vec2::new(0.25, 0.25);
+        camera.zoom = camera.fit_zoom(&bounds, CANVAS);
+        assert_eq!(camera.zoom, 0.1);
+        camera.clamp(&bounds, CANVAS);
+        assert_close(camera.half_extents(CANVAS), Vec2::new(1000., 750.));
+        assert_eq!(camera.position, Vec2::new(0., 250.));
+    }
+
+    #[test]
+    fn shake_fades_out() {
+        let mut camera = Camera2D::new(Vec2::zero());
+        camera.add_trauma(0.7);
+        camera.add_trauma(0.7);
+        assert_eq!(camera.shake.trauma, 1.);
+
+        camera.update_shake(1);
+        let shaken = world_to_screen(&camera, Vec2::zero(), CANVAS);
+        assert!((shaken - Vec2::new(400., 300.)).magnitude() > 1.);
+
+        for frame in 2..100 {
+            camera.update_shake(frame);
+        }
+        assert_eq!(camera.shake.trauma, 0.);
+        assert_eq!(
+            world_to_screen(&camera, Vec2::zero(), CANVAS),
+            Vec2::new(400., 300.)
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use crate::camera::Camera2D;
+use crate::components::Position;
+use crate::physics::PhysicsConfig;
+use crate::render_system::{Transform, WindowSize};
+use specs::prelude::*;
+
+/// Moves cameras after their follow targets, keeps them inside their bounds
+/// and shakes them. Runs after everything else has moved.
+pub struct CameraSystem {
+    frame: u32,
+}
+
+impl CameraSystem {
+    pub fn new() -> Self {
+        CameraSystem { frame: 0 }
+    }
+}
+
+impl Default for CameraSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> System<'a> for CameraSystem {
+    type SystemData = (
+        WriteStorage<'a, Camera2D>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Transform>,
+        Read<'a, PhysicsConfig>,
+        Read<'a, WindowSize>,
+    );
+
+    fn run(&mut self, (mut cameras, positions, transforms, config, window_size): Self::SystemData) {
+        self.frame = self.frame.wrapping_add(1);
+        for camera in (&mut cameras).join() {
+            if let Some(follow) = camera.follow {
+                if let Some(position) = positions.get(follow.target) {
+                    let target = match transforms.get(follow.target) {
+                        Some(transform) => transform.world_position(position.0),
+                        None => position.0,
+                    };
+                    camera.follow(target);
+                }
+            }
+            let bounds = match camera.bounds {
+                Some(bounds) => Some(bounds),
+                None if camera.fit_bounds => Some(config.bounds(window_size.size)),
+                None => None,
+            };
+            if let Some(bounds) = bounds {
+                if camera.fit_bounds {
+                    camera.zoom = camera.fit_zoom(&bounds, window_size.size);
+                }
+                camera.clamp(&bounds, window_size.size);
+            }
+            camera.update_shake(self.frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Follow;
+    use crate::physics::Bounds;
+    use vek::Vec2;
+
+    /// A world with an 800x600 canvas and a playfield as big, and a camera
+    /// following a target at `target`.
+    fn setup(bounds: Option<Bounds>, target: Vec2<f32>) -> (World, Entity, Entity) {
+        let mut world = World::new();
+        world.register::<Camera2D>();
+        world.register::<Position>();
+        world.register::<Transform>();
+        world.insert(WindowSize {
+            size: Vec2::new(800, 600),
+        });
+        world.insert(PhysicsConfig::default());
+        let target = world.create_entity().with(Position(target)).build();
+        let mut camera = Camera2D::for_canvas(Vec2::new(800, 600));
+        camera.bounds = bounds;
+        camera.follow = Some(Follow {
+            target,
+            deadzone: Vec2::new(100., 100.),
+            smoothing: 1.,
+        });
+        let camera = world.create_entity().with(camera).build();
+        (world, camera, target)
+    }
+
+    fn position(world: &World, camera: Entity) -> Vec2<f32> {
+        world
+            .read_storage::<Camera2D>()
+            .get(camera)
+            .unwrap()
+            .position
+    }
+
+    #[test]
+    fn camera_follows_its_target_inside_its_bounds() {
+        let bounds = Bounds {
+            min: Vec2::zero(),
+            max: Vec2::new(3000., 600.),
+        };
+        let (world, camera, target) = setup(Some(bounds), Vec2::new(1500., 300.));
+        let mut system = CameraSystem::new();
+        system.run_now(&world);
+        assert_eq!(position(&world, camera), Vec2::new(1400., 300.));
+
+        world
+            .write_storage::<Position>()
+            .insert(target, Position::new(2900., 300.))
+            .unwrap();
+        system.run_now(&world);
+        assert_eq!(position(&world, camera), Vec2::new(2600., 300.));
+    }
+
+    #[test]
+    fn cameras_without_bounds_follow_anywhere() {
+        let (world, camera, _) = setup(None, Vec2::new(900., -200.));
+        CameraSystem::new().run_now(&world);
+        assert_eq!(position(&world, camera), Vec2::new(800., -100.));
+
+        // Minimaps still fit the playfield.
+        let (world, camera, _) = setup(None, Vec2::new(900., -200.));
+        world
+            .write_storage::<Camera2D>()
+            .get_mut(camera)
+            .unwrap()
+            .fit_bounds = true;
+        CameraSystem::new().run_now(&world);
+        assert_eq!(position(&world, camera), Vec2::new(400., 300.));
+    }
+}
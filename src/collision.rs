@@ -0,0 +1,188 @@
+use crate::physics::{Bounds, PhysicsConfig};
+use crate::render_system::Transform;
+use serde::{Deserialize, Serialize};
+use specs::{Component, DenseVecStorage};
+use vek::Vec2;
+
+/// Collision shape centered on the entity's `Position`.
+///
+/// Entities without one collide with the bounds of their `Transform`, which
+/// follows the sprite's size and rotation. Prefabs can pick one, e.g.
+/// `"collider": { "circle": { "radius": 12 } }`.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[storage(DenseVecStorage)]
+#[serde(rename_all = "snake_case")]
+pub enum Collider {
+    Aabb { half_extents: Vec2<f32> },
+    Circle { radius: f32 },
+}
+
+impl Collider {
+    pub fn from_transform(transform: &Transform) -> Self {
+        Collider::Aabb {
+            half_extents: transform.extents(),
+        }
+    }
+
+    /// Half size of the axis aligned box around the shape.
+    pub fn extents(&self) -> Vec2<f32> {
+        match *self {
+            Collider::Aabb { half_extents } => half_extents,
+            Collider::Circle { radius } => Vec2::broadcast(radius),
+        }
+    }
+}
+
+/// Half size of an entity's collision bounds, zero for entities without a
+/// collider or a transform.
+///
+/// Walls are axis aligned, so a circle touches them exactly where its box
+/// does and `bounce_off_walls` needs nothing more.
+pub fn collision_extents(collider: Option<&Collider>, transform: Option<&Transform>) -> Vec2<f32> {
+    match (collider, transform) {
+        (Some(collider), _) => collider.extents(),
+        (None, Some(transform)) => transform.extents(),
+        (None, None) => Vec2::zero(),
+    }
+}
+
+/// Keeps a body with the given half size inside `bounds`, bouncing it off the
+/// walls it touches. Returns whether it hit the floor.
+///
+/// The velocity is always turned back towards the inside, so bodies left
+/// outside when the bounds shrink are pushed back in instead of jittering.
+pub fn bounce_off_walls(
+    position: &mut Vec2<f32>,
+    velocity: &mut Vec2<f32>,
+    extents: Vec2<f32>,
+    bounds: &Bounds,
+    config: &PhysicsConfig,
+) -> bool {
+    let min = bounds.min + extents;
+    let max = bounds.max - extents;
+
+    if position.x > max.x {
+        velocity.x = -velocity.x.abs() * config.restitution;
+        position.x = max.x;
+    } else if position.x < min.x {
+        velocity.x = velocity.x.abs() * config.restitution;
+        position.x = min.x;
+    }
+
+    if position.y > max.y {
+        velocity.y = velocity.y.min(0.);
+        position.y = max.y;
+    } else if position.y < min.y {
+        velocity.y = velocity.y.abs() * config.restitution;
+        velocity.x *= 1. - config.friction;
+        position.y = min.y;
+        return true;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounds() -> Bounds {
+        Bounds {
+            min: Vec2::zero(),
+            max: Vec2::new(800., 600.),
+        }
+    }
+
+    fn bounce(position: Vec2<f32>, velocity: Vec2<f32>) -> (Vec2<f32>, Vec2<f32>, bool) {
+        let (mut position, mut velocity) = (position, velocity);
+        let extents = Vec2::new(10., 20.);
+        let config = PhysicsConfig::default();
+        let floor = bounce_off_walls(&mut position, &mut velocity, extents, &bounds(), &config);
+        (position, velocity, floor)
+    }
+
+    #[test]
+    fn inside_the_bounds_nothing_happens() {
+        let result = bounce(Vec2::new(400., 300.), Vec2::new(3., -2.));
+        assert_eq!(result, (Vec2::new(400., 300.), Vec2::new(3., -2.), false));
+    }
+
+    #[test]
+    fn sprite_edge_bounces_off_the_left_wall() {
+        let result = bounce(Vec2::new(8., 300.), Vec2::new(-3., -2.));
+        assert_eq!(result, (Vec2::new(10., 300.), Vec2::new(3., -2.), false));
+    }
+
+    #[test]
+    fn sprite_edge_bounces_off_the_right_wall() {
+        let result = bounce(Vec2::new(795., 300.), Vec2::new(3., -2.));
+        assert_eq!(result, (Vec2::new(790., 300.), Vec2::new(-3., -2.), false));
+    }
+
+    #[test]
+    fn sprite_edge_bounces_off_the_floor() {
+        let result = bounce(Vec2::new(400., 15.), Vec2::new(3., -2.));
+        assert_eq!(result, (Vec2::new(400., 20.), Vec2::new(3., 2.), true));
+    }
+
+    #[test]
+    fn sprite_edge_stops_at_the_ceiling() {
+        let result = bounce(Vec2::new(400., 590.), Vec2::new(3., 2.));
+        assert_eq!(result, (Vec2::new(400., 580.), Vec2::new(3., 0.), false));
+    }
+
+    #[test]
+    fn corner_hits_both_walls() {
+        let result = bounce(Vec2::new(5., 5.), Vec2::new(-3., -2.));
+        assert_eq!(result, (Vec2::new(10., 20.), Vec2::new(3., 2.), true));
+    }
+
+    #[test]
+    fn bodies_outside_shrunk_bounds_head_back_inside() {
+        // The window shrank while the body was far outside and already moving
+        // back in. Flipping the velocity would send it away again.
+        let result = bounce(Vec2::new(1000., 300.), Vec2::new(-3., 0.));
+        assert_eq!(result, (Vec2::new(790., 300.), Vec2::new(-3., 0.), false));
+
+        let result = bounce(Vec2::new(400., 900.), Vec2::new(0., -4.));
+        assert_eq!(result, (Vec2::new(400., 580.), Vec2::new(0., -4.), false));
+    }
+
+    #[test]
+    fn circles_touch_the_walls_at_their_radius() {
+        let circle = Collider::Circle { radius: 7. };
+        let extents = collision_extents(Some(&circle), None);
+        let (mut position, mut velocity) = (Vec2::new(795., 3.), Vec2::new(2., -2.));
+        let config = PhysicsConfig::default();
+        assert!(bounce_off_walls(
+            &mut position,
+            &mut velocity,
+            extents,
+            &bounds(),
+            &config
+        ));
+        assert_eq!(position, Vec2::new(793., 7.));
+    }
+
+    #[test]
+    fn rotated_sprites_use_their_rotated_bounds() {
+        let mut transform = Transform::from_size(Vec2::new(40, 10));
+        transform.compose(
+            std::f32::consts::FRAC_PI_2,
+            Vec2::one(),
+            Vec2::broadcast(0.5),
+        );
+        let extents = collision_extents(None, Some(&transform));
+        assert!(
+            (extents - Vec2::new(5., 20.))
+                .map(f32::abs)
+                .reduce_partial_max()
+                < 1e-4
+        );
+
+        let circle = Collider::Circle { radius: 7. };
+        assert_eq!(
+            collision_extents(Some(&circle), Some(&transform)),
+            Vec2::new(7., 7.)
+        );
+    }
+}
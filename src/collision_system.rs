@@ -0,0 +1,445 @@
+use crate::collision::{collision_extents, Collider};
+use crate::components::{Position, Velocity};
+use crate::drag_system::Dragged;
+use crate::physics::PhysicsConfig;
+use crate::player_system::Player;
+use crate::render_system::Transform;
+use crate::spatial::SpatialHash;
+use shrev::EventChannel;
+use specs::prelude::*;
+use vek::Vec2;
+
+/// Published for every pair of overlapping bodies, before they are pushed
+/// apart.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CollisionEvent {
+    pub a: Entity,
+    pub b: Entity,
+    /// Unit vector pointing from `a` towards `b`.
+    pub normal: Vec2<f32>,
+    pub depth: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Contact {
+    normal: Vec2<f32>,
+    depth: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Shape {
+    Aabb(Vec2<f32>),
+    Circle(f32),
+}
+
+impl Shape {
+    fn extents(&self) -> Vec2<f32> {
+        match *self {
+            Shape::Aabb(extents) => extents,
+            Shape::Circle(radius) => Vec2::broadcast(radius),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Body {
+    entity: Entity,
+    position: Vec2<f32>,
+    velocity: Vec2<f32>,
+    shape: Shape,
+    inverse_mass: f32,
+}
+
+/// Pushes moving bodies apart and bounces them off each other. Enabled with
+/// `PhysicsConfig::body_collisions`.
+///
+/// Candidate pairs come from a uniform grid, so the cost grows with the
+/// number of bodies sharing a cell rather than the square of all bodies.
+pub struct CollisionSystem {
+    grid: SpatialHash<usize>,
+    bodies: Vec<Body>,
+    contacts: Vec<(usize, usize, Contact)>,
+    events: Vec<CollisionEvent>,
+}
+
+impl CollisionSystem {
+    pub fn new() -> Self {
+        CollisionSystem {
+            grid: SpatialHash::default(),
+            bodies: Vec::new(),
+            contacts: Vec::new(),
+            events: Vec::new(),
+        }
+    }
+}
+
+impl Default for CollisionSystem {
+    fn default() -> Self {
+        CollisionSystem::new()
+    }
+}
+
+impl<'a> System<'a> for CollisionSystem {
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, Position>,
+        WriteStorage<'a, Velocity>,
+        ReadStorage<'a, Collider>,
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, Dragged>,
+        ReadStorage<'a, Player>,
+        Read<'a, PhysicsConfig>,
+        Write<'a, EventChannel<CollisionEvent>>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            mut positions,
+            mut velocities,
+            colliders,
+            transforms,
+            dragged,
+            players,
+            config,
+            mut collision_events,
+        ): Self::SystemData,
+    ) {
+        if !config.body_collisions {
+            return;
+        }
+
+        let CollisionSystem {
+            grid,
+            bodies,
+            contacts,
+            events,
+        } = self;
+
+        grid.clear();
+        bodies.clear();
+        for (entity, position, velocity, collider, transform, dragged, _) in (
+            &entities,
+            &positions,
+            &velocities,
+            colliders.maybe(),
+            transforms.maybe(),
+            dragged.maybe(),
+            !&players,
+        )
+            .join()
+        {
+            let shape = match collider {
+                Some(Collider::Circle { radius }) => Shape::Circle(*radius),
+                _ => Shape::Aabb(collision_extents(collider, transform)),
+            };
+            let extents = shape.extents();
+            grid.insert(bodies.len(), position.0 - extents, position.0 + extents);
+            bodies.push(Body {
+                entity,
+                position: position.0,
+                velocity: velocity.0,
+                shape,
+                // Dragged bodies follow the pointer and push others around.
+                inverse_mass: if dragged.is_some() { 0. } else { 1. },
+            });
+        }
+
+        contacts.clear();
+        grid.for_each_cell(|cell, items| {
+            for (i, &a) in items.iter().enumerate() {
+                for &b in &items[i + 1..] {
+                    // Bodies spanning several cells meet in each of them, only
+                    // handle the pair in the cell where their overlap starts.
+                    let min_a = bodies[a].position - bodies[a].shape.extents();
+                    let min_b = bodies[b].position - bodies[b].shape.extents();
+                    if grid.cell(Vec2::partial_max(min_a, min_b)) != cell {
+                        continue;
+                    }
+                    if let Some(contact) = collide(&bodies[a], &bodies[b]) {
+                        contacts.push((a, b, contact));
+                    }
+                }
+            }
+        });
+
+        for &(a, b, contact) in contacts.iter() {
+            let (body_a, body_b) = resolve(bodies[a], bodies[b], contact, config.restitution);
+            bodies[a] = body_a;
+            bodies[b] = body_b;
+            events.push(CollisionEvent {
+                a: body_a.entity,
+                b: body_b.entity,
+                normal: contact.normal,
+                depth: contact.depth,
+            });
+        }
+
+        for body in bodies.iter() {
+            positions.get_mut(body.entity).unwrap().0 = body.position;
+            velocities.get_mut(body.entity).unwrap().0 = body.velocity;
+        }
+
+        // Writing to a channel nobody reads from only fills it up.
+        if collision_events.would_write() {
+            collision_events.drain_vec_write(events);
+        } else {
+            events.clear();
+        }
+    }
+}
+
+fn collide(a: &Body, b: &Body) -> Option<Contact> {
+    match (a.shape, b.shape) {
+        (Shape::Circle(ra), Shape::Circle(rb)) => circle_circle(a.position, ra, b.position, rb),
+        (Shape::Aabb(ea), Shape::Aabb(eb)) => aabb_aabb(a.position, ea, b.position, eb),
+        (Shape::Aabb(ea), Shape::Circle(rb)) => aabb_circle(a.position, ea, b.position, rb),
+        (Shape::Circle(ra), Shape::Aabb(eb)) => {
+            aabb_circle(b.position, eb, a.position, ra).map(|contact| Contact {
+                normal: -contact.normal,
+                ..contact
+            })
+        }
+    }
+}
+
+fn circle_circle(a: Vec2<f32>, ra: f32, b: Vec2<f32>, rb: f32) -> Option<Contact> {
+    let offset = b - a;
+    let radii = ra + rb;
+    let distance_squared = offset.magnitude_squared();
+    if distance_squared >= radii * radii {
+        return None;
+    }
+    let distance = distance_squared.sqrt();
+    let normal = if distance > 0. {
+        offset / distance
+    } else {
+        Vec2::unit_x()
+    };
+    Some(Contact {
+        normal,
+        depth: radii - distance,
+    })
+}
+
+fn aabb_aabb(a: Vec2<f32>, ea: Vec2<f32>, b: Vec2<f32>, eb: Vec2<f32>) -> Option<Contact> {
+    let offset = b - a;
+    let overlap = ea + eb - offset.map(f32::abs);
+    if overlap.x <= 0. || overlap.y <= 0. {
+        return None;
+    }
+    Some(min_axis_contact(offset, overlap))
+}
+
+/// Contact from the box at `a` towards the circle at `b`.
+fn aabb_circle(a: Vec2<f32>, ea: Vec2<f32>, b: Vec2<f32>, rb: f32) -> Option<Contact> {
+    let offset = b - a;
+    let closest = Vec2::partial_min(Vec2::partial_max(offset, -ea), ea);
+
+    if closest == offset {
+        // The circle's center is inside the box, push it out the nearest side.
+        let contact = min_axis_contact(offset, ea - offset.map(f32::abs));
+        return Some(Contact {
+            depth: contact.depth + rb,
+            ..contact
+        });
+    }
+
+    let outside = offset - closest;
+    let distance_squared = outside.magnitude_squared();
+    if distance_squared >= rb * rb {
+        return None;
+    }
+    let distance = distance_squared.sqrt();
+    Some(Contact {
+        normal: outside / distance,
+        depth: rb - distance,
+    })
+}
+
+fn min_axis_contact(offset: Vec2<f32>, overlap: Vec2<f32>) -> Contact {
+    let sign = |value: f32| if value < 0. { -1. } else { 1. };
+    if overlap.x < overlap.y {
+        Contact {
+            normal: Vec2::new(sign(offset.x), 0.),
+            depth: overlap.x,
+        }
+    } else {
+        Contact {
+            normal: Vec2::new(0., sign(offset.y)),
+            depth: overlap.y,
+        }
+    }
+}
+
+fn resolve(mut a: Body, mut b: Body, contact: Contact, restitution: f32) -> (Body, Body) {
+    let inverse_mass = a.inverse_mass + b.inverse_mass;
+    if inverse_mass == 0. {
+        return (a, b);
+    }
+
+    let correction = contact.normal * (contact.depth / inverse_mass);
+    a.position -= correction * a.inverse_mass;
+    b.position += correction * b.inverse_mass;
+
+    let approach = (b.velocity - a.velocity).dot(contact.normal);
+    if approach < 0. {
+        let impulse = contact.normal * (-(1. + restitution) * approach / inverse_mass);
+        a.velocity -= impulse * a.inverse_mass;
+        b.velocity += impulse * b.inverse_mass;
+    }
+    (a, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Test {
+        world: World,
+        system: CollisionSystem,
+        reader: ReaderId<CollisionEvent>,
+    }
+
+    impl Test {
+        fn new() -> Self {
+            let mut world = World::new();
+            world.register::<Position>();
+            world.register::<Velocity>();
+            world.register::<Collider>();
+            world.register::<Transform>();
+            world.register::<Dragged>();
+            world.register::<Player>();
+            world.insert(PhysicsConfig {
+                body_collisions: true,
+                ..PhysicsConfig::default()
+            });
+            let mut channel = EventChannel::<CollisionEvent>::new();
+            let reader = channel.register_reader();
+            world.insert(channel);
+            Test {
+                world,
+                system: CollisionSystem::new(),
+                reader,
+            }
+        }
+
+        fn body(&mut self, position: Vec2<f32>, velocity: Vec2<f32>, collider: Collider) -> Entity {
+            self.world
+                .create_entity()
+                .with(Position(position))
+                .with(Velocity(velocity))
+                .with(collider)
+                .build()
+        }
+
+        fn run(&mut self) -> Vec<CollisionEvent> {
+            self.system.run_now(&self.world);
+            let channel = self.world.fetch::<EventChannel<CollisionEvent>>();
+            channel.read(&mut self.reader).cloned().collect()
+        }
+
+        fn velocity(&self, entity: Entity) -> Vec2<f32> {
+            self.world.read_storage::<Velocity>().get(entity).unwrap().0
+        }
+
+        fn position(&self, entity: Entity) -> Vec2<f32> {
+            self.world.read_storage::<Position>().get(entity).unwrap().0
+        }
+    }
+
+    #[test]
+    fn equal_circles_exchange_velocities() {
+        let mut test = Test::new();
+        let circle = Collider::Circle { radius: 10. };
+        let a = test.body(Vec2::new(100., 100.), Vec2::new(2., 0.), circle);
+        let b = test.body(Vec2::new(118., 100.), Vec2::new(-1., 0.), circle);
+
+        let events = test.run();
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].a, a);
+        assert_eq!(events[0].normal, Vec2::new(1., 0.));
+        assert_eq!(events[0].depth, 2.);
+        assert_eq!(test.velocity(a), Vec2::new(-1., 0.));
+        assert_eq!(test.velocity(b), Vec2::new(2., 0.));
+        assert_eq!(test.position(b).x - test.position(a).x, 20.);
+    }
+
+    #[test]
+    fn boxes_separate_along_the_smallest_overlap() {
+        let mut test = Test::new();
+        let square = Collider::Aabb {
+            half_extents: Vec2::new(10., 10.),
+        };
+        let below = test.body(Vec2::new(100., 100.), Vec2::zero(), square);
+        let above = test.body(Vec2::new(105., 116.), Vec2::new(0., -3.), square);
+
+        let events = test.run();
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].normal, Vec2::new(0., 1.));
+        assert_eq!(test.position(above).y - test.position(below).y, 20.);
+        assert_eq!(test.velocity(below), Vec2::new(0., -3.));
+        assert_eq!(test.velocity(above), Vec2::zero());
+    }
+
+    #[test]
+    fn circle_touching_box_corner() {
+        let contact = aabb_circle(Vec2::zero(), Vec2::new(10., 10.), Vec2::new(13., 14.), 6.);
+        let contact = contact.unwrap();
+        assert_eq!(contact.normal, Vec2::new(0.6, 0.8));
+        assert_eq!(contact.depth, 1.);
+
+        assert_eq!(
+            aabb_circle(Vec2::zero(), Vec2::new(10., 10.), Vec2::new(14., 14.), 5.),
+            None
+        );
+    }
+
+    #[test]
+    fn pairs_sharing_many_cells_collide_once() {
+        let mut test = Test::new();
+        let large = Collider::Aabb {
+            half_extents: Vec2::new(100., 100.),
+        };
+        test.body(Vec2::new(0., 0.), Vec2::zero(), large);
+        test.body(Vec2::new(10., 10.), Vec2::zero(), large);
+
+        assert_eq!(test.run().len(), 1);
+    }
+
+    #[test]
+    fn dragged_bodies_are_immovable() {
+        let mut test = Test::new();
+        let circle = Collider::Circle { radius: 10. };
+        let held = test.body(Vec2::new(100., 100.), Vec2::zero(), circle);
+        let other = test.body(Vec2::new(110., 100.), Vec2::zero(), circle);
+        test.world
+            .write_storage::<Dragged>()
+            .insert(
+                held,
+                Dragged {
+                    offset: Vec2::zero(),
+                },
+            )
+            .unwrap();
+
+        test.run();
+
+        assert_eq!(test.position(held), Vec2::new(100., 100.));
+        assert_eq!(test.position(other), Vec2::new(120., 100.));
+    }
+
+    #[test]
+    fn disabled_by_default() {
+        let mut test = Test::new();
+        test.world.insert(PhysicsConfig::default());
+        let circle = Collider::Circle { radius: 10. };
+        test.body(Vec2::new(100., 100.), Vec2::zero(), circle);
+        test.body(Vec2::new(110., 100.), Vec2::zero(), circle);
+
+        assert!(test.run().is_empty());
+    }
+}
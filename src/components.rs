@@ -1,9 +1,10 @@
-use specs::prelude::*;
-use specs::{Component, DenseVecStorage};
+use serde::{Deserialize, Serialize};
+use specs::{Component, DenseVecStorage, FlaggedStorage};
 use vek::Vec2;
 
-#[derive(Component, Clone, Copy, Debug, PartialEq)]
-#[storage(DenseVecStorage)]
+/// Flagged so `RenderSystem` can tell which static sprites moved.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[storage(FlaggedStorage)]
 pub struct Position(pub Vec2<f32>);
 
 impl Position {
@@ -16,7 +17,7 @@ impl Position {
     }
 }
 
-#[derive(Component, Clone, Copy, Debug, PartialEq)]
+#[derive(Component, Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 #[storage(DenseVecStorage)]
 pub struct Velocity(pub Vec2<f32>);
 
@@ -32,3 +33,36 @@ impl Velocity {
         vel
     }
 }
+
+/// Counter clockwise rotation in radians, around the `Pivot`.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[storage(DenseVecStorage)]
+pub struct Rotation(pub f32);
+
+/// Radians added to the `Rotation` every frame.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[storage(DenseVecStorage)]
+pub struct AngularVelocity(pub f32);
+
+/// Multiplies the sprite size, negative values mirror it.
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+#[storage(DenseVecStorage)]
+pub struct Scale(pub Vec2<f32>);
+
+impl Default for Scale {
+    fn default() -> Self {
+        Scale(Vec2::one())
+    }
+}
+
+/// Point of the sprite placed on the `Position`, from `(0, 0)` at its bottom
+/// left corner to `(1, 1)` at the top right one.
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+#[storage(DenseVecStorage)]
+pub struct Pivot(pub Vec2<f32>);
+
+impl Default for Pivot {
+    fn default() -> Self {
+        Pivot(Vec2::broadcast(0.5))
+    }
+}
@@ -0,0 +1,165 @@
+//! Skipping sprites no camera can see before they're written into the vertex
+//! buffer.
+//!
+//! Sprites are tested with the axis aligned box around their quad against
+//! the box around each camera's view, so a sprite is only culled when it's
+//! certainly off screen. Rotated cameras and sprites keep a few more than
+//! strictly needed.
+
+use crate::camera::{Camera2D, RenderLayers};
+use crate::physics::Bounds;
+use serde::Serialize;
+use vek::Vec2;
+
+/// What `RenderSystem` did last frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct RenderStats {
+    /// Sprites in the world.
+    pub sprites: u32,
+    /// Sprites in the static batch, see `sprite_batch`.
+    pub static_sprites: u32,
+    /// Sprites seen by at least one camera, and written into the vertex
+    /// buffer.
+    pub drawn: u32,
+    /// Sprites no camera could see.
+    pub culled: u32,
+    pub draw_calls: u32,
+    /// Quads uploaded to the vertex buffers.
+    pub uploaded: u32,
+}
+
+/// The part of the world a camera draws, and what it draws there.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct View {
+    pub bounds: Bounds,
+    pub layers: RenderLayers,
+}
+
+impl View {
+    pub fn new(camera: &Camera2D, canvas_size: Vec2<i32>) -> Self {
+        View {
+            bounds: camera.view_bounds(canvas_size),
+            layers: camera.layers,
+        }
+    }
+
+    /// Whether the view sees a sprite on `layers` inside the box from `min`
+    /// to `max`.
+    pub fn sees(&self, layers: RenderLayers, min: Vec2<f32>, max: Vec2<f32>) -> bool {
+        self.layers.intersects(layers)
+            && min.x <= self.bounds.max.x
+            && min.y <= self.bounds.max.y
+            && max.x >= self.bounds.min.x
+            && max.y >= self.bounds.min.y
+    }
+}
+
+/// Whether any of `views` sees a sprite on `layers`, with a quad inside the
+/// box from `min` to `max`.
+pub fn is_visible(views: &[View], layers: RenderLayers, min: Vec2<f32>, max: Vec2<f32>) -> bool {
+    views.iter().any(|view| view.sees(layers, min, max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::world_to_screen;
+    use crate::render_system::Transform;
+    use proptest::prelude::*;
+    use std::f32::consts::PI;
+
+    const CANVAS: Vec2<i32> = Vec2 { x: 800, y: 600 };
+
+    fn camera(position: (f32, f32), zoom: f32, rotation: f32) -> Camera2D {
+        let mut camera = Camera2D::new(Vec2::new(position.0, position.1));
+        camera.zoom = zoom;
+        camera.rotation = rotation;
+        camera
+    }
+
+    fn sprite(size: (f32, f32), rotation: f32) -> Transform {
+        let mut transform = Transform::new(Vec2::new(size.0, size.1));
+        transform.compose(rotation, Vec2::one(), Vec2::broadcast(0.5));
+        transform
+    }
+
+    fn on_screen(camera: &Camera2D, point: Vec2<f32>) -> bool {
+        camera
+            .viewport
+            .contains(world_to_screen(camera, point, CANVAS), CANVAS)
+    }
+
+    proptest! {
+        #[test]
+        fn sprites_on_screen_are_kept(
+            eye in (-1000f32..1000., -1000f32..1000.),
+            zoom in 0.1f32..4.,
+            camera_rotation in -PI..PI,
+            position in (-2000f32..2000., -2000f32..2000.),
+            size in (1f32..300., 1f32..300.),
+            rotation in -PI..PI,
+        ) {
+            let camera = camera(eye, zoom, camera_rotation);
+            let transform = sprite(size, rotation);
+            let position = Vec2::new(position.0, position.1);
+            let center = transform.center(position);
+            let extents = transform.extents();
+            let visible = is_visible(
+                &[View::new(&camera, CANVAS)],
+                RenderLayers::DEFAULT,
+                center - extents,
+                center + extents,
+            );
+
+            let mut points = transform.corners(position).to_vec();
+            points.push(center);
+            if points.iter().any(|point| on_screen(&camera, *point)) {
+                prop_assert!(visible);
+            }
+        }
+
+        #[test]
+        fn sprites_past_the_view_are_culled(
+            eye in (-1000f32..1000., -1000f32..1000.),
+            zoom in 0.1f32..4.,
+            camera_rotation in -PI..PI,
+            size in (1f32..300., 1f32..300.),
+            rotation in -PI..PI,
+            direction in -PI..PI,
+            gap in 1f32..1000.,
+        ) {
+            let camera = camera(eye, zoom, camera_rotation);
+            let transform = sprite(size, rotation);
+            let view = View::new(&camera, CANVAS);
+
+            // Far enough that neither box can reach the other.
+            let reach = (view.bounds.max - view.bounds.min).magnitude() / 2.
+                + transform.extents().magnitude();
+            let (sin, cos) = direction.sin_cos();
+            let center = camera.position + Vec2::new(cos, sin) * (reach + gap);
+            let extents = transform.extents();
+            prop_assert!(!is_visible(
+                &[view],
+                RenderLayers::DEFAULT,
+                center - extents,
+                center + extents,
+            ));
+        }
+    }
+
+    #[test]
+    fn views_only_see_their_layers() {
+        let main = camera((0., 0.), 1., 0.);
+        let mut minimap = main;
+        minimap.layers = RenderLayers::DEFAULT;
+        let (min, max) = (Vec2::broadcast(-10.), Vec2::broadcast(10.));
+
+        let views = [View::new(&minimap, CANVAS)];
+        assert!(is_visible(&views, RenderLayers::DEFAULT, min, max));
+        assert!(!is_visible(&views, RenderLayers::DETAIL, min, max));
+
+        let views = [View::new(&minimap, CANVAS), View::new(&main, CANVAS)];
+        assert!(is_visible(&views, RenderLayers::DETAIL, min, max));
+        assert!(!is_visible(&[], RenderLayers::ALL, min, max));
+    }
+}
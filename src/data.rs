@@ -1,14 +1,10 @@
-use js_sys::{Function, JsString, Promise};
-use std::mem::MaybeUninit;
-use std::rc::Rc;
+use js_sys::{JsString, Promise};
 use web_sys::HtmlImageElement;
 
-use wasm_bindgen_futures::future_to_promise;
-
 use std::char::{decode_utf16, REPLACEMENT_CHARACTER};
-use wasm_bindgen::__rt::core::fmt::{Error, Formatter};
+use std::fmt::Formatter;
 use wasm_bindgen::prelude::*;
-use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use wasm_bindgen::{JsCast, JsValue};
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Size<T> {
@@ -36,6 +32,17 @@ pub async fn load_image(url: &str) -> Result<HtmlImageElement, JsError> {
     }
 }
 
+#[wasm_bindgen(module = "/js/load_text.js")]
+extern "C" {
+    fn raw_load_text(url: &str) -> Promise;
+}
+
+pub async fn load_text(url: &str) -> Result<String, JsError> {
+    let promise = raw_load_text(url);
+    let text = wasm_bindgen_futures::JsFuture::from(promise).await?;
+    Ok(text.as_string().unwrap_or_default())
+}
+
 pub struct JsError {
     name: String,
     message: String,
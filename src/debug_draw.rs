@@ -0,0 +1,313 @@
+//! Lines drawn over the sprites for debugging, e.g. velocities and bounds.
+//! Any system can add shapes to `DebugDraw`, `RenderSystem` draws them after
+//! each camera's sprites with `line.frag` and clears them for the next frame.
+
+use crate::camera::RenderLayers;
+use crate::collision::Collider;
+use crate::collision_system::CollisionEvent;
+use crate::components::{Position, Velocity};
+use crate::physics::PhysicsConfig;
+use crate::render_system::{Transform, WindowSize};
+use shrev::EventChannel;
+use specs::prelude::*;
+use std::f32::consts::TAU;
+use vek::{Rgba, Vec2};
+
+/// Circles are drawn with this many lines.
+const CIRCLE_SEGMENTS: usize = 24;
+/// Arrow heads are this long, or a third of the arrow if it's shorter.
+const ARROW_HEAD: f32 = 6.;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineVertex {
+    pub position: Vec2<f32>,
+    pub color: Rgba<u8>,
+}
+
+/// This frame's lines in world coordinates, two vertices each.
+pub struct DebugDraw {
+    /// Cameras seeing any of these draw the lines.
+    pub layers: RenderLayers,
+    vertices: Vec<LineVertex>,
+}
+
+impl Default for DebugDraw {
+    fn default() -> Self {
+        DebugDraw {
+            layers: RenderLayers::DEFAULT,
+            vertices: Vec::new(),
+        }
+    }
+}
+
+impl DebugDraw {
+    pub fn line(&mut self, from: Vec2<f32>, to: Vec2<f32>, color: Rgba<f32>) {
+        let color = color.map(|c| (c.clamp(0., 1.) * 255.).round() as u8);
+        self.vertices.push(LineVertex {
+            position: from,
+            color,
+        });
+        self.vertices.push(LineVertex {
+            position: to,
+            color,
+        });
+    }
+
+    /// A closed outline through `points`.
+    pub fn polygon(&mut self, points: &[Vec2<f32>], color: Rgba<f32>) {
+        for (i, point) in points.iter().enumerate() {
+            self.line(*point, points[(i + 1) % points.len()], color);
+        }
+    }
+
+    pub fn rect(&mut self, min: Vec2<f32>, max: Vec2<f32>, color: Rgba<f32>) {
+        let corners = [min, Vec2::new(max.x, min.y), max, Vec2::new(min.x, max.y)];
+        self.polygon(&corners, color);
+    }
+
+    /// The outline of a quad with corners in the order of
+    /// `Transform::corners`.
+    pub fn quad(&mut self, corners: [Vec2<f32>; 4], color: Rgba<f32>) {
+        self.polygon(&[corners[0], corners[1], corners[3], corners[2]], color);
+    }
+
+    pub fn circle(&mut self, center: Vec2<f32>, radius: f32, color: Rgba<f32>) {
+        let points: Vec<_> = (0..CIRCLE_SEGMENTS)
+            .map(|i| {
+                let angle = i as f32 / CIRCLE_SEGMENTS as f32 * TAU;
+                center + Vec2::new(angle.cos(), angle.sin()) * radius
+            })
+            .collect();
+        self.polygon(&points, color);
+    }
+
+    pub fn arrow(&mut self, from: Vec2<f32>, to: Vec2<f32>, color: Rgba<f32>) {
+        self.line(from, to, color);
+        let length = from.distance(to);
+        if length == 0. {
+            return;
+        }
+        let back = (from - to) / length * ARROW_HEAD.min(length / 3.);
+        let side = Vec2::new(-back.y, back.x) / 2.;
+        self.line(to, to + back + side, color);
+        self.line(to, to + back - side, color);
+    }
+
+    pub fn vertices(&self) -> &[LineVertex] {
+        &self.vertices
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+}
+
+/// What `DebugDrawSystem` shows.
+#[derive(Default, Debug)]
+pub struct DebugDrawOptions {
+    pub velocities: bool,
+    /// Of `Transform` quads and colliders, and the playfield's bounds.
+    pub outlines: bool,
+    /// A line between every pair of bodies that collided.
+    pub contacts: bool,
+}
+
+/// Velocities are in pixels per frame, the arrows show where things will be
+/// this many frames later.
+const VELOCITY_SCALE: f32 = 5.;
+
+/// Draws each `Velocity` as an arrow and each `Transform`'s quad, along with
+/// what they collide with, when `DebugDrawOptions` asks for them.
+pub struct DebugDrawSystem {
+    contacts: ReaderId<CollisionEvent>,
+}
+
+impl DebugDrawSystem {
+    pub fn new(world: &mut World) -> Self {
+        let contacts = world
+            .fetch_mut::<EventChannel<CollisionEvent>>()
+            .register_reader();
+        DebugDrawSystem { contacts }
+    }
+}
+
+impl<'a> System<'a> for DebugDrawSystem {
+    type SystemData = (
+        Read<'a, DebugDrawOptions>,
+        Write<'a, DebugDraw>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Velocity>,
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, Collider>,
+        Read<'a, PhysicsConfig>,
+        Read<'a, WindowSize>,
+        Read<'a, EventChannel<CollisionEvent>>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            options,
+            mut draw,
+            positions,
+            velocities,
+            transforms,
+            colliders,
+            config,
+            window_size,
+            collision_events,
+        ): Self::SystemData,
+    ) {
+        if options.outlines {
+            let color = Rgba::new(0.2, 1., 0.4, 1.);
+            for (position, transform) in (&positions, &transforms).join() {
+                draw.quad(transform.corners(position.vector()), color);
+            }
+            let color = Rgba::new(1., 0.3, 0.8, 1.);
+            for (position, collider) in (&positions, &colliders).join() {
+                match *collider {
+                    Collider::Aabb { half_extents } => {
+                        draw.rect(position.0 - half_extents, position.0 + half_extents, color)
+                    }
+                    Collider::Circle { radius } => draw.circle(position.0, radius, color),
+                }
+            }
+            let bounds = config.bounds(window_size.size);
+            draw.rect(bounds.min, bounds.max, Rgba::new(1., 1., 1., 0.5));
+        }
+        if options.velocities {
+            let color = Rgba::new(1., 0.9, 0.2, 1.);
+            for (position, velocity, transform) in
+                (&positions, &velocities, transforms.maybe()).join()
+            {
+                let from = match transform {
+                    Some(transform) => transform.center(position.vector()),
+                    None => position.vector(),
+                };
+                draw.arrow(from, from + velocity.0 * VELOCITY_SCALE, color);
+            }
+        }
+        // Read every frame so the events don't pile up while hidden.
+        let color = Rgba::new(1., 0.2, 0.2, 1.);
+        for event in collision_events.read(&mut self.contacts) {
+            if !options.contacts {
+                continue;
+            }
+            if let (Some(a), Some(b)) = (positions.get(event.a), positions.get(event.b)) {
+                draw.line(a.0, b.0, color);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(draw: &DebugDraw) -> Vec<(Vec2<f32>, Vec2<f32>)> {
+        draw.vertices()
+            .chunks(2)
+            .map(|line| (line[0].position, line[1].position))
+            .collect()
+    }
+
+    #[test]
+    fn shapes_are_made_of_lines() {
+        let mut draw = DebugDraw::default();
+        draw.rect(Vec2::zero(), Vec2::new(2., 1.), Rgba::white());
+        assert_eq!(
+            lines(&draw),
+            vec![
+                (Vec2::new(0., 0.), Vec2::new(2., 0.)),
+                (Vec2::new(2., 0.), Vec2::new(2., 1.)),
+                (Vec2::new(2., 1.), Vec2::new(0., 1.)),
+                (Vec2::new(0., 1.), Vec2::new(0., 0.)),
+            ]
+        );
+        assert_eq!(draw.vertices()[0].color, Rgba::new(255, 255, 255, 255));
+
+        draw.clear();
+        draw.circle(Vec2::new(5., 5.), 2., Rgba::white());
+        let circle = lines(&draw);
+        assert_eq!(circle.len(), CIRCLE_SEGMENTS);
+        assert_eq!(circle[0].0, Vec2::new(7., 5.));
+        assert_eq!(circle[CIRCLE_SEGMENTS - 1].1, Vec2::new(7., 5.));
+
+        draw.clear();
+        draw.arrow(Vec2::zero(), Vec2::new(30., 0.), Rgba::white());
+        assert_eq!(
+            lines(&draw),
+            vec![
+                (Vec2::new(0., 0.), Vec2::new(30., 0.)),
+                (Vec2::new(30., 0.), Vec2::new(24., -3.)),
+                (Vec2::new(30., 0.), Vec2::new(24., 3.)),
+            ]
+        );
+    }
+
+    #[test]
+    fn options_pick_what_is_drawn() {
+        let mut world = World::new();
+        world.register::<Position>();
+        world.register::<Velocity>();
+        world.register::<Transform>();
+        world.register::<Collider>();
+        world.insert(PhysicsConfig::default());
+        world.insert(WindowSize {
+            size: Vec2::new(800, 600),
+        });
+        world.insert(DebugDraw::default());
+        world.insert(DebugDrawOptions::default());
+        world.insert(EventChannel::<CollisionEvent>::new());
+        let mut system = DebugDrawSystem::new(&mut world);
+        let a = world
+            .create_entity()
+            .with(Position::new(10., 10.))
+            .with(Velocity::new(2., 0.))
+            .with(Transform::new(Vec2::new(4., 4.)))
+            .build();
+        let b = world.create_entity().with(Position::new(10., 20.)).build();
+
+        system.run_now(&world);
+        assert!(world.fetch::<DebugDraw>().vertices().is_empty());
+
+        world.fetch_mut::<DebugDrawOptions>().velocities = true;
+        system.run_now(&world);
+        let arrow = lines(&world.fetch::<DebugDraw>());
+        assert_eq!(arrow.len(), 3);
+        assert_eq!(arrow[0], (Vec2::new(10., 10.), Vec2::new(20., 10.)));
+
+        world.fetch_mut::<DebugDraw>().clear();
+        *world.fetch_mut::<DebugDrawOptions>() = DebugDrawOptions {
+            velocities: false,
+            outlines: true,
+            contacts: false,
+        };
+        system.run_now(&world);
+        let outline = lines(&world.fetch::<DebugDraw>());
+        // The quad and the canvas.
+        assert_eq!(outline.len(), 8);
+        assert!(outline.contains(&(Vec2::new(800., 600.), Vec2::new(0., 600.))));
+        assert!(outline.contains(&(Vec2::new(8., 12.), Vec2::new(12., 12.))));
+
+        world.fetch_mut::<DebugDraw>().clear();
+        *world.fetch_mut::<DebugDrawOptions>() = DebugDrawOptions {
+            contacts: true,
+            ..DebugDrawOptions::default()
+        };
+        world
+            .fetch_mut::<EventChannel<CollisionEvent>>()
+            .single_write(CollisionEvent {
+                a,
+                b,
+                normal: Vec2::unit_y(),
+                depth: 1.,
+            });
+        system.run_now(&world);
+        assert_eq!(
+            lines(&world.fetch::<DebugDraw>()),
+            vec![(Vec2::new(10., 10.), Vec2::new(10., 20.))]
+        );
+    }
+}
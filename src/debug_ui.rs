@@ -0,0 +1,575 @@
+//! A tiny immediate-mode UI for tweaking the game while it runs. Widgets are
+//! function calls made every frame, which return whether they were used:
+//!
+//! ```text
+//! ui.panel("Debug", Vec2::new(8., 8.), 200.);
+//! if ui.button("Step") { ... }
+//! ui.slider("Gravity", &mut gravity, -2.0..=0.);
+//! ui.end_panel();
+//! ```
+//!
+//! `Ui` only lays widgets out and tracks the pointer, in canvas pixels with y
+//! down like `InputEvent`s. What it draws ends up in a list of
+//! `DrawCommand`s, which `UiView` turns into sprites and text on the HUD.
+
+use crate::assets::ImageId;
+use crate::camera::RenderLayers;
+use crate::components::Position;
+use crate::draw_order::{Layer, ZIndex};
+use crate::input_system::{InputEvent, PointerClaimed};
+use crate::render_system::{Sprite, Tint, Transform, WindowSize};
+use crate::text::{Align, FontId, Text, TextStyle};
+use shrev::EventChannel;
+use specs::prelude::*;
+use std::ops::RangeInclusive;
+use vek::{Aabr, Rgba, Vec2};
+
+const PADDING: f32 = 8.;
+const SPACING: f32 = 4.;
+/// Height of a line of text, and of buttons.
+const ROW_HEIGHT: f32 = 18.;
+const TRACK_HEIGHT: f32 = 6.;
+const HANDLE_WIDTH: f32 = 8.;
+const CHECKBOX_SIZE: f32 = 12.;
+/// Of the HUD font, whose lines are 28 pixels.
+const TEXT_SCALE: f32 = 0.6;
+
+const PANEL_COLOR: Rgba<f32> = Rgba::new(0., 0., 0., 0.6);
+const WIDGET_COLOR: Rgba<f32> = Rgba::new(0.3, 0.3, 0.35, 1.);
+const HOVERED_COLOR: Rgba<f32> = Rgba::new(0.4, 0.4, 0.5, 1.);
+const TRACK_COLOR: Rgba<f32> = Rgba::new(0.15, 0.15, 0.2, 1.);
+const ACTIVE_COLOR: Rgba<f32> = Rgba::new(0.5, 0.6, 0.9, 1.);
+const TEXT_COLOR: Rgba<f32> = Rgba::new(1., 1., 1., 1.);
+
+/// What the UI draws, in canvas pixels with y down. Later commands are drawn
+/// on top.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DrawCommand {
+    Rect {
+        rect: Aabr<f32>,
+        color: Rgba<f32>,
+    },
+    /// A line of text hanging from `position`, which is its left, center or
+    /// right end depending on `align`.
+    Text {
+        position: Vec2<f32>,
+        text: String,
+        align: Align,
+        color: Rgba<f32>,
+    },
+}
+
+/// Widgets are told apart by the order they're made in, so the same widget
+/// has to be made at the same point every frame.
+type WidgetId = u32;
+
+#[derive(Default)]
+pub struct Ui {
+    pointer: Vec2<f32>,
+    down: bool,
+    /// Whether the button went down or up since the last frame.
+    pressed: bool,
+    released: bool,
+    /// The widget the button went down on, until it's released.
+    active: Option<WidgetId>,
+    next_id: WidgetId,
+    /// Panels of the frame, where the pointer is the UI's.
+    panels: Vec<Aabr<f32>>,
+    /// The open panel: its rect and the index of its background command.
+    panel: Option<(Aabr<f32>, usize)>,
+    /// Top of the next widget.
+    cursor: f32,
+    commands: Vec<DrawCommand>,
+}
+
+impl Ui {
+    pub fn handle(&mut self, event: &InputEvent) {
+        match *event {
+            InputEvent::MouseDown(point) => {
+                self.pointer = point;
+                self.down = true;
+                self.pressed = true;
+            }
+            InputEvent::MouseUp => {
+                self.down = false;
+                self.released = true;
+            }
+            InputEvent::MouseMove(point) => self.pointer = point,
+            _ => {}
+        }
+    }
+
+    /// The panels made since `begin`, in canvas pixels.
+    pub fn panels(&self) -> &[Aabr<f32>] {
+        &self.panels
+    }
+
+    pub fn begin(&mut self) {
+        self.next_id = 0;
+        self.commands.clear();
+        self.panels.clear();
+    }
+
+    pub fn end(&mut self) {
+        debug_assert!(self.panel.is_none(), "panel left open");
+        self.pressed = false;
+        self.released = false;
+        if !self.down {
+            self.active = None;
+        }
+    }
+
+    pub fn commands(&self) -> &[DrawCommand] {
+        &self.commands
+    }
+
+    /// Starts a panel with its top left corner at `position`. It's as tall
+    /// as the widgets added until `end_panel`.
+    pub fn panel(&mut self, title: &str, position: Vec2<f32>, width: f32) {
+        let rect = Aabr {
+            min: position,
+            max: position + Vec2::new(width, 0.),
+        };
+        self.panel = Some((rect, self.commands.len()));
+        self.commands.push(DrawCommand::Rect {
+            rect,
+            color: PANEL_COLOR,
+        });
+        self.cursor = position.y + PADDING;
+        self.label(title);
+    }
+
+    pub fn end_panel(&mut self) {
+        let (mut rect, background) = self.panel.take().expect("no panel to end");
+        rect.max.y = self.cursor - SPACING + PADDING;
+        self.commands[background] = DrawCommand::Rect {
+            rect,
+            color: PANEL_COLOR,
+        };
+        self.panels.push(rect);
+    }
+
+    pub fn label(&mut self, text: &str) {
+        let row = self.row(ROW_HEIGHT);
+        self.text(row.min, text, Align::Left);
+    }
+
+    /// Returns true when clicked, i.e. released over the button it was
+    /// pressed on.
+    pub fn button(&mut self, text: &str) -> bool {
+        let rect = self.row(ROW_HEIGHT);
+        let id = self.interact(rect);
+        let color = self.widget_color(id, rect);
+        self.commands.push(DrawCommand::Rect { rect, color });
+        self.text(Vec2::new(rect.center().x, rect.min.y), text, Align::Center);
+        self.released && self.active == Some(id) && rect.contains_point(self.pointer)
+    }
+
+    /// Returns true when toggled.
+    pub fn checkbox(&mut self, text: &str, value: &mut bool) -> bool {
+        let row = self.row(ROW_HEIGHT);
+        let id = self.interact(row);
+        let clicked = self.released && self.active == Some(id) && row.contains_point(self.pointer);
+        if clicked {
+            *value = !*value;
+        }
+        let inset = (ROW_HEIGHT - CHECKBOX_SIZE) / 2.;
+        let min = row.min + inset;
+        let box_rect = Aabr {
+            min,
+            max: min + CHECKBOX_SIZE,
+        };
+        let color = self.widget_color(id, row);
+        self.commands.push(DrawCommand::Rect {
+            rect: box_rect,
+            color,
+        });
+        if *value {
+            self.commands.push(DrawCommand::Rect {
+                rect: Aabr {
+                    min: box_rect.min + 3.,
+                    max: box_rect.max - 3.,
+                },
+                color: TEXT_COLOR,
+            });
+        }
+        self.text(
+            Vec2::new(box_rect.max.x + SPACING, row.min.y),
+            text,
+            Align::Left,
+        );
+        clicked
+    }
+
+    /// A label with the value on the right and a track below. Dragging
+    /// anywhere on the track sets the value, and keeps setting it while the
+    /// button is held, even off the track. Returns true when it changed.
+    pub fn slider(&mut self, text: &str, value: &mut f32, range: RangeInclusive<f32>) -> bool {
+        let row = self.row(ROW_HEIGHT);
+        let track = self.row(TRACK_HEIGHT);
+        // A bit taller than the track, so it's easier to hit.
+        let target = Aabr {
+            min: track.min - Vec2::new(0., SPACING),
+            max: track.max + Vec2::new(0., SPACING),
+        };
+        let id = self.interact(target);
+        let (min, max) = (*range.start(), *range.end());
+        let old = *value;
+        if self.down && self.active == Some(id) {
+            let width = track.size().w - HANDLE_WIDTH;
+            let t = (self.pointer.x - track.min.x - HANDLE_WIDTH / 2.) / width;
+            *value = min + t.clamp(0., 1.) * (max - min);
+        }
+
+        self.text(row.min, text, Align::Left);
+        self.text(
+            Vec2::new(row.max.x, row.min.y),
+            &format!("{:.2}", value),
+            Align::Right,
+        );
+        let t = ((*value - min) / (max - min)).clamp(0., 1.);
+        let handle_x = track.min.x + t * (track.size().w - HANDLE_WIDTH);
+        let color = self.widget_color(id, target);
+        self.commands.push(DrawCommand::Rect {
+            rect: track,
+            color: TRACK_COLOR,
+        });
+        self.commands.push(DrawCommand::Rect {
+            rect: Aabr {
+                min: Vec2::new(handle_x, target.min.y),
+                max: Vec2::new(handle_x + HANDLE_WIDTH, target.max.y),
+            },
+            color,
+        });
+        *value != old
+    }
+
+    /// Takes the next row of the open panel, `height` tall and as wide as
+    /// its insides.
+    fn row(&mut self, height: f32) -> Aabr<f32> {
+        let (panel, _) = self.panel.expect("widgets go in a panel");
+        let min = Vec2::new(panel.min.x + PADDING, self.cursor);
+        self.cursor += height + SPACING;
+        Aabr {
+            min,
+            max: Vec2::new(panel.max.x - PADDING, min.y + height),
+        }
+    }
+
+    /// Makes the widget at `rect` the active one if the button went down on
+    /// it.
+    fn interact(&mut self, rect: Aabr<f32>) -> WidgetId {
+        let id = self.next_id;
+        self.next_id += 1;
+        if self.pressed && self.active.is_none() && rect.contains_point(self.pointer) {
+            self.active = Some(id);
+        }
+        id
+    }
+
+    fn widget_color(&self, id: WidgetId, rect: Aabr<f32>) -> Rgba<f32> {
+        if self.active == Some(id) {
+            ACTIVE_COLOR
+        } else if self.active.is_none() && rect.contains_point(self.pointer) {
+            HOVERED_COLOR
+        } else {
+            WIDGET_COLOR
+        }
+    }
+
+    fn text(&mut self, position: Vec2<f32>, text: &str, align: Align) {
+        self.commands.push(DrawCommand::Text {
+            position,
+            text: text.to_string(),
+            align,
+            color: TEXT_COLOR,
+        });
+    }
+}
+
+/// Draws a `Ui` with a pool of entities on `RenderLayers::HUD`, which grows
+/// to the most rects and texts drawn in a frame. Ones that aren't needed are
+/// taken off every layer. Either way, cameras that don't draw the HUD don't
+/// pick them either.
+pub struct UiView {
+    white: ImageId,
+    font: FontId,
+    rects: Vec<Entity>,
+    texts: Vec<Entity>,
+}
+
+impl UiView {
+    pub fn new(white: ImageId, font: FontId) -> Self {
+        UiView {
+            white,
+            font,
+            rects: Vec::new(),
+            texts: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self, world: &mut World, commands: &[DrawCommand]) {
+        let rect_count = commands
+            .iter()
+            .filter(|command| matches!(command, DrawCommand::Rect { .. }))
+            .count();
+        while self.rects.len() < rect_count {
+            let rect = world
+                .create_entity()
+                .with(Sprite::from_image(self.white))
+                .with(Layer::FOREGROUND)
+                .build();
+            self.rects.push(rect);
+        }
+        while self.texts.len() < commands.len() - rect_count {
+            let text = world
+                .create_entity()
+                .with(Text::new("", self.font))
+                .with(Layer::FOREGROUND)
+                .build();
+            self.texts.push(text);
+        }
+
+        let height = world.fetch::<WindowSize>().size.y as f32;
+        let to_world = |point: Vec2<f32>| Vec2::new(point.x, height - point.y);
+        let mut positions = world.write_storage::<Position>();
+        let mut transforms = world.write_storage::<Transform>();
+        let mut tints = world.write_storage::<Tint>();
+        let mut z_indices = world.write_storage::<ZIndex>();
+        let mut texts = world.write_storage::<Text>();
+        let mut render_layers = world.write_storage::<RenderLayers>();
+        let (mut rects, mut labels) = (self.rects.iter(), self.texts.iter());
+        for (z, command) in commands.iter().enumerate() {
+            let (entity, position, color) = match command {
+                DrawCommand::Rect { rect, color } => {
+                    let entity = *rects.next().unwrap();
+                    set(&mut transforms, entity, Transform::new(rect.size().into()));
+                    (entity, rect.center(), *color)
+                }
+                DrawCommand::Text {
+                    position,
+                    text,
+                    align,
+                    color,
+                } => {
+                    let entity = *labels.next().unwrap();
+                    let style = TextStyle {
+                        scale: TEXT_SCALE,
+                        align: *align,
+                        ..TextStyle::default()
+                    };
+                    let text = Text {
+                        text: text.clone(),
+                        font: self.font,
+                        style,
+                    };
+                    set(&mut texts, entity, text);
+                    (entity, *position, *color)
+                }
+            };
+            set(&mut positions, entity, Position(to_world(position)));
+            set(&mut tints, entity, Tint(color));
+            set(&mut z_indices, entity, ZIndex(z as i32));
+            set(&mut render_layers, entity, RenderLayers::HUD);
+        }
+        for entity in rects.chain(labels) {
+            set(&mut render_layers, *entity, RenderLayers(0));
+        }
+    }
+}
+
+/// Only touches components that changed, so unchanged widgets stay out of
+/// the upload.
+fn set<T: Component + PartialEq>(storage: &mut WriteStorage<T>, entity: Entity, value: T) {
+    if storage.get(entity) != Some(&value) {
+        storage.insert(entity, value).unwrap();
+    }
+}
+
+/// The `Ui` the game builds every frame, fed from the `InputEvent` channel.
+pub struct DebugUi {
+    pub ui: Ui,
+    input_reader: ReaderId<InputEvent>,
+    view: UiView,
+}
+
+impl DebugUi {
+    pub fn new(world: &mut World, white: ImageId, font: FontId) -> Self {
+        let input_reader = world
+            .fetch_mut::<EventChannel<InputEvent>>()
+            .register_reader();
+        DebugUi {
+            ui: Ui::default(),
+            input_reader,
+            view: UiView::new(white, font),
+        }
+    }
+
+    /// Reads the frame's input. Between this and `end`, which sets
+    /// `PointerClaimed`, go the widgets, and after it the systems reading
+    /// input.
+    pub fn begin(&mut self, world: &World) -> &mut Ui {
+        for event in world
+            .fetch::<EventChannel<InputEvent>>()
+            .read(&mut self.input_reader)
+        {
+            self.ui.handle(event);
+        }
+        self.ui.begin();
+        &mut self.ui
+    }
+
+    pub fn end(&mut self, world: &mut World) {
+        self.ui.end();
+        world.fetch_mut::<PointerClaimed>().panels = self.ui.panels().to_vec();
+        self.view.update(world, self.ui.commands());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rects(ui: &Ui) -> Vec<Aabr<f32>> {
+        ui.commands()
+            .iter()
+            .filter_map(|command| match command {
+                DrawCommand::Rect { rect, .. } => Some(*rect),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// A panel at (10, 10) with a button under its title.
+    fn button_frame(ui: &mut Ui, events: &[InputEvent]) -> bool {
+        for event in events {
+            ui.handle(event);
+        }
+        ui.begin();
+        ui.panel("Debug", Vec2::new(10., 10.), 100.);
+        let clicked = ui.button("Go");
+        ui.end_panel();
+        ui.end();
+        clicked
+    }
+
+    #[test]
+    fn widgets_stack_down_the_panel() {
+        let mut ui = Ui::default();
+        ui.begin();
+        ui.panel("Debug", Vec2::new(10., 10.), 100.);
+        ui.button("Go");
+        let mut value = 0.5;
+        ui.slider("Speed", &mut value, 0.0..=1.);
+        ui.end_panel();
+        ui.end();
+
+        let rects = rects(&ui);
+        // Title row 18..36, button 40..58, slider label 62..80, track 84..90.
+        assert_eq!(rects[0].min, Vec2::new(10., 10.));
+        assert_eq!(rects[0].max, Vec2::new(110., 98.));
+        assert_eq!(rects[1].min, Vec2::new(18., 40.));
+        assert_eq!(rects[1].max, Vec2::new(102., 58.));
+        assert_eq!(rects[2].min, Vec2::new(18., 84.));
+        // The handle is centered on the track.
+        assert_eq!(rects[3].min, Vec2::new(56., 80.));
+        assert!(ui.commands().contains(&DrawCommand::Text {
+            position: Vec2::new(102., 62.),
+            text: "0.50".to_string(),
+            align: Align::Right,
+            color: TEXT_COLOR,
+        }));
+    }
+
+    #[test]
+    fn buttons_click_when_released_over_them() {
+        let mut ui = Ui::default();
+        let on_button = Vec2::new(50., 50.);
+        button_frame(&mut ui, &[]);
+        assert!(!button_frame(&mut ui, &[InputEvent::MouseDown(on_button)]));
+        assert!(button_frame(&mut ui, &[InputEvent::MouseUp]));
+
+        // Pressed and released within a frame.
+        let click = [InputEvent::MouseDown(on_button), InputEvent::MouseUp];
+        assert!(button_frame(&mut ui, &click));
+
+        // Dragged off before releasing.
+        button_frame(&mut ui, &[InputEvent::MouseDown(on_button)]);
+        let away = [
+            InputEvent::MouseMove(Vec2::new(50., 200.)),
+            InputEvent::MouseUp,
+        ];
+        assert!(!button_frame(&mut ui, &away));
+
+        // Pressed elsewhere and released over it.
+        button_frame(&mut ui, &[InputEvent::MouseDown(Vec2::new(50., 200.))]);
+        let onto = [InputEvent::MouseMove(on_button), InputEvent::MouseUp];
+        assert!(!button_frame(&mut ui, &onto));
+    }
+
+    #[test]
+    fn sliders_follow_the_pointer_while_held() {
+        let mut ui = Ui::default();
+        let mut value = -1.;
+        let mut frame = |ui: &mut Ui, events: &[InputEvent]| {
+            for event in events {
+                ui.handle(event);
+            }
+            ui.begin();
+            ui.panel("Debug", Vec2::new(10., 10.), 100.);
+            let changed = ui.slider("Speed", &mut value, -1.0..=1.);
+            ui.end_panel();
+            ui.end();
+            (changed, value)
+        };
+        // The track is 84 wide at x 18, the handle's center moves over 22..98.
+        assert_eq!(
+            frame(&mut ui, &[InputEvent::MouseDown(Vec2::new(60., 68.))]),
+            (true, 0.)
+        );
+        assert_eq!(
+            frame(&mut ui, &[InputEvent::MouseMove(Vec2::new(79., 0.))]),
+            (true, 0.5)
+        );
+        assert_eq!(
+            frame(&mut ui, &[InputEvent::MouseMove(Vec2::new(500., 0.))]),
+            (true, 1.)
+        );
+        assert_eq!(frame(&mut ui, &[InputEvent::MouseUp]), (false, 1.));
+        assert_eq!(
+            frame(&mut ui, &[InputEvent::MouseMove(Vec2::new(22., 68.))]),
+            (false, 1.)
+        );
+    }
+
+    #[test]
+    fn panels_claim_the_pointer() {
+        let mut world = World::new();
+        world.register::<Position>();
+        world.register::<Transform>();
+        world.register::<Sprite>();
+        world.register::<Tint>();
+        world.register::<Text>();
+        world.register::<Layer>();
+        world.register::<ZIndex>();
+        world.register::<RenderLayers>();
+        world.insert(EventChannel::<InputEvent>::new());
+        world.insert(PointerClaimed::default());
+        world.insert(WindowSize {
+            size: Vec2::new(800, 600),
+        });
+        let mut debug_ui = DebugUi::new(&mut world, ImageId { id: 0 }, FontId::default());
+        let ui = debug_ui.begin(&world);
+        ui.panel("Debug", Vec2::new(10., 10.), 100.);
+        ui.label("Hello");
+        ui.end_panel();
+        debug_ui.end(&mut world);
+
+        let claimed = world.fetch::<PointerClaimed>();
+        // The panel's title isn't a widget, but it's still the UI's.
+        assert!(claimed.claims(Vec2::new(60., 20.)));
+        assert!(claimed.claims(Vec2::new(20., 50.)));
+        assert!(!claimed.claims(Vec2::new(60., 200.)));
+        assert!(!claimed.claims(Vec2::new(300., 20.)));
+    }
+}
@@ -0,0 +1,345 @@
+use crate::camera::{camera_at, screen_to_world, Camera2D, RenderLayers};
+use crate::components::{Position, Velocity};
+use crate::draw_order::{draw_key, Layer, ZIndex};
+use crate::input_system::{InputEvent, PointerClaimed};
+use crate::picking::{pick, SpatialIndex};
+use crate::player_system::Player;
+use crate::render_system::{Sprite, Transform, WindowSize};
+use shrev::EventChannel;
+use specs::prelude::*;
+use specs::{Component, HashMapStorage};
+use vek::Vec2;
+
+/// The entity follows the pointer until the mouse button is released.
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+#[storage(HashMapStorage)]
+pub struct Dragged {
+    /// From the pointer to the entity's position when it was grabbed.
+    pub offset: Vec2<f32>,
+}
+
+pub struct DragSystem {
+    input_reader: ReaderId<InputEvent>,
+    pointer: Vec2<f32>,
+    /// Camera the mouse was pressed in, the pointer stays in its view until
+    /// released.
+    camera: Option<Entity>,
+}
+
+impl DragSystem {
+    pub fn new(world: &mut World) -> Self {
+        let input_reader = world
+            .fetch_mut::<EventChannel<InputEvent>>()
+            .register_reader();
+        DragSystem {
+            input_reader,
+            pointer: Vec2::zero(),
+            camera: None,
+        }
+    }
+}
+
+impl<'a> System<'a> for DragSystem {
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, Dragged>,
+        WriteStorage<'a, Position>,
+        WriteStorage<'a, Velocity>,
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, Sprite>,
+        ReadStorage<'a, Layer>,
+        ReadStorage<'a, ZIndex>,
+        ReadStorage<'a, Player>,
+        ReadStorage<'a, Camera2D>,
+        ReadStorage<'a, RenderLayers>,
+        Read<'a, SpatialIndex>,
+        Read<'a, WindowSize>,
+        Read<'a, EventChannel<InputEvent>>,
+        Read<'a, PointerClaimed>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            mut dragged,
+            mut positions,
+            mut velocities,
+            transforms,
+            sprites,
+            layers,
+            z_indices,
+            players,
+            cameras,
+            render_layers,
+            index,
+            window_size,
+            input_events,
+            pointer_claimed,
+        ): Self::SystemData,
+    ) {
+        let canvas_size = window_size.size;
+        let canvas = Camera2D::for_canvas(canvas_size);
+        let to_world = |camera: Option<Entity>, point: Vec2<f32>| {
+            let camera = camera.and_then(|entity| cameras.get(entity));
+            screen_to_world(camera.unwrap_or(&canvas), point, canvas_size)
+        };
+        for event in input_events.read(&mut self.input_reader) {
+            match event {
+                InputEvent::MouseDown(point) if pointer_claimed.claims(*point) => {}
+                InputEvent::MouseDown(point) => {
+                    self.camera = camera_at((&entities, &cameras).join(), *point, canvas_size)
+                        .map(|(entity, _)| entity);
+                    self.pointer = to_world(self.camera, *point);
+                    let camera = self.camera.and_then(|entity| cameras.get(entity));
+                    let picked = pick(
+                        self.pointer,
+                        camera.unwrap_or(&canvas).layers,
+                        &index,
+                        &positions,
+                        &transforms,
+                        &render_layers,
+                        |entity| draw_key(entity, &sprites, &layers, &z_indices),
+                    );
+                    if let Some(entity) = picked {
+                        if velocities.contains(entity) && !players.contains(entity) {
+                            let offset = positions.get(entity).unwrap().0 - self.pointer;
+                            dragged.insert(entity, Dragged { offset }).unwrap();
+                        }
+                    }
+                }
+                InputEvent::MouseMove(point) => {
+                    self.pointer = to_world(self.camera, *point);
+                }
+                InputEvent::MouseUp => {
+                    // Released entities keep the velocity of the last frame.
+                    dragged.clear();
+                }
+                _ => {}
+            }
+        }
+
+        for (held, position, velocity) in (&dragged, &mut positions, &mut velocities).join() {
+            let target = self.pointer + held.offset;
+            velocity.0 = target - position.0;
+            position.0 = target;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::ImageId;
+    use crate::debug_ui::{DrawCommand, UiView};
+    use crate::picking::SpatialIndexSystem;
+    use crate::render_system::Tint;
+    use crate::text::{FontId, Text};
+    use vek::{Aabr, Rgba};
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.register::<Dragged>();
+        world.register::<Position>();
+        world.register::<Velocity>();
+        world.register::<Transform>();
+        world.register::<Sprite>();
+        world.register::<Layer>();
+        world.register::<ZIndex>();
+        world.register::<Player>();
+        world.register::<Camera2D>();
+        world.register::<RenderLayers>();
+        world.insert(SpatialIndex::default());
+        world.insert(EventChannel::<InputEvent>::new());
+        world.insert(PointerClaimed::default());
+        world.insert(WindowSize {
+            size: Vec2::new(800, 600),
+        });
+        world
+    }
+
+    fn spawn_bunny(world: &mut World, position: Position) -> Entity {
+        world
+            .create_entity()
+            .with(position)
+            .with(Velocity::new(3., -2.))
+            .with(Transform::from_size(Vec2::new(20, 20)))
+            .with(Sprite::from_image(ImageId { id: 0 }))
+            .build()
+    }
+
+    fn step(world: &World, system: &mut DragSystem, event: InputEvent) {
+        SpatialIndexSystem.run_now(world);
+        world
+            .fetch_mut::<EventChannel<InputEvent>>()
+            .single_write(event);
+        system.run_now(world);
+    }
+
+    #[test]
+    fn dragged_entity_follows_the_pointer_and_is_thrown() {
+        let mut world = world();
+        let mut system = DragSystem::new(&mut world);
+        let bunny = spawn_bunny(&mut world, Position::new(100., 500.));
+
+        let mut step = |event: InputEvent| {
+            step(&world, &mut system, event);
+            (
+                world.read_storage::<Position>().get(bunny).unwrap().0,
+                world.read_storage::<Velocity>().get(bunny).unwrap().0,
+            )
+        };
+
+        // Screen (105, 100) is world (105, 500), 5 pixels right of the center.
+        let (position, _) = step(InputEvent::MouseDown(Vec2::new(105., 100.)));
+        assert_eq!(position, Vec2::new(100., 500.));
+
+        let (position, velocity) = step(InputEvent::MouseMove(Vec2::new(125., 90.)));
+        assert_eq!(position, Vec2::new(120., 510.));
+        assert_eq!(velocity, Vec2::new(20., 10.));
+
+        step(InputEvent::MouseUp);
+        assert!(!world.read_storage::<Dragged>().contains(bunny));
+        assert_eq!(
+            world.read_storage::<Velocity>().get(bunny).unwrap().0,
+            Vec2::new(20., 10.)
+        );
+    }
+
+    #[test]
+    fn claimed_presses_dont_grab() {
+        let mut world = world();
+        let mut system = DragSystem::new(&mut world);
+        let bunny = spawn_bunny(&mut world, Position::new(100., 500.));
+        world.insert(PointerClaimed {
+            panels: vec![Aabr {
+                min: Vec2::new(50., 50.),
+                max: Vec2::new(150., 150.),
+            }],
+        });
+
+        step(
+            &world,
+            &mut system,
+            InputEvent::MouseDown(Vec2::new(105., 100.)),
+        );
+        assert!(!world.read_storage::<Dragged>().contains(bunny));
+        step(&world, &mut system, InputEvent::MouseUp);
+
+        // Each press is told apart, even within a frame.
+        let beside = spawn_bunny(&mut world, Position::new(200., 500.));
+        {
+            let mut input_events = world.fetch_mut::<EventChannel<InputEvent>>();
+            input_events.single_write(InputEvent::MouseDown(Vec2::new(105., 100.)));
+            input_events.single_write(InputEvent::MouseUp);
+        }
+        step(
+            &world,
+            &mut system,
+            InputEvent::MouseDown(Vec2::new(205., 100.)),
+        );
+        assert!(!world.read_storage::<Dragged>().contains(bunny));
+        assert!(world.read_storage::<Dragged>().contains(beside));
+    }
+
+    #[test]
+    fn hud_sprites_dont_block_grabs() {
+        let mut world = world();
+        let mut system = DragSystem::new(&mut world);
+        let bunny = spawn_bunny(&mut world, Position::new(100., 500.));
+        let mut camera = Camera2D::for_canvas(Vec2::new(800, 600));
+        camera.layers = RenderLayers::ALL.without(RenderLayers::HUD);
+        world.create_entity().with(camera).build();
+        world
+            .create_entity()
+            .with(Position::new(100., 500.))
+            .with(Transform::from_size(Vec2::new(50, 50)))
+            .with(Sprite::from_image(ImageId { id: 1 }))
+            .with(Layer::FOREGROUND)
+            .with(RenderLayers::HUD)
+            .build();
+
+        step(
+            &world,
+            &mut system,
+            InputEvent::MouseDown(Vec2::new(105., 100.)),
+        );
+        assert!(world.read_storage::<Dragged>().contains(bunny));
+    }
+
+    #[test]
+    fn debug_ui_panels_dont_block_grabs() {
+        let mut world = world();
+        world.register::<Tint>();
+        world.register::<Text>();
+        let mut system = DragSystem::new(&mut world);
+        let bunny = spawn_bunny(&mut world, Position::new(100., 500.));
+        let mut camera = Camera2D::for_canvas(Vec2::new(800, 600));
+        camera.layers = RenderLayers::ALL.without(RenderLayers::HUD);
+        let camera = world.create_entity().with(camera).build();
+        let mut view = UiView::new(ImageId { id: 1 }, FontId::default());
+        let panel = DrawCommand::Rect {
+            rect: Aabr {
+                min: Vec2::new(50., 50.),
+                max: Vec2::new(150., 150.),
+            },
+            color: Rgba::black(),
+        };
+        view.update(&mut world, &[panel]);
+
+        step(
+            &world,
+            &mut system,
+            InputEvent::MouseDown(Vec2::new(105., 100.)),
+        );
+        assert!(world.read_storage::<Dragged>().contains(bunny));
+        step(&world, &mut system, InputEvent::MouseUp);
+
+        // Pooled sprites without a command are on no layer at all, so they
+        // don't get in the way even of cameras drawing every layer.
+        view.update(&mut world, &[]);
+        world.delete_entity(camera).unwrap();
+        step(
+            &world,
+            &mut system,
+            InputEvent::MouseDown(Vec2::new(105., 100.)),
+        );
+        assert!(world.read_storage::<Dragged>().contains(bunny));
+    }
+
+    #[test]
+    fn pointer_stays_in_the_view_it_was_pressed_in() {
+        let mut world = world();
+        let mut system = DragSystem::new(&mut world);
+        let bunny = spawn_bunny(&mut world, Position::new(400., 300.));
+        world
+            .create_entity()
+            .with(Camera2D::for_canvas(Vec2::new(800, 600)))
+            .build();
+        // The whole canvas at half size in the bottom right quarter.
+        let mut overview = Camera2D::new(Vec2::new(400., 300.));
+        overview.zoom = 0.5;
+        overview.order = 1;
+        overview.viewport.position = Vec2::new(0.5, 0.);
+        overview.viewport.size = Vec2::new(0.5, 0.5);
+        world.create_entity().with(overview).build();
+
+        step(
+            &world,
+            &mut system,
+            InputEvent::MouseDown(Vec2::new(600., 450.)),
+        );
+        assert!(world.read_storage::<Dragged>().contains(bunny));
+
+        // Leaving the small view still moves twice as far in the world.
+        step(
+            &world,
+            &mut system,
+            InputEvent::MouseMove(Vec2::new(350., 450.)),
+        );
+        assert_eq!(
+            world.read_storage::<Position>().get(bunny).unwrap().0,
+            Vec2::new(-100., 300.)
+        );
+    }
+}
@@ -0,0 +1,208 @@
+//! Which sprite is drawn on top of which.
+//!
+//! Sprites are sorted by `Layer`, then `ZIndex`, then image and finally
+//! entity id. The entity id makes the order total, so it doesn't change from
+//! frame to frame, and sorting by image last means sprites sharing a layer and
+//! z index are drawn with as few texture switches as possible. Switches are
+//! only needed where the layers really interleave different images.
+
+use crate::render_system::Sprite;
+use specs::storage::GenericReadStorage;
+use specs::{Component, Entity, FlaggedStorage};
+use std::ops::Range;
+
+/// Coarse draw order, higher layers are drawn on top. Entities without one
+/// are on `Layer::DEFAULT`.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[storage(FlaggedStorage)]
+pub struct Layer(pub i32);
+
+impl Layer {
+    pub const BACKGROUND: Layer = Layer(-10);
+    pub const DEFAULT: Layer = Layer(0);
+    pub const FOREGROUND: Layer = Layer(10);
+}
+
+/// Draw order within a layer, higher values are drawn on top.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[storage(FlaggedStorage)]
+pub struct ZIndex(pub i32);
+
+/// Sprites are drawn in ascending order of their keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DrawKey {
+    pub layer: i32,
+    pub z: i32,
+    pub image: u32,
+    pub entity: u32,
+}
+
+impl DrawKey {
+    pub fn new(layer: Option<&Layer>, z: Option<&ZIndex>, sprite: &Sprite, entity: Entity) -> Self {
+        DrawKey {
+            layer: layer.copied().unwrap_or_default().0,
+            z: z.copied().unwrap_or_default().0,
+            image: sprite.batch_id(),
+            entity: entity.id(),
+        }
+    }
+}
+
+/// Key of a sprite entity, `None` if it has no `Sprite`.
+pub fn draw_key<S, L, Z>(entity: Entity, sprites: &S, layers: &L, z_indices: &Z) -> Option<DrawKey>
+where
+    S: GenericReadStorage<Component = Sprite>,
+    L: GenericReadStorage<Component = Layer>,
+    Z: GenericReadStorage<Component = ZIndex>,
+{
+    let sprite = sprites.get(entity)?;
+    Some(DrawKey::new(
+        layers.get(entity),
+        z_indices.get(entity),
+        sprite,
+        entity,
+    ))
+}
+
+/// Sorts a draw list into drawing order.
+pub fn sort<T>(items: &mut [(DrawKey, T)]) {
+    // Keys are unique apart from the glyphs of a text, which don't overlap,
+    // so there's nothing for a stable sort to preserve.
+    items.sort_unstable_by_key(|(key, _)| *key);
+}
+
+/// Splits a sorted draw list into runs drawn with the same image.
+pub fn texture_runs<T>(items: &[(DrawKey, T)]) -> impl Iterator<Item = (u32, Range<usize>)> + '_ {
+    visible_runs(items, |_| true)
+}
+
+/// Like `texture_runs`, but leaves out the items that aren't `visible`,
+/// e.g. to a camera that doesn't render their layers.
+pub fn visible_runs<'a, T>(
+    items: &'a [(DrawKey, T)],
+    visible: impl Fn(&T) -> bool + 'a,
+) -> impl Iterator<Item = (u32, Range<usize>)> + 'a {
+    merged_runs([items, &[]], visible).map(|(_, image, run)| (image, run))
+}
+
+/// Like `visible_runs`, but for two sorted draw lists drawn from separate
+/// buffers. Runs come in drawing order across both, each with the index of
+/// the list it's from.
+pub fn merged_runs<'a, T>(
+    lists: [&'a [(DrawKey, T)]; 2],
+    visible: impl Fn(&T) -> bool + 'a,
+) -> impl Iterator<Item = (usize, u32, Range<usize>)> + 'a {
+    let mut starts = [0, 0];
+    std::iter::from_fn(move || {
+        for (items, start) in lists.iter().zip(starts.iter_mut()) {
+            *start += items[*start..]
+                .iter()
+                .take_while(|(_, item)| !visible(item))
+                .count();
+        }
+        let next = |list: usize| lists[list].get(starts[list]).map(|(key, _)| *key);
+        // A run ends where the other list has something to draw in between.
+        let (list, limit) = match (next(0), next(1)) {
+            (None, None) => return None,
+            (Some(_), None) => (0, None),
+            (None, Some(_)) => (1, None),
+            (Some(a), Some(b)) if a < b => (0, Some(b)),
+            (Some(a), Some(_)) => (1, Some(a)),
+        };
+        let items = lists[list];
+        let start = starts[list];
+        let image = items[start].0.image;
+        let len = items[start..]
+            .iter()
+            .take_while(|(key, item)| {
+                key.image == image && visible(item) && limit.is_none_or(|limit| *key < limit)
+            })
+            .count();
+        starts[list] += len;
+        Some((list, image, start..start + len))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(layer: i32, z: i32, image: u32, entity: u32) -> (DrawKey, ()) {
+        (
+            DrawKey {
+                layer,
+                z,
+                image,
+                entity,
+            },
+            (),
+        )
+    }
+
+    #[test]
+    fn layers_beat_z_beats_image_beats_entity() {
+        let mut items = vec![
+            key(1, 0, 0, 0),
+            key(0, 5, 0, 1),
+            key(0, 0, 1, 2),
+            key(0, 0, 0, 4),
+            key(0, 0, 0, 3),
+        ];
+        sort(&mut items);
+        let entities: Vec<_> = items.iter().map(|(key, _)| key.entity).collect();
+        assert_eq!(entities, vec![3, 4, 2, 1, 0]);
+    }
+
+    #[test]
+    fn equal_layers_group_images_together() {
+        // Images alternate by entity id, but share a layer.
+        let mut items: Vec<_> = (0..10)
+            .map(|entity| key(0, 0, entity % 2, entity))
+            .collect();
+        sort(&mut items);
+        let runs: Vec<_> = texture_runs(&items).collect();
+        assert_eq!(runs, vec![(0, 0..5), (1, 5..10)]);
+    }
+
+    #[test]
+    fn interleaved_layers_switch_textures_only_when_needed() {
+        let mut items = vec![
+            key(0, 0, 0, 0),
+            key(1, 0, 1, 1),
+            key(2, 0, 0, 2),
+            key(2, 0, 0, 3),
+            key(3, 0, 0, 4),
+        ];
+        sort(&mut items);
+        let runs: Vec<_> = texture_runs(&items).collect();
+        assert_eq!(runs, vec![(0, 0..1), (1, 1..2), (0, 2..5)]);
+        assert_eq!(texture_runs::<()>(&[]).count(), 0);
+    }
+
+    #[test]
+    fn hidden_items_split_runs() {
+        let items: Vec<_> = (0..6)
+            .map(|entity| (key(0, 0, entity / 3, entity).0, entity))
+            .collect();
+        let runs: Vec<_> = visible_runs(&items, |entity| *entity != 1 && *entity < 5).collect();
+        assert_eq!(runs, vec![(0, 0..1), (0, 2..3), (1, 3..5)]);
+        assert_eq!(visible_runs(&items, |_| false).count(), 0);
+    }
+
+    #[test]
+    fn merged_lists_keep_the_draw_order() {
+        let background = vec![key(-1, 0, 0, 0), key(-1, 0, 0, 1), key(1, 0, 0, 2)];
+        let moving = vec![key(0, 0, 0, 3), key(0, 0, 1, 4), key(1, 0, 0, 5)];
+        let runs: Vec<_> = merged_runs([&background, &moving], |_| true).collect();
+        assert_eq!(
+            runs,
+            vec![
+                (0, 0, 0..2),
+                (1, 0, 0..1),
+                (1, 1, 1..2),
+                (0, 0, 2..3),
+                (1, 0, 2..3)
+            ]
+        );
+    }
+}
@@ -0,0 +1,438 @@
+//! AngelCode BMFont glyph atlases, in either the text or the XML `.fnt`
+//! variant. Both list the same tags with the same attributes, so they're read
+//! into tags first and built into a `Font` the same way.
+//!
+//! Signed distance field atlases come from msdf-atlas-gen's JSON instead,
+//! which measures everything in ems from the baseline. They're converted to
+//! the same pixel metrics, so both kinds lay out the same way.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use vek::Vec2;
+
+/// Where a character is in the atlas and how it's placed on a line. Offsets
+/// and sizes are in pixels, y down from the top of the line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Glyph {
+    pub position: Vec2<f32>,
+    pub size: Vec2<f32>,
+    pub offset: Vec2<f32>,
+    pub advance: f32,
+    pub page: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Font {
+    pub line_height: f32,
+    /// Distance from the top of a line to the baseline.
+    pub base: f32,
+    /// Size of the atlas pages in pixels.
+    pub atlas_size: Vec2<f32>,
+    /// Page image files, relative to the `.fnt` file.
+    pub pages: Vec<String>,
+    /// Set for signed distance field atlases: how many atlas pixels the
+    /// field spans across the glyphs' edges.
+    pub distance_range: Option<f32>,
+    glyphs: HashMap<char, Glyph>,
+    kerning: HashMap<(char, char), f32>,
+}
+
+type Attributes = HashMap<String, String>;
+
+impl Font {
+    /// Reads either variant, telling them apart by the XML declaration or
+    /// root element.
+    pub fn parse(text: &str) -> Result<Self> {
+        if text.trim_start().starts_with('<') {
+            Font::from_xml(text)
+        } else {
+            Font::from_fnt(text)
+        }
+    }
+
+    /// Reads the text variant, one tag per line followed by `key=value`
+    /// pairs. Values with spaces are quoted.
+    pub fn from_fnt(text: &str) -> Result<Self> {
+        let tags = text
+            .lines()
+            .enumerate()
+            .filter_map(|(number, line)| {
+                let mut tokens = split_quoted(line).into_iter();
+                let tag = tokens.next()?;
+                let attributes = tokens
+                    .map(|token| match token.split_once('=') {
+                        Some((key, value)) => {
+                            Ok((key.to_string(), value.trim_matches('"').to_string()))
+                        }
+                        None => Err(anyhow!(
+                            "line {}: expected key=value, got {}",
+                            number + 1,
+                            token
+                        )),
+                    })
+                    .collect::<Result<Attributes>>();
+                Some(attributes.map(|attributes| (tag.to_string(), attributes)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Font::from_tags(tags)
+    }
+
+    /// Reads the XML variant, where the tags are elements.
+    pub fn from_xml(text: &str) -> Result<Self> {
+        let document = roxmltree::Document::parse(text)?;
+        let tags = document
+            .descendants()
+            .filter(|node| node.is_element())
+            .map(|node| {
+                let attributes = node
+                    .attributes()
+                    .map(|attribute| (attribute.name().to_string(), attribute.value().to_string()))
+                    .collect();
+                (node.tag_name().name().to_string(), attributes)
+            });
+        Font::from_tags(tags)
+    }
+
+    /// Reads msdf-atlas-gen's JSON for an `sdf`, `psdf`, `msdf` or `mtsdf`
+    /// atlas. It doesn't name the atlas image, so that's `page`.
+    pub fn from_msdf_json(json: &str, page: &str) -> Result<Self> {
+        let json: MsdfFont = serde_json::from_str(json)?;
+        let atlas = &json.atlas;
+        if !["sdf", "psdf", "msdf", "mtsdf"].contains(&atlas.kind.as_str()) {
+            bail!("{} atlases have no distance field", atlas.kind);
+        }
+        let flipped = match atlas.y_origin.as_str() {
+            "bottom" => true,
+            "top" => false,
+            origin => bail!("unknown yOrigin {}", origin),
+        };
+
+        let size = atlas.size;
+        let base = json.metrics.ascender * size;
+        let mut font = Font {
+            line_height: json.metrics.line_height * size,
+            base,
+            atlas_size: Vec2::new(atlas.width, atlas.height),
+            pages: vec![page.to_string()],
+            distance_range: Some(atlas.distance_range),
+            ..Font::default()
+        };
+        for glyph in json.glyphs.iter() {
+            let character = std::char::from_u32(glyph.unicode)
+                .ok_or_else(|| anyhow!("glyph {} isn't a character", glyph.unicode))?;
+            let mut placed = Glyph {
+                position: Vec2::zero(),
+                size: Vec2::zero(),
+                offset: Vec2::zero(),
+                advance: glyph.advance * size,
+                page: 0,
+            };
+            // Whitespace has no bounds.
+            if let (Some(plane), Some(bounds)) = (glyph.plane_bounds, glyph.atlas_bounds) {
+                let (top, plane_top) = if flipped {
+                    (atlas.height - bounds.top, -plane.top)
+                } else {
+                    (bounds.top, plane.top)
+                };
+                placed.position = Vec2::new(bounds.left, top);
+                placed.size = Vec2::new(
+                    bounds.right - bounds.left,
+                    (bounds.top - bounds.bottom).abs(),
+                );
+                placed.offset = Vec2::new(plane.left * size, base + plane_top * size);
+            }
+            font.glyphs.insert(character, placed);
+        }
+        for pair in json.kerning.iter() {
+            let character = |id| {
+                std::char::from_u32(id).ok_or_else(|| anyhow!("kerning {} isn't a character", id))
+            };
+            let pair_chars = (character(pair.unicode1)?, character(pair.unicode2)?);
+            font.kerning.insert(pair_chars, pair.advance * size);
+        }
+        Ok(font)
+    }
+
+    fn from_tags(tags: impl IntoIterator<Item = (String, Attributes)>) -> Result<Self> {
+        let mut font = Font::default();
+        let mut pages = Vec::new();
+        for (tag, attributes) in tags {
+            let number = |key: &str| -> Result<f32> {
+                let value = attributes
+                    .get(key)
+                    .ok_or_else(|| anyhow!("{} is missing {}", tag, key))?;
+                value
+                    .parse()
+                    .with_context(|| format!("{} has a bad {}: {}", tag, key, value))
+            };
+            match tag.as_str() {
+                "common" => {
+                    font.line_height = number("lineHeight")?;
+                    font.base = number("base")?;
+                    font.atlas_size = Vec2::new(number("scaleW")?, number("scaleH")?);
+                }
+                "page" => {
+                    let file = attributes
+                        .get("file")
+                        .ok_or_else(|| anyhow!("page is missing file"))?;
+                    pages.push((number("id")? as usize, file.clone()));
+                }
+                "char" => {
+                    let id = number("id")? as u32;
+                    let character = std::char::from_u32(id)
+                        .ok_or_else(|| anyhow!("char {} isn't a character", id))?;
+                    let glyph = Glyph {
+                        position: Vec2::new(number("x")?, number("y")?),
+                        size: Vec2::new(number("width")?, number("height")?),
+                        offset: Vec2::new(number("xoffset")?, number("yoffset")?),
+                        advance: number("xadvance")?,
+                        page: number("page")? as u32,
+                    };
+                    font.glyphs.insert(character, glyph);
+                }
+                "kerning" => {
+                    let character = |key| {
+                        let id = number(key)? as u32;
+                        std::char::from_u32(id)
+                            .ok_or_else(|| anyhow!("kerning {} isn't a character", id))
+                    };
+                    let pair = (character("first")?, character("second")?);
+                    font.kerning.insert(pair, number("amount")?);
+                }
+                _ => {}
+            }
+        }
+
+        if font.atlas_size.x <= 0. || font.atlas_size.y <= 0. {
+            bail!("font has no common tag with the atlas size");
+        }
+        pages.sort();
+        for (index, (id, file)) in pages.into_iter().enumerate() {
+            if id != index {
+                bail!("font is missing page {}", index);
+            }
+            font.pages.push(file);
+        }
+        if let Some(glyph) = font
+            .glyphs
+            .values()
+            .find(|glyph| glyph.page as usize >= font.pages.len())
+        {
+            bail!("glyph on page {} which doesn't exist", glyph.page);
+        }
+        Ok(font)
+    }
+
+    pub fn glyph(&self, character: char) -> Option<&Glyph> {
+        self.glyphs.get(&character)
+    }
+
+    /// How much closer `second` is placed after `first`, usually negative.
+    pub fn kerning(&self, first: char, second: char) -> f32 {
+        self.kerning
+            .get(&(first, second))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Deserialize)]
+struct MsdfFont {
+    atlas: MsdfAtlas,
+    metrics: MsdfMetrics,
+    glyphs: Vec<MsdfGlyph>,
+    #[serde(default)]
+    kerning: Vec<MsdfKerning>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MsdfAtlas {
+    #[serde(rename = "type")]
+    kind: String,
+    distance_range: f32,
+    size: f32,
+    width: f32,
+    height: f32,
+    y_origin: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MsdfMetrics {
+    line_height: f32,
+    ascender: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MsdfGlyph {
+    unicode: u32,
+    advance: f32,
+    plane_bounds: Option<MsdfBounds>,
+    atlas_bounds: Option<MsdfBounds>,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+struct MsdfBounds {
+    left: f32,
+    bottom: f32,
+    right: f32,
+    top: f32,
+}
+
+#[derive(Deserialize)]
+struct MsdfKerning {
+    unicode1: u32,
+    unicode2: u32,
+    advance: f32,
+}
+
+/// Splits a line at whitespace outside of quotes.
+fn split_quoted(line: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        if c.is_whitespace() && !quoted {
+            if let Some(start) = start.take() {
+                tokens.push(&line[start..i]);
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(start) = start {
+        tokens.push(&line[start..]);
+    }
+    tokens
+}
+
+/// A small font for tests: a space, `A`, `V` and `a`, with `AV` kerned.
+#[cfg(test)]
+pub(crate) const TEST_FONT: &str = r#"info face="Test Font" size=10 bold=0 italic=0
+common lineHeight=10 base=8 scaleW=100 scaleH=50 pages=1 packed=0
+page id=0 file="test_0.png"
+chars count=4
+char id=32 x=0 y=0 width=0 height=0 xoffset=0 yoffset=0 xadvance=4 page=0 chnl=15
+char id=65 x=10 y=0 width=6 height=8 xoffset=1 yoffset=2 xadvance=8 page=0 chnl=15
+char id=86 x=20 y=0 width=6 height=8 xoffset=1 yoffset=2 xadvance=8 page=0 chnl=15
+char id=97 x=30 y=10 width=5 height=5 xoffset=0 yoffset=5 xadvance=5 page=0 chnl=15
+kernings count=1
+kerning first=65 second=86 amount=-2
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_FONT_XML: &str = r#"<?xml version="1.0"?>
+<font>
+  <info face="Test Font" size="10" bold="0" italic="0"/>
+  <common lineHeight="10" base="8" scaleW="100" scaleH="50" pages="1" packed="0"/>
+  <pages>
+    <page id="0" file="test_0.png"/>
+  </pages>
+  <chars count="4">
+    <char id="32" x="0" y="0" width="0" height="0" xoffset="0" yoffset="0" xadvance="4" page="0" chnl="15"/>
+    <char id="65" x="10" y="0" width="6" height="8" xoffset="1" yoffset="2" xadvance="8" page="0" chnl="15"/>
+    <char id="86" x="20" y="0" width="6" height="8" xoffset="1" yoffset="2" xadvance="8" page="0" chnl="15"/>
+    <char id="97" x="30" y="10" width="5" height="5" xoffset="0" yoffset="5" xadvance="5" page="0" chnl="15"/>
+  </chars>
+  <kernings count="1">
+    <kerning first="65" second="86" amount="-2"/>
+  </kernings>
+</font>
+"#;
+
+    #[test]
+    fn text_and_xml_variants_read_the_same() {
+        let font = Font::parse(TEST_FONT).unwrap();
+        assert_eq!(font.line_height, 10.);
+        assert_eq!(font.atlas_size, Vec2::new(100., 50.));
+        assert_eq!(font.pages, vec!["test_0.png".to_string()]);
+        assert_eq!(
+            font.glyph('a'),
+            Some(&Glyph {
+                position: Vec2::new(30., 10.),
+                size: Vec2::new(5., 5.),
+                offset: Vec2::new(0., 5.),
+                advance: 5.,
+                page: 0,
+            })
+        );
+        assert_eq!(font.kerning('A', 'V'), -2.);
+        assert_eq!(font.kerning('V', 'A'), 0.);
+
+        assert_eq!(Font::parse(TEST_FONT_XML).unwrap(), font);
+    }
+
+    #[test]
+    fn bundled_font_parses() {
+        let font = Font::parse(include_str!("../assets/fonts/dejavu-sans.fnt")).unwrap();
+        assert_eq!(font.pages, vec!["dejavu-sans_0.png".to_string()]);
+        assert!(" 09AZaz~".chars().all(|c| font.glyph(c).is_some()));
+    }
+
+    #[test]
+    fn msdf_json_is_measured_in_pixels() {
+        // `A` is 20 pixels tall from the baseline and padded by 2 pixels,
+        // 2 to the right of the atlas' left edge and 4 from its top.
+        let json = |origin: &str, bottom: f32, top: f32, plane_top: f32| {
+            format!(
+                r#"{{
+                    "atlas": {{"type": "msdf", "distanceRange": 4, "size": 40,
+                               "width": 64, "height": 32, "yOrigin": "{}"}},
+                    "metrics": {{"emSize": 1, "lineHeight": 1.25, "ascender": 1}},
+                    "glyphs": [
+                        {{"unicode": 32, "advance": 0.25}},
+                        {{"unicode": 65, "advance": 0.5,
+                          "planeBounds": {{"left": -0.05, "bottom": -0.05,
+                                           "right": 0.55, "top": {}}},
+                          "atlasBounds": {{"left": 2, "bottom": {}, "right": 26, "top": {}}}}}
+                    ],
+                    "kerning": [{{"unicode1": 65, "unicode2": 65, "advance": -0.1}}]
+                }}"#,
+                origin, plane_top, bottom, top
+            )
+        };
+        let font = Font::from_msdf_json(&json("bottom", 4., 28., 0.55), "a.png").unwrap();
+        assert_eq!(font.line_height, 50.);
+        assert_eq!(font.base, 40.);
+        assert_eq!(font.distance_range, Some(4.));
+        assert_eq!(font.pages, vec!["a.png".to_string()]);
+        let glyph = *font.glyph('A').unwrap();
+        assert_eq!(glyph.position, Vec2::new(2., 4.));
+        assert_eq!(glyph.size, Vec2::new(24., 24.));
+        assert_eq!(glyph.offset, Vec2::new(-2., 18.));
+        assert_eq!(glyph.advance, 20.);
+        assert_eq!(font.glyph(' ').unwrap().size, Vec2::zero());
+        assert_eq!(font.kerning('A', 'A'), -4.);
+
+        let top = Font::from_msdf_json(&json("top", 28., 4., -0.55), "a.png").unwrap();
+        assert_eq!(top, font);
+
+        let bitmap = json("bottom", 4., 28., 0.55).replace("\"msdf\"", "\"hardmask\"");
+        assert!(Font::from_msdf_json(&bitmap, "a.png").is_err());
+    }
+
+    #[test]
+    fn bundled_distance_field_font_parses() {
+        let json = include_str!("../assets/fonts/dejavu-sans-sdf.json");
+        let font = Font::from_msdf_json(json, "dejavu-sans-sdf.png").unwrap();
+        assert_eq!(font.distance_range, Some(8.));
+        assert!(" 09AZaz~".chars().all(|c| font.glyph(c).is_some()));
+    }
+
+    #[test]
+    fn broken_fonts_are_errors() {
+        assert!(Font::from_fnt("common lineHeight=10 base=8 scaleW=100").is_err());
+        let missing_page = TEST_FONT.replace("page id=0", "page id=1");
+        assert!(Font::from_fnt(&missing_page).is_err());
+        assert!(Font::from_xml("<font><common").is_err());
+    }
+}
@@ -0,0 +1,382 @@
+//! How long frames take and what's in them, over the last couple of seconds,
+//! and an overlay graphing it in the corner of the canvas.
+
+use crate::assets::ImageId;
+use crate::camera::RenderLayers;
+use crate::components::Position;
+use crate::draw_order::{Layer, ZIndex};
+use crate::render_system::{Sprite, Tint, Transform};
+use crate::text::{FontId, Text};
+use serde::Serialize;
+use specs::prelude::*;
+use std::collections::VecDeque;
+use vek::{Rgba, Vec2};
+
+/// Frames kept, about two seconds at 60 fps.
+pub const FRAME_WINDOW: usize = 120;
+
+/// Numbers of a single frame. Times are in milliseconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct FrameSample {
+    /// Since the previous frame started.
+    pub frame_ms: f64,
+    /// Running every system but the renderer.
+    pub simulation_ms: f64,
+    pub render_ms: f64,
+    pub draw_calls: u32,
+    pub uploaded_vertices: u32,
+    pub entities: u32,
+}
+
+/// The last `FRAME_WINDOW` frames, oldest first.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameStats {
+    samples: VecDeque<FrameSample>,
+}
+
+impl Default for FrameStats {
+    fn default() -> Self {
+        FrameStats {
+            samples: VecDeque::with_capacity(FRAME_WINDOW),
+        }
+    }
+}
+
+impl FrameStats {
+    pub fn push(&mut self, sample: FrameSample) {
+        if self.samples.len() == FRAME_WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    pub fn summary(&self) -> FrameSummary {
+        let metric = |value: fn(&FrameSample) -> f64| Metric::new(self.samples.iter().map(value));
+        let frame_ms = metric(|sample| sample.frame_ms);
+        FrameSummary {
+            frames: self.samples.len(),
+            fps: if frame_ms.average > 0. {
+                1000. / frame_ms.average
+            } else {
+                0.
+            },
+            frame_ms,
+            simulation_ms: metric(|sample| sample.simulation_ms),
+            render_ms: metric(|sample| sample.render_ms),
+            draw_calls: metric(|sample| sample.draw_calls.into()),
+            uploaded_vertices: metric(|sample| sample.uploaded_vertices.into()),
+            entities: metric(|sample| sample.entities.into()),
+        }
+    }
+}
+
+/// `FrameStats` boiled down, as handed to JS.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct FrameSummary {
+    pub frames: usize,
+    /// From the average frame time.
+    pub fps: f64,
+    pub frame_ms: Metric,
+    pub simulation_ms: Metric,
+    pub render_ms: Metric,
+    pub draw_calls: Metric,
+    pub uploaded_vertices: Metric,
+    pub entities: Metric,
+}
+
+/// One number over the window. Percentiles are nearest rank, so they're
+/// always one of the samples.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Metric {
+    pub last: f64,
+    pub average: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Metric {
+    fn new(values: impl Iterator<Item = f64>) -> Self {
+        let mut values: Vec<f64> = values.collect();
+        let last = match values.last() {
+            Some(last) => *last,
+            None => return Metric::default(),
+        };
+        let average = values.iter().sum::<f64>() / values.len() as f64;
+        values.sort_unstable_by(|a, b| a.total_cmp(b));
+        let percentile = |p: f64| {
+            let rank = (p / 100. * values.len() as f64).ceil() as usize;
+            values[rank.clamp(1, values.len()) - 1]
+        };
+        Metric {
+            last,
+            average,
+            p50: percentile(50.),
+            p95: percentile(95.),
+            p99: percentile(99.),
+            max: values[values.len() - 1],
+        }
+    }
+}
+
+/// Canvas pixels per millisecond of frame time on the graph.
+const GRAPH_SCALE: f32 = 2.;
+const BAR_WIDTH: f32 = 2.;
+/// Frame times are clamped to this on the graph, a frame at 30 fps.
+const GRAPH_MAX_MS: f32 = 1000. / 30.;
+
+/// The overlay's entities, they're created by `spawn_overlay`.
+pub struct FrameStatsOverlay {
+    pub visible: bool,
+    bars: Vec<Entity>,
+    parts: Vec<Entity>,
+    label: Entity,
+    origin: Vec2<f32>,
+}
+
+/// Spawns the overlay on `RenderLayers::HUD` with the graph's bottom left
+/// corner at `origin`. `white` is a plain white image for the bars.
+pub fn spawn_overlay(
+    world: &mut World,
+    origin: Vec2<f32>,
+    white: ImageId,
+    font: FontId,
+) -> FrameStatsOverlay {
+    let size = Vec2::new(FRAME_WINDOW as f32 * BAR_WIDTH, GRAPH_MAX_MS * GRAPH_SCALE);
+    let background = world
+        .create_entity()
+        .with(Position(origin + size / 2.))
+        .with(Transform::new(size))
+        .with(Sprite::from_image(white))
+        .with(Tint(Rgba::new(0., 0., 0., 0.5)))
+        .with(Layer::FOREGROUND)
+        .with(RenderLayers::HUD)
+        .build();
+    // A line at 60 fps.
+    let target = world
+        .create_entity()
+        .with(Position(
+            origin + Vec2::new(size.x / 2., 1000. / 60. * GRAPH_SCALE),
+        ))
+        .with(Transform::new(Vec2::new(size.x, 1.)))
+        .with(Sprite::from_image(white))
+        .with(Tint(Rgba::new(1., 1., 1., 0.4)))
+        .with(Layer::FOREGROUND)
+        .with(ZIndex(2))
+        .with(RenderLayers::HUD)
+        .build();
+    let bars = (0..FRAME_WINDOW)
+        .map(|_| {
+            world
+                .create_entity()
+                .with(Position(origin))
+                .with(Transform::new(Vec2::new(BAR_WIDTH, 0.)))
+                .with(Sprite::from_image(white))
+                .with(Tint::default())
+                .with(Layer::FOREGROUND)
+                .with(ZIndex(1))
+                .with(RenderLayers::HUD)
+                .build()
+        })
+        .collect();
+    let label = world
+        .create_entity()
+        .with(Position(origin + Vec2::new(size.x + 8., size.y)))
+        .with(Text::new("", font))
+        .with(Layer::FOREGROUND)
+        .with(RenderLayers::HUD)
+        .build();
+    FrameStatsOverlay {
+        visible: true,
+        bars,
+        parts: vec![background, target],
+        label,
+        origin,
+    }
+}
+
+fn bar_color(frame_ms: f64) -> Rgba<f32> {
+    if frame_ms <= 1000. / 60. + 1. {
+        Rgba::new(0.3, 0.9, 0.3, 1.)
+    } else if frame_ms <= 1000. / 30. + 1. {
+        Rgba::new(1., 0.8, 0.2, 1.)
+    } else {
+        Rgba::new(1., 0.3, 0.2, 1.)
+    }
+}
+
+/// Updates the overlay from `FrameStats`, newest frame on the right. It runs
+/// before the frame is over, so it shows the frames up to the previous one.
+pub struct FrameStatsOverlaySystem;
+
+impl<'a> System<'a> for FrameStatsOverlaySystem {
+    type SystemData = (
+        Option<Read<'a, FrameStatsOverlay>>,
+        Read<'a, FrameStats>,
+        WriteStorage<'a, Position>,
+        WriteStorage<'a, Transform>,
+        WriteStorage<'a, Tint>,
+        WriteStorage<'a, Text>,
+        WriteStorage<'a, RenderLayers>,
+    );
+
+    fn run(
+        &mut self,
+        (overlay, stats, mut positions, mut transforms, mut tints, mut texts, mut render_layers): Self::SystemData,
+    ) {
+        let overlay = match overlay {
+            Some(overlay) => overlay,
+            None => return,
+        };
+        let layers = if overlay.visible {
+            RenderLayers::HUD
+        } else {
+            RenderLayers(0)
+        };
+        let entities = overlay.bars.iter().chain(overlay.parts.iter());
+        for entity in entities.chain(std::iter::once(&overlay.label)) {
+            if render_layers.get(*entity) != Some(&layers) {
+                render_layers.insert(*entity, layers).unwrap();
+            }
+        }
+        if !overlay.visible {
+            return;
+        }
+
+        // Right aligned, so the bars fill in from the right at startup.
+        let empty = FRAME_WINDOW - stats.samples.len();
+        for (i, bar) in overlay.bars.iter().enumerate() {
+            let frame_ms = match i.checked_sub(empty) {
+                Some(index) => stats.samples[index].frame_ms,
+                None => 0.,
+            };
+            let height = (frame_ms as f32).min(GRAPH_MAX_MS) * GRAPH_SCALE;
+            let size = Vec2::new(BAR_WIDTH, height);
+            let position = overlay.origin + Vec2::new((i as f32 + 0.5) * BAR_WIDTH, height / 2.);
+            // Unchanged bars stay out of the upload.
+            if positions.get(*bar) != Some(&Position(position)) {
+                positions.insert(*bar, Position(position)).unwrap();
+            }
+            if transforms.get(*bar) != Some(&Transform::new(size)) {
+                transforms.insert(*bar, Transform::new(size)).unwrap();
+            }
+            let tint = Tint(bar_color(frame_ms));
+            if tints.get(*bar) != Some(&tint) {
+                tints.insert(*bar, tint).unwrap();
+            }
+        }
+
+        if let Some(label) = texts.get_mut(overlay.label) {
+            let summary = stats.summary();
+            label.text = format!(
+                "{:.0} fps  {:.1} ms (p95 {:.1})\nupdate {:.1} ms  render {:.1} ms\n{} draws  {} vertices\n{} entities",
+                summary.fps,
+                summary.frame_ms.average,
+                summary.frame_ms.p95,
+                summary.simulation_ms.average,
+                summary.render_ms.average,
+                summary.draw_calls.last,
+                summary.uploaded_vertices.last,
+                summary.entities.last,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(frame_ms: f64) -> FrameSample {
+        FrameSample {
+            frame_ms,
+            draw_calls: frame_ms as u32,
+            ..FrameSample::default()
+        }
+    }
+
+    #[test]
+    fn summary_covers_the_window() {
+        let mut stats = FrameStats::default();
+        assert_eq!(stats.summary(), FrameSummary::default());
+
+        // The first 20 frames fall out of the window.
+        for i in 0..FRAME_WINDOW + 20 {
+            stats.push(frame(if i < 20 { 1000. } else { 10. }));
+        }
+        stats.push(frame(40.));
+        let summary = stats.summary();
+        assert_eq!(summary.frames, FRAME_WINDOW);
+        assert_eq!(summary.frame_ms.last, 40.);
+        assert_eq!(summary.frame_ms.max, 40.);
+        assert_eq!(summary.frame_ms.p50, 10.);
+        assert_eq!(summary.frame_ms.p99, 10.);
+        assert_eq!(summary.frame_ms.average, 10.25);
+        assert_eq!(summary.draw_calls.last, 40.);
+    }
+
+    #[test]
+    fn percentiles_are_nearest_rank() {
+        let metric = Metric::new((1..=20).map(f64::from));
+        assert_eq!(metric.p50, 10.);
+        assert_eq!(metric.p95, 19.);
+        assert_eq!(metric.p99, 20.);
+        assert_eq!(metric.average, 10.5);
+        assert_eq!(Metric::new(std::iter::once(3.)).p95, 3.);
+    }
+
+    #[test]
+    fn overlay_graphs_the_newest_frames_on_the_right() {
+        let mut world = World::new();
+        world.register::<Position>();
+        world.register::<Transform>();
+        world.register::<Sprite>();
+        world.register::<Tint>();
+        world.register::<Layer>();
+        world.register::<ZIndex>();
+        world.register::<RenderLayers>();
+        world.register::<Text>();
+        let overlay = spawn_overlay(
+            &mut world,
+            Vec2::new(10., 10.),
+            ImageId { id: 0 },
+            FontId::default(),
+        );
+        let bars = overlay.bars.clone();
+        world.insert(overlay);
+        let mut stats = FrameStats::default();
+        stats.push(frame(16.));
+        stats.push(frame(100.));
+        world.insert(stats);
+
+        FrameStatsOverlaySystem.run_now(&world);
+        let transforms = world.read_storage::<Transform>();
+        let positions = world.read_storage::<Position>();
+        let tints = world.read_storage::<Tint>();
+        let newest = bars[FRAME_WINDOW - 1];
+        // Clamped to the top of the graph.
+        let top = GRAPH_MAX_MS * GRAPH_SCALE;
+        assert_eq!(
+            transforms.get(newest),
+            Some(&Transform::new(Vec2::new(2., top)))
+        );
+        assert_eq!(tints.get(newest).unwrap().0, bar_color(100.));
+        let previous = bars[FRAME_WINDOW - 2];
+        assert_eq!(
+            transforms.get(previous),
+            Some(&Transform::new(Vec2::new(2., 32.)))
+        );
+        assert_eq!(positions.get(previous), Some(&Position::new(247., 26.)));
+        assert_eq!(
+            transforms.get(bars[0]),
+            Some(&Transform::new(Vec2::new(2., 0.)))
+        );
+        let texts = world.read_storage::<Text>();
+        assert!(texts.join().next().unwrap().text.starts_with("17 fps"));
+    }
+}
@@ -1,43 +1,163 @@
-use crate::assets::{ImageId, Images};
-use crate::components::{Position, Velocity};
+use crate::assets::{ImageId, ImageNames, Images};
+use crate::benchmark::{Benchmark, BenchmarkConfig, BenchmarkResult};
+use crate::camera::{Camera2D, Follow, RenderLayers, Viewport};
+use crate::camera_system::CameraSystem;
+use crate::collision::Collider;
+use crate::collision_system::{CollisionEvent, CollisionSystem};
+use crate::components::{AngularVelocity, Pivot, Position, Rotation, Scale, Velocity};
+use crate::culling::RenderStats;
+use crate::data::load_text;
+use crate::debug_draw::{DebugDraw, DebugDrawOptions, DebugDrawSystem};
+use crate::debug_ui::DebugUi;
+use crate::drag_system::{DragSystem, Dragged};
+use crate::draw_order::{Layer, ZIndex};
+use crate::frame_stats::{
+    self, FrameSample, FrameStats, FrameStatsOverlay, FrameStatsOverlaySystem, FrameSummary,
+};
 use crate::gl::Gl;
-use crate::input_system::{BunnyCount, InputEvent, InputSystem};
+use crate::hierarchy::{set_parent, Children, Parent};
+use crate::input_system::{
+    Action, BunnyCount, InputEvent, InputSystem, PointerClaimed, SpawnRate, SpawnRequest,
+};
+use crate::inspector::{EntitySummary, Inspector};
+use crate::level::{Level, OBSTACLE_IMAGES};
 use crate::move_system::MoveSystem;
-use crate::render_system::{DisplayEvent, RenderSystem, Sprite, Transform, WindowSize};
+use crate::obstacle_system::{Grounded, Obstacle, ObstacleSystem};
+use crate::physics::{Bounds, Paused, PhysicsConfig, PhysicsOverride};
+use crate::picking::{SpatialIndex, SpatialIndexSystem};
+use crate::player_system::{CollectedCount, Player, PlayerControlSystem};
+use crate::prefab::{spawn_prefab, Param, Prefab, Prefabs, Vec2Param, VelocityParam};
+use crate::random::Random;
+use crate::render_system::{DisplayEvent, RenderSystem, Sprite, Tint, Transform, WindowSize};
+use crate::snapshot::{SaveMarker, Saved, Snapshot};
+use crate::sprite_batch::Static;
+use crate::text::{load_font, Align, FontId, Fonts, Outline, Shadow, Text, TextStyle};
+use crate::tilemap::{TileChunk, TileMap};
+use crate::tint_system::{ColorBySpeed, ColorBySpeedSystem};
+use crate::transform_system::{TransformPropagationSystem, TransformSystem};
 use anyhow::Result;
-use rand::Rng;
 use specs::prelude::*;
+use specs::saveload::SimpleMarkerAllocator;
 use specs::shrev::EventChannel;
+use std::f32::consts::PI;
 use std::rc::Rc;
 use std::sync::Mutex;
-use vek::Vec2;
+use vek::{Rgba, Vec2};
 use wasm_bindgen::closure::Closure;
 use wasm_bindgen::JsCast;
-use web_sys::Window;
+use web_sys::{
+    Gamepad, GamepadButton, HtmlCanvasElement, KeyboardEvent, MouseEvent, Performance, Window,
+};
+
+const MAP: &str = "/assets/maps/map.json";
 
 pub struct Game {
     gl: Rc<Gl>,
     images: Rc<Images>,
     dom_event_handlers: DomEvents,
     world: World,
+    spatial_index_system: SpatialIndexSystem,
+    drag_system: DragSystem,
     input_system: InputSystem,
+    player_control_system: PlayerControlSystem,
     move_system: MoveSystem,
+    transform_system: TransformSystem,
+    transform_propagation_system: TransformPropagationSystem,
+    obstacle_system: ObstacleSystem,
+    collision_system: CollisionSystem,
+    color_by_speed_system: ColorBySpeedSystem,
+    camera_system: CameraSystem,
+    frame_stats_overlay_system: FrameStatsOverlaySystem,
+    debug_draw_system: DebugDrawSystem,
     render_system: RenderSystem,
+    debug_ui: DebugUi,
+    /// Stops everything moving on its own, `step` runs one frame anyway.
+    paused: bool,
+    step: bool,
+    hud: Hud,
+    performance: Performance,
+    /// When the previous frame started, from `performance.now()`.
+    last_frame: Option<f64>,
+    benchmark: Option<Benchmark>,
 }
 
 impl Game {
-    pub async fn init(gl: Rc<Gl>, window: &Window, canvas_size: Vec2<i32>) -> Result<Self> {
-        let images = load_images(gl.clone()).await?;
+    pub async fn init(
+        gl: Rc<Gl>,
+        window: &Window,
+        canvas: &HtmlCanvasElement,
+        canvas_size: Vec2<i32>,
+        seed: u64,
+    ) -> Result<Self> {
+        let mut images = load_images(gl.clone()).await?;
+        let font = load_font(&mut images, "/assets/fonts/dejavu-sans.fnt").await?;
+        let label_font = load_font(&mut images, "/assets/fonts/dejavu-sans-sdf.json").await?;
+        let white = images.add_pixels("white", Vec2::new(1, 1), &[255; 4]);
+        let map = TileMap::from_json(&load_text(MAP).await?, MAP)?;
+        for path in map.image_paths() {
+            if images.find_image_id(&path).is_none() {
+                images.load(&path).await?;
+            }
+        }
         let images = Rc::new(images);
-        let bunny_image = images.find_image_id("/assets/images/bunny.png").unwrap();
-        let mut world = init_world(canvas_size, &images);
+        let prefabs = load_prefabs(&images).await?;
+        let mut world = init_world(canvas_size, seed, prefabs)?;
+        world.insert(images.names());
+
+        let physics = PhysicsConfig::from_json(&load_text("/assets/physics.json").await?)?;
+        world.insert(physics);
+
+        let level = Level::from_json(&load_text("/assets/levels/level.json").await?)?;
+        level.spawn(&mut world, &images)?;
+        map.spawn(&mut world, |path| images.find_image_id(path))?;
+        // The map places bunnies of its own.
+        let bunnies = (&world.read_storage::<SaveMarker>()).join().count() as u32;
+        world.fetch_mut::<BunnyCount>().0 = bunnies;
+
+        let player_image = images.find_image_id("/assets/images/player.png").unwrap();
+        let player_transform = Transform::from_size(images.size(player_image));
+        let player = spawn_player(&mut world, player_image, player_transform);
+
+        let hat_image = images.find_image_id("/assets/images/hat.png").unwrap();
+        let hat_transform = Transform::from_size(images.size(hat_image));
+        let hat_position = Position::new(0., player_transform.extents().y - 4.);
+        spawn_hat(&mut world, player, hat_position, hat_image, hat_transform)?;
+        spawn_camera(&mut world, player, canvas_size);
+        spawn_minimap(&mut world);
+        let font = world.fetch_mut::<Fonts>().add(font);
+        let hud = Hud::spawn(&mut world, canvas_size, font);
+        let overlay = frame_stats::spawn_overlay(&mut world, Vec2::new(8., 8.), white, font);
+        world.insert(overlay);
+        let label_font = world.fetch_mut::<Fonts>().add(label_font);
+        spawn_title(&mut world, canvas_size, label_font);
+
         Ok(Game {
             gl: gl.clone(),
             images: Rc::new(Images::new(gl.clone())),
-            dom_event_handlers: DomEvents::register(window),
-            input_system: InputSystem::new(images.clone(), &mut world, bunny_image),
-            render_system: RenderSystem::new(gl.clone(), &images, &world, canvas_size),
+            dom_event_handlers: DomEvents::register(window, canvas),
+            spatial_index_system: SpatialIndexSystem,
+            drag_system: DragSystem::new(&mut world),
+            input_system: InputSystem::new(&mut world),
+            player_control_system: PlayerControlSystem::new(&mut world),
+            render_system: RenderSystem::new(gl.clone(), &images, &mut world),
             move_system: MoveSystem,
+            transform_system: TransformSystem,
+            transform_propagation_system: TransformPropagationSystem::new(),
+            obstacle_system: ObstacleSystem::new(),
+            collision_system: CollisionSystem::new(),
+            color_by_speed_system: ColorBySpeedSystem,
+            camera_system: CameraSystem::new(),
+            frame_stats_overlay_system: FrameStatsOverlaySystem,
+            debug_draw_system: DebugDrawSystem::new(&mut world),
+            debug_ui: DebugUi::new(&mut world, white, font),
+            paused: false,
+            step: false,
+            hud,
+            performance: window
+                .performance()
+                .expect("no `performance` in this window"),
+            last_frame: None,
+            benchmark: None,
             world,
         })
     }
@@ -51,74 +171,449 @@ impl Game {
         self.world.fetch::<BunnyCount>().0
     }
 
-    pub fn run_world(&mut self, canvas_size: Vec2<i32>) {
+    pub fn collected_count(&self) -> u32 {
+        self.world.fetch::<CollectedCount>().0
+    }
+
+    pub fn render_stats(&self) -> RenderStats {
+        *self.world.fetch::<RenderStats>()
+    }
+
+    pub fn frame_stats(&self) -> FrameSummary {
+        self.world.fetch::<FrameStats>().summary()
+    }
+
+    /// Starts measuring from scratch, e.g. once a benchmark has warmed up.
+    pub fn reset_frame_stats(&mut self) {
+        self.world.fetch_mut::<FrameStats>().clear();
+    }
+
+    pub fn show_frame_stats(&mut self, visible: bool) {
+        self.world.fetch_mut::<FrameStatsOverlay>().visible = visible;
+    }
+
+    /// Starts a bunnymark from the current bunnies, replacing one that's
+    /// already running.
+    pub fn start_benchmark(&mut self, config: BenchmarkConfig) {
+        self.benchmark = Some(Benchmark::new(config));
+    }
+
+    pub fn benchmark_result(&self) -> Option<&BenchmarkResult> {
+        self.benchmark.as_ref()?.result()
+    }
+
+    pub fn entities(&self) -> Vec<EntitySummary> {
+        self.world.fetch::<Inspector>().entities(&self.world)
+    }
+
+    pub fn entity(&self, id: u32) -> Result<serde_json::Map<String, serde_json::Value>> {
+        self.world.fetch::<Inspector>().entity(&self.world, id)
+    }
+
+    pub fn set_component(&mut self, id: u32, component: &str, json: &str) -> Result<()> {
+        self.world
+            .fetch::<Inspector>()
+            .set(&self.world, id, component, json)
+    }
+
+    pub fn spawn_prefab(&mut self, name: &str, overrides: &Prefab) -> Result<Entity> {
+        spawn_prefab(&mut self.world, name, overrides)
+    }
+
+    pub fn save_snapshot(&self) -> Result<Snapshot> {
+        Snapshot::save(&self.world)
+    }
+
+    /// Replaces the bunnies with the snapshot's.
+    pub fn load_snapshot(&mut self, snapshot: Snapshot) -> Result<()> {
+        snapshot.restore(&mut self.world)
+    }
+
+    pub fn physics_config(&self) -> PhysicsConfig {
+        *self.world.fetch::<PhysicsConfig>()
+    }
+
+    pub fn update_physics_config(&mut self, json: &str) -> Result<()> {
+        self.world
+            .fetch_mut::<PhysicsConfig>()
+            .update_from_json(json)
+    }
+
+    /// Shakes the cameras, `trauma` from 0 to 1. Overviews like the minimap
+    /// and the HUD stay still.
+    pub fn shake_camera(&mut self, trauma: f32) {
+        for camera in (&mut self.world.write_storage::<Camera2D>()).join() {
+            if !camera.fit_bounds {
+                camera.add_trauma(trauma);
+            }
+        }
+    }
+
+    fn update_counter(&mut self) {
+        let text = format!(
+            "Bunnies: {} Collected: {}",
+            self.bunny_count(),
+            self.collected_count()
+        );
+        if let Some(counter) = self.world.write_storage::<Text>().get_mut(self.hud.counter) {
+            if counter.text != text {
+                counter.text = text;
+            }
+        }
+    }
+
+    /// The panel in the top right corner. It goes first, so presses on it
+    /// don't reach the other systems.
+    fn update_debug_ui(&mut self) {
+        let canvas_size = self.world.fetch::<WindowSize>().size;
+        let ui = self.debug_ui.begin(&self.world);
+        ui.panel("Debug", Vec2::new(canvas_size.x as f32 - 188., 8.), 180.);
+        let mut spawn_rate = self.world.fetch::<SpawnRate>().0 as f32;
+        if ui.slider("Spawn rate", &mut spawn_rate, 0.0..=200.) {
+            self.world.fetch_mut::<SpawnRate>().0 = spawn_rate.round() as u32;
+        }
+        let mut gravity = self.world.fetch::<PhysicsConfig>().gravity.y;
+        if ui.slider("Gravity", &mut gravity, -2.0..=0.) {
+            self.world.fetch_mut::<PhysicsConfig>().gravity.y = gravity;
+        }
+        ui.checkbox("Paused", &mut self.paused);
+        if self.paused && ui.button("Step") {
+            self.step = true;
+        }
         {
+            let mut options = self.world.fetch_mut::<DebugDrawOptions>();
+            ui.checkbox("Velocities", &mut options.velocities);
+            ui.checkbox("Outlines", &mut options.outlines);
+            ui.checkbox("Contacts", &mut options.contacts);
+        }
+        ui.end_panel();
+        self.debug_ui.end(&mut self.world);
+    }
+
+    pub fn run_world(&mut self, canvas_size: Vec2<i32>) {
+        let start = self.performance.now();
+        let resized = {
             let mut window_size = self.world.fetch_mut::<WindowSize>();
-            window_size.size = canvas_size;
+            std::mem::replace(&mut window_size.size, canvas_size) != canvas_size
+        };
+        if resized {
+            self.hud.layout(&self.world, canvas_size);
         }
+        self.update_debug_ui();
+        let simulate = !self.paused || std::mem::take(&mut self.step);
+        self.world.insert(Paused(!simulate));
 
-        self.input_system.run_now(&mut self.world);
-        self.move_system.run_now(&mut self.world);
-        self.render_system.run_now(&mut self.world);
+        self.spatial_index_system.run_now(&self.world);
+        self.drag_system.run_now(&self.world);
+        self.input_system.run_now(&self.world);
+        self.player_control_system.run_now(&self.world);
+        if simulate {
+            self.move_system.run_now(&self.world);
+        }
+        self.transform_system.run_now(&self.world);
+        if simulate {
+            self.obstacle_system.run_now(&self.world);
+            self.collision_system.run_now(&self.world);
+        }
+        // Last, so children follow where their parents ended up this frame.
+        self.transform_propagation_system.run_now(&self.world);
+        self.color_by_speed_system.run_now(&self.world);
+        self.camera_system.run_now(&self.world);
+        self.update_counter();
+        self.frame_stats_overlay_system.run_now(&self.world);
+        self.debug_draw_system.run_now(&self.world);
+        let simulated = self.performance.now();
+        self.render_system.run_now(&self.world);
+        let rendered = self.performance.now();
         self.world.maintain();
+
+        let render_stats = *self.world.fetch::<RenderStats>();
+        let sample = FrameSample {
+            frame_ms: start - self.last_frame.unwrap_or(start - (rendered - start)),
+            simulation_ms: simulated - start,
+            render_ms: rendered - simulated,
+            draw_calls: render_stats.draw_calls,
+            uploaded_vertices: render_stats.uploaded * 4,
+            entities: self.world.entities().join().count() as u32,
+        };
+        self.world.fetch_mut::<FrameStats>().push(sample);
+        self.last_frame = Some(start);
+
+        let bunnies = self.bunny_count();
+        if let Some(benchmark) = &mut self.benchmark {
+            self.world.fetch_mut::<SpawnRequest>().0 += benchmark.update(sample.frame_ms, bunnies);
+        }
     }
 }
 
+/// `assets/prefabs.json`, with all of `images` to pick from.
+async fn load_prefabs(images: &Images) -> Result<Prefabs> {
+    let mut prefabs = Prefabs::from_json(&load_text("/assets/prefabs.json").await?)?;
+    for (id, name) in images.names().0.iter().enumerate() {
+        let image_id = ImageId { id: id as u32 };
+        prefabs.add_image(name, image_id, images.size(image_id));
+    }
+    prefabs.check_images()?;
+    Ok(prefabs)
+}
+
 async fn load_images(gl: Rc<Gl>) -> Result<Images> {
     let mut images = Images::new(gl);
     images.load("/assets/images/bunny.png").await?;
+    images.load("/assets/images/player.png").await?;
+    images.load("/assets/images/hat.png").await?;
+    for path in OBSTACLE_IMAGES.iter() {
+        images.load(path).await?;
+    }
     Ok(images)
 }
 
-fn init_world(canvas_size: Vec2<i32>, images: &Images) -> World {
+pub(crate) fn init_world(canvas_size: Vec2<i32>, seed: u64, prefabs: Prefabs) -> Result<World> {
     let mut world = World::new();
     world.register::<Position>();
     world.register::<Velocity>();
     world.register::<Transform>();
     world.register::<Sprite>();
+    world.register::<Player>();
+    world.register::<Dragged>();
+    world.register::<PhysicsOverride>();
+    world.register::<Collider>();
+    world.register::<Obstacle>();
+    world.register::<Grounded>();
+    world.register::<Rotation>();
+    world.register::<AngularVelocity>();
+    world.register::<Scale>();
+    world.register::<Pivot>();
+    world.register::<Parent>();
+    world.register::<Children>();
+    world.register::<Tint>();
+    world.register::<Layer>();
+    world.register::<ZIndex>();
+    world.register::<ColorBySpeed>();
+    world.register::<Camera2D>();
+    world.register::<RenderLayers>();
+    world.register::<Static>();
+    world.register::<Text>();
+    world.register::<SaveMarker>();
+    world.register::<TileChunk>();
     world.insert(EventChannel::<DisplayEvent>::new());
     world.insert(EventChannel::<InputEvent>::new());
+    world.insert(EventChannel::<CollisionEvent>::new());
     world.insert(WindowSize { size: canvas_size });
     world.insert(BunnyCount(3));
+    world.insert(SpawnRate::default());
+    world.insert(SpawnRequest::default());
+    world.insert(PointerClaimed::default());
+    world.insert(CollectedCount(0));
+    world.insert(SpatialIndex::default());
+    world.insert(RenderStats::default());
+    world.insert(FrameStats::default());
+    world.insert(Fonts::default());
+    world.insert(PhysicsConfig::default());
+    world.insert(Paused::default());
+    world.insert(DebugDraw::default());
+    world.insert(Inspector::default());
+    world.insert(DebugDrawOptions::default());
+    world.insert(SimpleMarkerAllocator::<Saved>::default());
+    world.insert(ImageNames::default());
 
-    let mut rng = rand::thread_rng();
-
-    let bunny_id = images.find_image_id("/assets/images/bunny.png").unwrap();
-    let bunny_image = images.image(bunny_id).unwrap();
+    world.insert(Random::from_seed(seed));
+    world.insert(prefabs);
 
+    // A few bunnies anywhere on the canvas to start with.
+    let overrides = Prefab {
+        position: Some(Vec2Param {
+            x: Param::Range([0., canvas_size.x as f32]),
+            y: Param::Range([0., canvas_size.y as f32]),
+        }),
+        velocity: Some(VelocityParam::Polar {
+            angle: Param::Range([-PI, PI]),
+            speed: Param::Fixed(4.),
+        }),
+        ..Prefab::default()
+    };
     for _ in 0..3 {
-        let x: i32 = rng.gen_range(0, canvas_size.x);
-        let y: i32 = rng.gen_range(0, canvas_size.y);
+        spawn_prefab(&mut world, "bunny", &overrides)?;
+    }
+
+    Ok(world)
+}
 
+fn spawn_player(world: &mut World, image: ImageId, transform: Transform) -> Entity {
+    let x = world.fetch::<WindowSize>().size.x as f32 / 2.;
+    world
+        .create_entity()
+        .with(Player::default())
+        .with(Position::new(x, transform.extents().y))
+        .with(Velocity::new(0., 0.))
+        .with(transform)
+        .with(Collider::from_transform(&transform))
+        .with(PhysicsOverride {
+            restitution: Some(0.),
+            ..PhysicsOverride::default()
+        })
+        .with(Sprite::from_image(image))
+        .with(Layer::FOREGROUND)
+        .build()
+}
+
+/// A camera following `player` around the playfield.
+fn spawn_camera(world: &mut World, player: Entity, canvas_size: Vec2<i32>) -> Entity {
+    let mut camera = Camera2D::for_canvas(canvas_size);
+    camera.follow = Some(Follow {
+        target: player,
+        deadzone: Vec2::new(150., 100.),
+        smoothing: 0.1,
+    });
+    camera.layers = RenderLayers::ALL.without(RenderLayers::HUD);
+    world.create_entity().with(camera).build()
+}
+
+/// Shows the whole playfield in the top right corner, drawn over the main
+/// camera and without the details.
+fn spawn_minimap(world: &mut World) -> Entity {
+    let mut camera = Camera2D::new(Vec2::zero());
+    camera.viewport = Viewport {
+        position: Vec2::new(0.74, 0.74),
+        size: Vec2::new(0.24, 0.24),
+    };
+    camera.order = 1;
+    camera.layers = RenderLayers::DEFAULT;
+    camera.clear_color = Some(Rgba::new(0.1, 0.1, 0.15, 1.));
+    camera.fit_bounds = true;
+    world.create_entity().with(camera).build()
+}
+
+/// A camera drawing the HUD over everything in canvas pixels, and the counter
+/// in its top left corner.
+#[derive(Clone, Copy, Debug)]
+struct Hud {
+    camera: Entity,
+    counter: Entity,
+}
+
+impl Hud {
+    fn spawn(world: &mut World, canvas_size: Vec2<i32>, font: FontId) -> Self {
+        let mut camera = Camera2D::for_canvas(canvas_size);
+        camera.order = 2;
+        camera.layers = RenderLayers::HUD;
+        camera.clear_color = None;
+        camera.interactive = false;
+        // Its own view is the only place it can be, at a zoom of 1.
+        camera.fit_bounds = true;
+        let hud = Hud {
+            camera: world.create_entity().with(camera).build(),
+            counter: world
+                .create_entity()
+                .with(Text::new("", font))
+                .with(RenderLayers::HUD)
+                .build(),
+        };
+        hud.layout(world, canvas_size);
+        hud
+    }
+
+    /// Fits the camera to the canvas and puts the counter back in its
+    /// corner, after the canvas was resized.
+    fn layout(&self, world: &World, canvas_size: Vec2<i32>) {
+        let size: Vec2<f32> = canvas_size.numcast().unwrap();
+        if let Some(camera) = world.write_storage::<Camera2D>().get_mut(self.camera) {
+            camera.position = size / 2.;
+            camera.bounds = Some(Bounds {
+                min: Vec2::zero(),
+                max: size,
+            });
+        }
         world
-            .create_entity()
-            .with(Position::new(x as f32, y as f32))
-            .with(Velocity::from_angle(
-                rng.gen_range(-std::f32::consts::PI, std::f32::consts::PI),
-                4.,
-            ))
-            .with(Transform::from_image(&bunny_image))
-            .with(Sprite::from_image(bunny_id))
-            .build();
+            .write_storage::<Position>()
+            .insert(self.counter, Position::new(8., size.y - 4.))
+            .unwrap();
     }
+}
 
+/// A title over the playfield. Its font is a distance field, so it stays sharp
+/// on the minimap too.
+fn spawn_title(world: &mut World, canvas_size: Vec2<i32>, font: FontId) -> Entity {
+    let style = TextStyle {
+        align: Align::Center,
+        outline: Some(Outline {
+            width: 1.,
+            color: Rgba::new(0.1, 0.1, 0.2, 1.),
+        }),
+        shadow: Some(Shadow {
+            offset: Vec2::new(1.5, -1.5),
+            softness: 1.,
+            color: Rgba::new(0., 0., 0., 0.5),
+        }),
+        ..TextStyle::default()
+    };
+    let position = Position::new(canvas_size.x as f32 / 2., canvas_size.y as f32 - 40.);
     world
+        .create_entity()
+        .with(position)
+        .with(Text {
+            text: "Catch the bunnies!".to_string(),
+            font,
+            style,
+        })
+        .with(Tint(Rgba::new(1., 0.85, 0.3, 1.)))
+        .with(Layer::BACKGROUND)
+        .build()
+}
+
+/// Puts a hat on `wearer`, with its brim at `position` relative to it.
+fn spawn_hat(
+    world: &mut World,
+    wearer: Entity,
+    position: Position,
+    image: ImageId,
+    transform: Transform,
+) -> Result<Entity> {
+    let hat = world
+        .create_entity()
+        .with(position)
+        .with(Pivot(Vec2::new(0.5, 0.)))
+        .with(transform)
+        .with(Sprite::from_image(image))
+        .with(Layer::FOREGROUND)
+        .with(ZIndex(1))
+        .with(RenderLayers::DETAIL)
+        .build();
+    set_parent(
+        &mut world.write_storage(),
+        &mut world.write_storage(),
+        hat,
+        Parent::new(wearer),
+    )?;
+    Ok(hat)
 }
 
 struct DomEvents {
-    mouse_down: Closure<dyn Fn()>,
+    window: Window,
+    // The listeners are only held so they live as long as the game.
+    #[allow(dead_code)]
+    mouse_down: Closure<dyn Fn(MouseEvent)>,
+    #[allow(dead_code)]
     mouse_up: Closure<dyn Fn()>,
+    #[allow(dead_code)]
+    mouse_move: Closure<dyn Fn(MouseEvent)>,
+    #[allow(dead_code)]
+    key_down: Closure<dyn Fn(KeyboardEvent)>,
+    #[allow(dead_code)]
+    key_up: Closure<dyn Fn(KeyboardEvent)>,
     input_events: Rc<Mutex<Vec<InputEvent>>>,
+    gamepad_actions: Vec<Action>,
 }
 
 impl DomEvents {
-    pub fn register(window: &Window) -> Self {
+    pub fn register(window: &Window, canvas: &HtmlCanvasElement) -> Self {
         let input_events = Rc::new(Mutex::new(Vec::<InputEvent>::new()));
         let events = input_events.clone();
-        let mouse_down = Closure::wrap(Box::new(move || {
+        let target = canvas.clone();
+        let mouse_down = Closure::wrap(Box::new(move |event: MouseEvent| {
             let mut evs = events.lock().unwrap();
-            evs.push(InputEvent::MouseDown);
-        }) as Box<dyn Fn()>);
+            evs.push(InputEvent::MouseDown(canvas_point(&target, &event)));
+        }) as Box<dyn Fn(MouseEvent)>);
         window
             .add_event_listener_with_callback("mousedown", mouse_down.as_ref().unchecked_ref())
             .unwrap();
@@ -130,20 +625,313 @@ impl DomEvents {
         window
             .add_event_listener_with_callback("mouseup", mouse_up.as_ref().unchecked_ref())
             .unwrap();
+        let events = input_events.clone();
+        let target = canvas.clone();
+        let mouse_move = Closure::wrap(Box::new(move |event: MouseEvent| {
+            let mut evs = events.lock().unwrap();
+            evs.push(InputEvent::MouseMove(canvas_point(&target, &event)));
+        }) as Box<dyn Fn(MouseEvent)>);
+        window
+            .add_event_listener_with_callback("mousemove", mouse_move.as_ref().unchecked_ref())
+            .unwrap();
+        let events = input_events.clone();
+        let key_down = Closure::wrap(Box::new(move |event: KeyboardEvent| {
+            if let Some(action) = key_action(&event.code()) {
+                event.prevent_default();
+                if !event.repeat() {
+                    events.lock().unwrap().push(InputEvent::ActionStart(action));
+                }
+            }
+        }) as Box<dyn Fn(KeyboardEvent)>);
+        window
+            .add_event_listener_with_callback("keydown", key_down.as_ref().unchecked_ref())
+            .unwrap();
+        let events = input_events.clone();
+        let key_up = Closure::wrap(Box::new(move |event: KeyboardEvent| {
+            if let Some(action) = key_action(&event.code()) {
+                events.lock().unwrap().push(InputEvent::ActionEnd(action));
+            }
+        }) as Box<dyn Fn(KeyboardEvent)>);
+        window
+            .add_event_listener_with_callback("keyup", key_up.as_ref().unchecked_ref())
+            .unwrap();
 
         DomEvents {
+            window: window.clone(),
             mouse_down,
             mouse_up,
+            mouse_move,
+            key_down,
+            key_up,
             input_events,
+            gamepad_actions: Vec::new(),
         }
     }
 
-    pub fn process_input(&self, channel: &mut EventChannel<InputEvent>) {
+    pub fn process_input(&mut self, channel: &mut EventChannel<InputEvent>) {
+        self.poll_gamepads(channel);
+
         let mut events = self.input_events.lock().unwrap();
         channel.drain_vec_write(&mut events);
     }
 
-    pub fn clear(&mut self) {
-        self.input_events.lock().unwrap().clear();
+    /// Gamepads don't fire events, so compare the currently held actions to
+    /// the previous frame and emit the difference.
+    fn poll_gamepads(&mut self, channel: &mut EventChannel<InputEvent>) {
+        let mut actions = Vec::new();
+        if let Ok(gamepads) = self.window.navigator().get_gamepads() {
+            for gamepad in gamepads.iter() {
+                if let Ok(gamepad) = gamepad.dyn_into::<Gamepad>() {
+                    gamepad_actions(&gamepad, &mut actions);
+                }
+            }
+        }
+
+        for action in actions.iter() {
+            if !self.gamepad_actions.contains(action) {
+                channel.single_write(InputEvent::ActionStart(*action));
+            }
+        }
+        for action in self.gamepad_actions.iter() {
+            if !actions.contains(action) {
+                channel.single_write(InputEvent::ActionEnd(*action));
+            }
+        }
+        self.gamepad_actions = actions;
+    }
+}
+
+/// Mouse position in canvas pixels, which differ from CSS pixels when the
+/// canvas is scaled.
+fn canvas_point(canvas: &HtmlCanvasElement, event: &MouseEvent) -> Vec2<f32> {
+    let rect = canvas.get_bounding_client_rect();
+    let x = (event.client_x() as f64 - rect.left()) * canvas.width() as f64 / rect.width();
+    let y = (event.client_y() as f64 - rect.top()) * canvas.height() as f64 / rect.height();
+    Vec2::new(x as f32, y as f32)
+}
+
+fn key_action(code: &str) -> Option<Action> {
+    match code {
+        "ArrowLeft" | "KeyA" => Some(Action::Left),
+        "ArrowRight" | "KeyD" => Some(Action::Right),
+        "ArrowUp" | "KeyW" | "Space" => Some(Action::Jump),
+        _ => None,
+    }
+}
+
+// Button indices follow the "standard" gamepad mapping.
+const GAMEPAD_JUMP: u32 = 0;
+const GAMEPAD_DPAD_LEFT: u32 = 14;
+const GAMEPAD_DPAD_RIGHT: u32 = 15;
+const GAMEPAD_DEADZONE: f64 = 0.5;
+
+fn gamepad_actions(gamepad: &Gamepad, actions: &mut Vec<Action>) {
+    let pressed = |index: u32| {
+        gamepad
+            .buttons()
+            .get(index)
+            .dyn_into::<GamepadButton>()
+            .map(|button| button.pressed())
+            .unwrap_or(false)
+    };
+    let stick_x = gamepad.axes().get(0).as_f64().unwrap_or(0.);
+
+    let mut push = |action: Action| {
+        if !actions.contains(&action) {
+            actions.push(action);
+        }
+    };
+    if stick_x < -GAMEPAD_DEADZONE || pressed(GAMEPAD_DPAD_LEFT) {
+        push(Action::Left);
+    }
+    if stick_x > GAMEPAD_DEADZONE || pressed(GAMEPAD_DPAD_RIGHT) {
+        push(Action::Right);
+    }
+    if pressed(GAMEPAD_JUMP) {
+        push(Action::Jump);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vek::Aabr;
+
+    fn simulate(seed: u64, ticks: u32) -> Vec<(u32, Vec2<u32>)> {
+        simulate_with(PhysicsConfig::default(), seed, ticks)
+    }
+
+    fn simulate_with(physics: PhysicsConfig, seed: u64, ticks: u32) -> Vec<(u32, Vec2<u32>)> {
+        let prefabs = Prefabs::builtin(&[("/assets/images/bunny.png", Vec2::new(26, 37))]);
+        let mut world = init_world(Vec2::new(800, 600), seed, prefabs).unwrap();
+        world.insert(physics);
+        let mut input_system = InputSystem::new(&mut world);
+        let mut move_system = MoveSystem;
+        let mut collision_system = CollisionSystem::new();
+
+        for tick in 0..ticks {
+            // Hold the mouse for a while to spawn bunnies mid-simulation.
+            match tick {
+                100 => world
+                    .fetch_mut::<EventChannel<InputEvent>>()
+                    .single_write(InputEvent::MouseDown(Vec2::zero())),
+                110 => world
+                    .fetch_mut::<EventChannel<InputEvent>>()
+                    .single_write(InputEvent::MouseUp),
+                _ => {}
+            }
+
+            input_system.run_now(&world);
+            move_system.run_now(&world);
+            TransformSystem.run_now(&world);
+            collision_system.run_now(&world);
+            world.maintain();
+        }
+
+        let entities = world.entities();
+        let positions = world.read_storage::<Position>();
+        (&entities, &positions)
+            .join()
+            .map(|(entity, position)| (entity.id(), position.0.map(f32::to_bits)))
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_produces_identical_positions() {
+        let a = simulate(1234, 1000);
+        let b = simulate(1234, 1000);
+        assert!(a.len() > 3);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn body_collisions_are_deterministic() {
+        let physics = PhysicsConfig {
+            body_collisions: true,
+            ..PhysicsConfig::default()
+        };
+        let a = simulate_with(physics, 1234, 1000);
+        assert_eq!(a, simulate_with(physics, 1234, 1000));
+        assert_ne!(a, simulate(1234, 1000));
+    }
+
+    #[test]
+    fn the_hud_follows_the_canvas_size() {
+        let prefabs = Prefabs::builtin(&[("/assets/images/bunny.png", Vec2::new(26, 37))]);
+        let mut world = init_world(Vec2::new(800, 600), 1, prefabs).unwrap();
+        let hud = Hud::spawn(&mut world, Vec2::new(800, 600), FontId::default());
+        hud.layout(&world, Vec2::new(1024, 300));
+        world.fetch_mut::<WindowSize>().size = Vec2::new(1024, 300);
+        CameraSystem::new().run_now(&world);
+
+        let cameras = world.read_storage::<Camera2D>();
+        let camera = cameras.get(hud.camera).unwrap();
+        assert_eq!(camera.position, Vec2::new(512., 150.));
+        assert_eq!(camera.zoom, 1.);
+        assert_eq!(
+            world.read_storage::<Position>().get(hud.counter),
+            Some(&Position::new(8., 296.))
+        );
+    }
+
+    #[test]
+    fn paused_worlds_only_change_on_step() {
+        let prefabs = Prefabs::builtin(&[("/assets/images/bunny.png", Vec2::new(26, 37))]);
+        let mut world = init_world(Vec2::new(800, 600), 1, prefabs).unwrap();
+        world
+            .create_entity()
+            .with(Player::default())
+            .with(Position::new(400., 20.))
+            .with(Velocity::new(0., 0.))
+            .with(Transform::from_size(Vec2::new(32, 40)))
+            .build();
+        let mut input_system = InputSystem::new(&mut world);
+        let mut player_control_system = PlayerControlSystem::new(&mut world);
+        let mut obstacle_system = ObstacleSystem::new();
+        let mut collision_system = CollisionSystem::new();
+        // The simulating part of `Game::run_world`.
+        let mut frame = |world: &mut World, simulate: bool| {
+            world.insert(Paused(!simulate));
+            input_system.run_now(world);
+            player_control_system.run_now(world);
+            if simulate {
+                MoveSystem.run_now(world);
+            }
+            TransformSystem.run_now(world);
+            if simulate {
+                obstacle_system.run_now(world);
+                collision_system.run_now(world);
+            }
+            world.maintain();
+        };
+        let state = |world: &World| {
+            let positions: Vec<_> = world.read_storage::<Position>().join().copied().collect();
+            (positions, world.fetch::<BunnyCount>().0)
+        };
+
+        frame(&mut world, true);
+        let before = state(&world);
+        {
+            let mut input_events = world.fetch_mut::<EventChannel<InputEvent>>();
+            input_events.single_write(InputEvent::MouseDown(Vec2::zero()));
+            input_events.single_write(InputEvent::ActionStart(Action::Right));
+        }
+        world.fetch_mut::<SpawnRequest>().0 = 5;
+        for _ in 0..10 {
+            frame(&mut world, false);
+        }
+        assert_eq!(state(&world), before);
+
+        // Stepping runs a single frame, with what was held meanwhile.
+        frame(&mut world, true);
+        let (positions, bunnies) = state(&world);
+        assert_eq!(bunnies, before.1 + 5 + SpawnRate::default().0);
+        assert_ne!(positions[..before.0.len()], before.0[..]);
+    }
+
+    #[test]
+    fn holding_the_mouse_over_the_debug_ui_doesnt_spawn() {
+        let prefabs = Prefabs::builtin(&[("/assets/images/bunny.png", Vec2::new(26, 37))]);
+        let mut world = init_world(Vec2::new(800, 600), 1, prefabs).unwrap();
+        world.insert(PointerClaimed {
+            panels: vec![Aabr {
+                min: Vec2::new(600., 0.),
+                max: Vec2::new(800., 200.),
+            }],
+        });
+        let mut input_system = InputSystem::new(&mut world);
+        let mut frame = |world: &mut World, event: InputEvent| {
+            world
+                .fetch_mut::<EventChannel<InputEvent>>()
+                .single_write(event);
+            let before = world.fetch::<BunnyCount>().0;
+            input_system.run_now(world);
+            world.maintain();
+            world.fetch::<BunnyCount>().0 - before
+        };
+        let rate = SpawnRate::default().0;
+
+        assert_eq!(
+            frame(&mut world, InputEvent::MouseDown(Vec2::new(700., 100.))),
+            0
+        );
+        assert_eq!(frame(&mut world, InputEvent::MouseUp), 0);
+        assert_eq!(
+            frame(&mut world, InputEvent::MouseDown(Vec2::new(100., 100.))),
+            rate
+        );
+        assert_eq!(
+            frame(&mut world, InputEvent::MouseMove(Vec2::new(700., 100.))),
+            0
+        );
+        assert_eq!(
+            frame(&mut world, InputEvent::MouseMove(Vec2::new(500., 100.))),
+            rate
+        );
+    }
+
+    #[test]
+    fn different_seeds_diverge() {
+        assert_ne!(simulate(1, 1000), simulate(2, 1000));
     }
 }
@@ -330,6 +330,38 @@ impl Image {
     }
 }
 
+impl Image {
+    /// A texture from raw RGBA bytes, row by row from the top.
+    pub fn from_pixels(gl: Rc<Gl>, size: Vec2<u16>, pixels: &[u8]) -> Image {
+        let texture = gl.create_texture().expect("should create texture");
+        gl.bind_texture(Gl::TEXTURE_2D, Some(&texture));
+
+        gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+            Gl::TEXTURE_2D,
+            0,
+            Gl::RGBA as i32,
+            size.x as i32,
+            size.y as i32,
+            0,
+            Gl::RGBA,
+            Gl::UNSIGNED_BYTE,
+            Some(pixels),
+        )
+        .unwrap();
+
+        gl.generate_mipmap(Gl::TEXTURE_2D);
+
+        gl.bind_texture(Gl::TEXTURE_2D, None);
+
+        Image {
+            gl,
+            width: size.x,
+            height: size.y,
+            texture: Rc::new(texture),
+        }
+    }
+}
+
 unsafe impl Send for Image {}
 unsafe impl Sync for Image {}
 
@@ -0,0 +1,181 @@
+use anyhow::Result;
+use specs::prelude::*;
+use specs::storage::MaskedStorage;
+use specs::{Component, DenseVecStorage, HashMapStorage};
+use std::ops::Deref;
+
+/// Attaches an entity to another one. The child's `Position`, `Rotation` and
+/// `Scale` are then relative to the parent, see `TransformPropagationSystem`.
+///
+/// Use `set_parent` instead of inserting it directly, so the parent's
+/// `Children` stay in sync and cycles are caught.
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+#[storage(DenseVecStorage)]
+pub struct Parent {
+    pub entity: Entity,
+    /// Whether the child is deleted along with its parent. Otherwise it's
+    /// left where it was in world space.
+    pub despawn_with_parent: bool,
+}
+
+impl Parent {
+    pub fn new(entity: Entity) -> Self {
+        Parent {
+            entity,
+            despawn_with_parent: true,
+        }
+    }
+}
+
+#[derive(Component, Clone, Debug, Default, PartialEq)]
+#[storage(HashMapStorage)]
+pub struct Children(Vec<Entity>);
+
+impl Children {
+    pub fn iter(&self) -> impl Iterator<Item = &Entity> {
+        self.0.iter()
+    }
+
+    pub(crate) fn retain(&mut self, f: impl FnMut(&Entity) -> bool) {
+        self.0.retain(f);
+    }
+}
+
+/// Attaches `child` to `parent.entity`, moving it away from its previous
+/// parent. Fails without changing anything if `child` is `parent.entity`
+/// or one of its ancestors.
+pub fn set_parent(
+    parents: &mut WriteStorage<Parent>,
+    children: &mut WriteStorage<Children>,
+    child: Entity,
+    parent: Parent,
+) -> Result<()> {
+    let mut ancestor = Some(parent.entity);
+    while let Some(entity) = ancestor {
+        if entity == child {
+            anyhow::bail!(
+                "attaching {:?} to {:?} would create a cycle",
+                child,
+                parent.entity
+            );
+        }
+        ancestor = parents.get(entity).map(|parent| parent.entity);
+    }
+
+    remove_parent(parents, children, child);
+    children
+        .entry(parent.entity)?
+        .or_insert_with(Children::default)
+        .0
+        .push(child);
+    parents.insert(child, parent)?;
+    Ok(())
+}
+
+/// Detaches `child` from its parent. Its `Position` is kept as is, so it's
+/// now read as a world space position.
+pub fn remove_parent(
+    parents: &mut WriteStorage<Parent>,
+    children: &mut WriteStorage<Children>,
+    child: Entity,
+) {
+    if let Some(parent) = parents.remove(child) {
+        if let Some(siblings) = children.get_mut(parent.entity) {
+            siblings.retain(|entity| *entity != child);
+        }
+    }
+}
+
+/// Deletes `entity` and all of its descendants.
+pub fn despawn_recursive<D>(entities: &Entities, children: &Storage<Children, D>, entity: Entity)
+where
+    D: Deref<Target = MaskedStorage<Children>>,
+{
+    let mut stack = vec![entity];
+    while let Some(entity) = stack.pop() {
+        if let Some(children) = children.get(entity) {
+            stack.extend(children.iter());
+        }
+        // Children may already be gone, that's fine.
+        let _ = entities.delete(entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.register::<Parent>();
+        world.register::<Children>();
+        world
+    }
+
+    fn attach(world: &World, child: Entity, parent: Entity) -> Result<()> {
+        set_parent(
+            &mut world.write_storage(),
+            &mut world.write_storage(),
+            child,
+            Parent::new(parent),
+        )
+    }
+
+    fn children(world: &World, parent: Entity) -> Vec<Entity> {
+        world
+            .read_storage::<Children>()
+            .get(parent)
+            .map(|children| children.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn reparenting_moves_the_child() {
+        let mut world = world();
+        let a = world.create_entity().build();
+        let b = world.create_entity().build();
+        let child = world.create_entity().build();
+
+        attach(&world, child, a).unwrap();
+        assert_eq!(children(&world, a), vec![child]);
+
+        attach(&world, child, b).unwrap();
+        assert_eq!(children(&world, a), vec![]);
+        assert_eq!(children(&world, b), vec![child]);
+        assert_eq!(world.read_storage::<Parent>().get(child).unwrap().entity, b);
+    }
+
+    #[test]
+    fn cycles_are_rejected() {
+        let mut world = world();
+        let root = world.create_entity().build();
+        let middle = world.create_entity().build();
+        let leaf = world.create_entity().build();
+        attach(&world, middle, root).unwrap();
+        attach(&world, leaf, middle).unwrap();
+
+        assert!(attach(&world, root, leaf).is_err());
+        assert!(attach(&world, root, root).is_err());
+        assert!(!world.read_storage::<Parent>().contains(root));
+        assert_eq!(children(&world, leaf), vec![]);
+    }
+
+    #[test]
+    fn despawning_removes_the_subtree() {
+        let mut world = world();
+        let root = world.create_entity().build();
+        let middle = world.create_entity().build();
+        let leaf = world.create_entity().build();
+        let other = world.create_entity().build();
+        attach(&world, middle, root).unwrap();
+        attach(&world, leaf, middle).unwrap();
+
+        despawn_recursive(&world.entities(), &world.read_storage(), root);
+        world.maintain();
+
+        assert!(!world.is_alive(root));
+        assert!(!world.is_alive(middle));
+        assert!(!world.is_alive(leaf));
+        assert!(world.is_alive(other));
+    }
+}
@@ -1,37 +1,76 @@
-use crate::assets::{ImageId, Images};
-use crate::components::{Position, Velocity};
-use crate::gl::Image;
-use crate::render_system::{Sprite, Transform, WindowSize};
-use rand::Rng;
+use crate::drag_system::Dragged;
+use crate::physics::Paused;
+use crate::prefab::{Param, Prefab, Prefabs, Vec2Param};
+use crate::random::{Random, RngStream};
+use crate::render_system::WindowSize;
 use shrev::EventChannel;
 use specs::prelude::*;
-use std::rc::Rc;
+use vek::{Aabr, Vec2};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Left,
+    Right,
+    Jump,
+}
+
+/// Mouse positions are in canvas pixels with the origin at the top left.
 #[derive(Debug, Clone, Copy)]
 pub enum InputEvent {
-    MouseDown,
+    MouseDown(Vec2<f32>),
     MouseUp,
+    MouseMove(Vec2<f32>),
+    ActionStart(Action),
+    ActionEnd(Action),
 }
 
 #[derive(Default, Debug)]
 pub struct BunnyCount(pub u32);
 
+/// Where the pointer belongs to the debug UI, in canvas pixels like
+/// `InputEvent`s. Presses there don't reach the game, and neither does
+/// holding the button while hovering there.
+#[derive(Default, Debug)]
+pub struct PointerClaimed {
+    pub panels: Vec<Aabr<f32>>,
+}
+
+impl PointerClaimed {
+    pub fn claims(&self, point: Vec2<f32>) -> bool {
+        self.panels.iter().any(|panel| panel.contains_point(point))
+    }
+}
+
+/// Bunnies spawned every frame while the mouse button is held down.
+#[derive(Debug)]
+pub struct SpawnRate(pub u32);
+
+impl Default for SpawnRate {
+    fn default() -> Self {
+        SpawnRate(20)
+    }
+}
+
+/// Bunnies to spawn next frame on top of the ones from holding the mouse,
+/// e.g. by the benchmark.
+#[derive(Default, Debug)]
+pub struct SpawnRequest(pub u32);
+
 pub struct InputSystem {
     input_reader: ReaderId<InputEvent>,
-    images: Rc<Images>,
-    bunny_image_id: ImageId,
+    pointer: Vec2<f32>,
+    /// Whether the button went down on the game and is still held.
     spawn: bool,
 }
 
 impl InputSystem {
-    pub fn new(images: Rc<Images>, world: &mut World, bunny_image: ImageId) -> Self {
+    pub fn new(world: &mut World) -> Self {
         let input_reader = world
             .fetch_mut::<EventChannel<InputEvent>>()
             .register_reader();
         InputSystem {
-            images,
             input_reader,
-            bunny_image_id: bunny_image,
+            pointer: Vec2::zero(),
             spawn: false,
         }
     }
@@ -42,46 +81,85 @@ impl<'a> System<'a> for InputSystem {
         Entities<'a>,
         Read<'a, WindowSize>,
         Write<'a, BunnyCount>,
+        Write<'a, SpawnRequest>,
+        Read<'a, SpawnRate>,
+        Read<'a, PointerClaimed>,
+        Read<'a, Paused>,
+        Write<'a, Random>,
+        Read<'a, Prefabs>,
         Read<'a, EventChannel<InputEvent>>,
         Read<'a, LazyUpdate>,
+        ReadStorage<'a, Dragged>,
     );
 
     fn run(
         &mut self,
-        (entities, window_size, mut bunny_count, mut input_events, updater): Self::SystemData,
+        (
+            entities,
+            window_size,
+            mut bunny_count,
+            mut spawn_request,
+            spawn_rate,
+            pointer_claimed,
+            paused,
+            mut random,
+            prefabs,
+            input_events,
+            updater,
+            dragged,
+        ): Self::SystemData,
     ) {
         {
             let events = input_events.read(&mut self.input_reader);
             for event in events {
                 match event {
-                    InputEvent::MouseDown => {
-                        self.spawn = true;
+                    InputEvent::MouseDown(point) => {
+                        self.pointer = *point;
+                        self.spawn = !pointer_claimed.claims(*point);
+                    }
+                    InputEvent::MouseMove(point) => {
+                        self.pointer = *point;
                     }
                     InputEvent::MouseUp => {
                         self.spawn = false;
                     }
+                    _ => {}
                 }
             }
         }
 
-        if self.spawn {
-            let mut rng = rand::thread_rng();
-            let size = window_size.size;
-            let image = self.images.image(self.bunny_image_id).unwrap();
+        // Requests wait for the next frame that runs.
+        if paused.0 {
+            return;
+        }
+
+        // Grabbing a bunny shouldn't also spawn new ones.
+        let dragging = (&dragged).join().next().is_some();
+
+        let mut count = std::mem::take(&mut spawn_request.0);
+        if self.spawn && !dragging && !pointer_claimed.claims(self.pointer) {
+            count += spawn_rate.0;
+        }
 
-            let count = 20;
+        if count > 0 {
+            let rng = random.stream(RngStream::Spawn);
+            // Bunnies come in at the top left.
+            let overrides = Prefab {
+                position: Some(Vec2Param {
+                    x: Param::Range([10., 150.]),
+                    y: Param::Fixed(window_size.size.y as f32 - 10.),
+                }),
+                ..Prefab::default()
+            };
 
             for _ in 0..count {
-                let bunny = entities.create();
-                let angle: f32 = rng.gen_range(-std::f32::consts::PI / 2., 0.);
-                let velocity: f32 = rng.gen_range(0.1, 10.);
-                let x = rng.gen_range(10., 150.);
-                updater.insert(bunny, Position::new(x, size.y as f32 - 10.));
-                updater.insert(bunny, Velocity::from_angle(angle, velocity));
-                updater.insert(bunny, Transform::from_image(image));
-                updater.insert(bunny, Sprite::from_image(self.bunny_image_id));
+                // Only fails without a bunny prefab, `Game` checks its image.
+                match prefabs.instance("bunny", &overrides, rng) {
+                    Ok(bunny) => bunny.build(updater.create_entity(&entities)),
+                    Err(_) => return,
+                };
+                bunny_count.0 += 1;
             }
-            bunny_count.0 += count;
         }
     }
 }
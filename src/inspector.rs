@@ -0,0 +1,306 @@
+//! Looking at and editing components of the running game as JSON, from the
+//! devtools console through `js_api`.
+//!
+//! Components opt in by implementing `Inspect` and being registered with the
+//! `Inspector` resource, which works for components from other crates too:
+//!
+//! ```text
+//! impl Inspect for Health {
+//!     const NAME: &'static str = "Health";
+//! }
+//! world.fetch_mut::<Inspector>().register::<Health>();
+//! ```
+
+use crate::components::{AngularVelocity, Position, Rotation, Velocity};
+use crate::render_system::{Sprite, Tint, Transform};
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use specs::prelude::*;
+
+/// A component the inspector can show and edit.
+pub trait Inspect: Component + Serialize + DeserializeOwned {
+    /// What it's called in the JSON.
+    const NAME: &'static str;
+}
+
+impl Inspect for Position {
+    const NAME: &'static str = "Position";
+}
+
+impl Inspect for Velocity {
+    const NAME: &'static str = "Velocity";
+}
+
+impl Inspect for Rotation {
+    const NAME: &'static str = "Rotation";
+}
+
+impl Inspect for AngularVelocity {
+    const NAME: &'static str = "AngularVelocity";
+}
+
+impl Inspect for Transform {
+    const NAME: &'static str = "Transform";
+}
+
+impl Inspect for Sprite {
+    const NAME: &'static str = "Sprite";
+}
+
+impl Inspect for Tint {
+    const NAME: &'static str = "Tint";
+}
+
+/// An inspected component with its type erased.
+struct Entry {
+    name: &'static str,
+    has: fn(&World, Entity) -> bool,
+    get: fn(&World, Entity) -> Result<Option<Value>>,
+    set: fn(&World, Entity, &str) -> Result<()>,
+}
+
+fn has<T: Inspect>(world: &World, entity: Entity) -> bool {
+    world.read_storage::<T>().contains(entity)
+}
+
+fn get<T: Inspect>(world: &World, entity: Entity) -> Result<Option<Value>> {
+    match world.read_storage::<T>().get(entity) {
+        Some(component) => Ok(Some(serde_json::to_value(component)?)),
+        None => Ok(None),
+    }
+}
+
+fn set<T: Inspect>(world: &World, entity: Entity, json: &str) -> Result<()> {
+    let mut storage = world.write_storage::<T>();
+    let update: Value = serde_json::from_str(json)?;
+    let value = match (storage.get(entity), update) {
+        // Objects only replace the fields they have, like the physics config.
+        (Some(current), Value::Object(update)) => match serde_json::to_value(current)? {
+            Value::Object(mut current) => {
+                current.extend(update);
+                Value::Object(current)
+            }
+            _ => Value::Object(update),
+        },
+        (_, update) => update,
+    };
+    storage.insert(entity, serde_json::from_value(value)?)?;
+    Ok(())
+}
+
+/// The components the inspector knows about, a resource.
+pub struct Inspector {
+    entries: Vec<Entry>,
+}
+
+impl Default for Inspector {
+    fn default() -> Self {
+        let mut inspector = Inspector {
+            entries: Vec::new(),
+        };
+        inspector.register::<Position>();
+        inspector.register::<Velocity>();
+        inspector.register::<Rotation>();
+        inspector.register::<AngularVelocity>();
+        inspector.register::<Transform>();
+        inspector.register::<Sprite>();
+        inspector.register::<Tint>();
+        inspector
+    }
+}
+
+/// An entity in `Inspector::entities`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct EntitySummary {
+    pub id: u32,
+    pub components: Vec<&'static str>,
+}
+
+impl Inspector {
+    /// `T` also has to be registered with the world.
+    pub fn register<T: Inspect>(&mut self) {
+        self.entries.retain(|entry| entry.name != T::NAME);
+        self.entries.push(Entry {
+            name: T::NAME,
+            has: has::<T>,
+            get: get::<T>,
+            set: set::<T>,
+        });
+    }
+
+    /// Every live entity, with the names of its inspected components.
+    pub fn entities(&self, world: &World) -> Vec<EntitySummary> {
+        world
+            .entities()
+            .join()
+            .map(|entity| EntitySummary {
+                id: entity.id(),
+                components: self
+                    .entries
+                    .iter()
+                    .filter(|entry| (entry.has)(world, entity))
+                    .map(|entry| entry.name)
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// The inspected components of entity `id` as a JSON object by name.
+    pub fn entity(&self, world: &World, id: u32) -> Result<Map<String, Value>> {
+        let entity = alive(world, id)?;
+        let mut components = Map::new();
+        for entry in self.entries.iter() {
+            if let Some(value) = (entry.get)(world, entity)? {
+                components.insert(entry.name.to_string(), value);
+            }
+        }
+        Ok(components)
+    }
+
+    /// Sets a component of entity `id` from JSON, adding it if the entity
+    /// doesn't have one. For an existing component, an object only needs
+    /// the fields that change.
+    pub fn set(&self, world: &World, id: u32, component: &str, json: &str) -> Result<()> {
+        let entity = alive(world, id)?;
+        let entry = self
+            .entries
+            .iter()
+            .find(|entry| entry.name == component)
+            .ok_or_else(|| anyhow!("no component called {}", component))?;
+        (entry.set)(world, entity, json)
+    }
+}
+
+/// `Entities::entity` makes up a generation for ids that aren't in use, so
+/// only the ones a join finds are alive.
+fn alive(world: &World, id: u32) -> Result<Entity> {
+    world
+        .entities()
+        .join()
+        .find(|entity| entity.id() == id)
+        .ok_or_else(|| anyhow!("no entity {}", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use serde_json::json;
+    use specs::{Component, VecStorage};
+    use vek::Vec2;
+
+    /// Stands in for a component from another crate.
+    #[derive(Component, Debug, PartialEq, Serialize, Deserialize)]
+    #[storage(VecStorage)]
+    struct Health {
+        current: u32,
+        max: u32,
+    }
+
+    impl Inspect for Health {
+        const NAME: &'static str = "Health";
+    }
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.register::<Position>();
+        world.register::<Velocity>();
+        world.register::<Rotation>();
+        world.register::<AngularVelocity>();
+        world.register::<Transform>();
+        world.register::<Sprite>();
+        world.register::<Tint>();
+        world.register::<Health>();
+        world
+    }
+
+    #[test]
+    fn entities_list_their_inspected_components() {
+        let mut world = world();
+        let mut inspector = Inspector::default();
+        let bunny = world
+            .create_entity()
+            .with(Position::new(1., 2.))
+            .with(Velocity::new(3., 0.))
+            .with(Health {
+                current: 5,
+                max: 10,
+            })
+            .build();
+        let dead = world.create_entity().with(Position::new(0., 0.)).build();
+        world.delete_entity(dead).unwrap();
+        world.maintain();
+
+        let entities = inspector.entities(&world);
+        assert_eq!(
+            entities,
+            vec![EntitySummary {
+                id: bunny.id(),
+                components: vec!["Position", "Velocity"],
+            }]
+        );
+
+        inspector.register::<Health>();
+        let components = inspector.entity(&world, bunny.id()).unwrap();
+        assert_eq!(
+            Value::Object(components),
+            json!({
+                "Position": {"x": 1.0, "y": 2.0},
+                "Velocity": {"x": 3.0, "y": 0.0},
+                "Health": {"current": 5, "max": 10},
+            })
+        );
+        assert!(inspector.entity(&world, dead.id()).is_err());
+    }
+
+    #[test]
+    fn components_are_edited_in_place() {
+        let mut world = world();
+        let mut inspector = Inspector::default();
+        inspector.register::<Health>();
+        let bunny = world
+            .create_entity()
+            .with(Position::new(1., 2.))
+            .with(Health {
+                current: 5,
+                max: 10,
+            })
+            .build();
+
+        inspector
+            .set(&world, bunny.id(), "Position", r#"{"y": 20}"#)
+            .unwrap();
+        inspector
+            .set(&world, bunny.id(), "Health", r#"{"current": 10}"#)
+            .unwrap();
+        inspector
+            .set(&world, bunny.id(), "Velocity", r#"{"x": 1, "y": -1}"#)
+            .unwrap();
+        assert_eq!(
+            world.read_storage::<Position>().get(bunny),
+            Some(&Position::new(1., 20.))
+        );
+        assert_eq!(
+            world.read_storage::<Health>().get(bunny),
+            Some(&Health {
+                current: 10,
+                max: 10
+            })
+        );
+        assert_eq!(
+            world.read_storage::<Velocity>().get(bunny).unwrap().0,
+            Vec2::new(1., -1.)
+        );
+
+        assert!(inspector.set(&world, bunny.id(), "Mana", "{}").is_err());
+        assert!(inspector
+            .set(&world, bunny.id(), "Position", r#"{"y": "up"}"#)
+            .is_err());
+        assert_eq!(
+            world.read_storage::<Position>().get(bunny),
+            Some(&Position::new(1., 20.))
+        );
+    }
+}
@@ -0,0 +1,167 @@
+//! Functions for poking at the running game from JS, e.g. the devtools console.
+
+use crate::benchmark::BenchmarkConfig;
+use crate::game::Game;
+use crate::prefab::Prefab;
+use crate::snapshot::Snapshot;
+use anyhow::Result;
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+
+thread_local! {
+    static GAME: RefCell<Option<Rc<RefCell<Game>>>> = const { RefCell::new(None) };
+}
+
+pub fn set_game(game: Rc<RefCell<Game>>) {
+    GAME.with(|current| *current.borrow_mut() = Some(game));
+}
+
+fn with_game<T>(f: impl FnOnce(&mut Game) -> Result<T>) -> Result<T, JsValue> {
+    GAME.with(|current| {
+        let current = current.borrow();
+        let game = current
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("the game hasn't been started"))?;
+        let mut game = game.borrow_mut();
+        f(&mut game).map_err(|err| JsValue::from_str(&err.to_string()))
+    })
+}
+
+/// The current physics configuration as JSON.
+#[wasm_bindgen]
+pub fn physics_config() -> Result<String, JsValue> {
+    with_game(|game| Ok(game.physics_config().to_json()))
+}
+
+/// Updates the fields present in the JSON object, e.g.
+/// `set_physics_config('{"gravity": {"x": 0, "y": -0.2}}')`.
+#[wasm_bindgen]
+pub fn set_physics_config(json: &str) -> Result<(), JsValue> {
+    with_game(|game| game.update_physics_config(json))
+}
+
+/// Sprites drawn and culled last frame, and the number of draw calls, as
+/// JSON.
+#[wasm_bindgen]
+pub fn render_stats() -> Result<String, JsValue> {
+    with_game(|game| Ok(serde_json::to_string(&game.render_stats())?))
+}
+
+/// Frame times and counts over the last couple of seconds as JSON, with
+/// averages and percentiles, e.g. for benchmark runs.
+#[wasm_bindgen]
+pub fn frame_stats() -> Result<String, JsValue> {
+    with_game(|game| Ok(serde_json::to_string(&game.frame_stats())?))
+}
+
+/// Forgets the frames so far, e.g. after warming up.
+#[wasm_bindgen]
+pub fn reset_frame_stats() -> Result<(), JsValue> {
+    with_game(|game| {
+        game.reset_frame_stats();
+        Ok(())
+    })
+}
+
+/// Shows or hides the frame stats overlay.
+#[wasm_bindgen]
+pub fn show_frame_stats(visible: bool) -> Result<(), JsValue> {
+    with_game(|game| {
+        game.show_frame_stats(visible);
+        Ok(())
+    })
+}
+
+/// Starts a bunnymark, which adds bunnies until frames get too slow. The
+/// optional JSON overrides fields of the defaults, e.g.
+/// `start_benchmark('{"step": 500, "threshold_ms": 34}')`.
+#[wasm_bindgen]
+pub fn start_benchmark(config: Option<String>) -> Result<(), JsValue> {
+    with_game(|game| {
+        let config = match config {
+            Some(json) => BenchmarkConfig::from_json(&json)?,
+            None => BenchmarkConfig::default(),
+        };
+        game.start_benchmark(config);
+        Ok(())
+    })
+}
+
+/// The finished benchmark's result as JSON, or `undefined` while it's still
+/// running or none was started.
+#[wasm_bindgen]
+pub fn benchmark_result() -> Result<Option<String>, JsValue> {
+    with_game(|game| {
+        Ok(match game.benchmark_result() {
+            Some(result) => Some(serde_json::to_string(result)?),
+            None => None,
+        })
+    })
+}
+
+/// Live entities with the names of their inspectable components, as JSON.
+#[wasm_bindgen]
+pub fn entities() -> Result<String, JsValue> {
+    with_game(|game| Ok(serde_json::to_string(&game.entities())?))
+}
+
+/// The inspectable components of an entity as a JSON object by name.
+#[wasm_bindgen]
+pub fn entity(id: u32) -> Result<String, JsValue> {
+    with_game(|game| Ok(serde_json::to_string(&game.entity(id)?)?))
+}
+
+/// Changes a component of an entity, e.g.
+/// `set_component(12, 'Velocity', '{"y": 20}')`. Objects only need the
+/// fields that change.
+#[wasm_bindgen]
+pub fn set_component(id: u32, component: &str, json: &str) -> Result<(), JsValue> {
+    with_game(|game| game.set_component(id, component, json))
+}
+
+/// Spawns a prefab from `assets/prefabs.json` and returns the entity's id,
+/// e.g. `spawn_prefab('bunny', '{"position": {"x": 100, "y": [0, 600]}}')`.
+#[wasm_bindgen]
+pub fn spawn_prefab(name: &str, overrides: Option<String>) -> Result<u32, JsValue> {
+    with_game(|game| {
+        let overrides = match overrides {
+            Some(json) => Prefab::from_json(&json)?,
+            None => Prefab::default(),
+        };
+        Ok(game.spawn_prefab(name, &overrides)?.id())
+    })
+}
+
+/// The bunnies, counters and physics config as JSON, for `load_snapshot`.
+#[wasm_bindgen]
+pub fn save_snapshot() -> Result<String, JsValue> {
+    with_game(|game| game.save_snapshot()?.to_json())
+}
+
+/// Like `save_snapshot`, but a lot smaller, as a `Uint8Array`.
+#[wasm_bindgen]
+pub fn save_snapshot_binary() -> Result<Vec<u8>, JsValue> {
+    with_game(|game| game.save_snapshot()?.to_bytes())
+}
+
+/// Replaces the bunnies with the ones from `save_snapshot`.
+#[wasm_bindgen]
+pub fn load_snapshot(json: &str) -> Result<(), JsValue> {
+    with_game(|game| game.load_snapshot(Snapshot::from_json(json)?))
+}
+
+#[wasm_bindgen]
+pub fn load_snapshot_binary(bytes: &[u8]) -> Result<(), JsValue> {
+    with_game(|game| game.load_snapshot(Snapshot::from_bytes(bytes)?))
+}
+
+/// Shakes the camera, e.g. `shake_camera(0.5)`. Trauma adds up to 1 and
+/// fades out over a second or so.
+#[wasm_bindgen]
+pub fn shake_camera(trauma: f32) -> Result<(), JsValue> {
+    with_game(|game| {
+        game.shake_camera(trauma);
+        Ok(())
+    })
+}
@@ -0,0 +1,125 @@
+use crate::assets::{ImageId, Images};
+use crate::components::{Position, Scale};
+use crate::draw_order::Layer;
+use crate::obstacle_system::{Obstacle, ObstacleKind};
+use crate::render_system::{Sprite, Transform};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use specs::prelude::*;
+use vek::Vec2;
+
+pub const OBSTACLE_IMAGES: [&str; 3] = [
+    "/assets/images/block.png",
+    "/assets/images/slope.png",
+    "/assets/images/platform.png",
+];
+
+/// Static geometry of the playfield, loaded from `assets/levels/*.json`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Level {
+    pub obstacles: Vec<ObstacleDef>,
+}
+
+/// An obstacle in world coordinates, `position` is its center.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ObstacleDef {
+    pub kind: ObstacleKind,
+    pub position: Vec2<f32>,
+    pub size: Vec2<f32>,
+}
+
+impl Level {
+    pub fn from_json(json: &str) -> Result<Self> {
+        let level: Level = serde_json::from_str(json)?;
+        if let Some(def) = level
+            .obstacles
+            .iter()
+            .find(|def| def.size.x <= 0. || def.size.y <= 0.)
+        {
+            anyhow::bail!("obstacle at {} has an empty size", def.position);
+        }
+        Ok(level)
+    }
+
+    pub fn spawn(&self, world: &mut World, images: &Images) -> Result<Vec<Entity>> {
+        let find = |path: &str| {
+            images
+                .find_image_id(path)
+                .ok_or_else(|| anyhow!("{} isn't loaded", path))
+        };
+        let block = find(OBSTACLE_IMAGES[0])?;
+        let slope = find(OBSTACLE_IMAGES[1])?;
+        let platform = find(OBSTACLE_IMAGES[2])?;
+
+        Ok(self
+            .obstacles
+            .iter()
+            .map(|def| {
+                let image = match def.kind {
+                    ObstacleKind::Box => block,
+                    ObstacleKind::SlopeUp | ObstacleKind::SlopeDown => slope,
+                    ObstacleKind::Platform => platform,
+                };
+                spawn_obstacle(world, def, image)
+            })
+            .collect())
+    }
+}
+
+fn spawn_obstacle(world: &mut World, def: &ObstacleDef, image: ImageId) -> Entity {
+    let mut builder = world
+        .create_entity()
+        .with(Position(def.position))
+        .with(Obstacle {
+            kind: def.kind,
+            half_extents: def.size / 2.,
+        })
+        .with(Transform::new(def.size))
+        .with(Sprite::from_image(image))
+        .with(Layer::BACKGROUND);
+    // The slope image rises to the right, mirror it for the other direction.
+    if def.kind == ObstacleKind::SlopeDown {
+        builder = builder.with(Scale(Vec2::new(-1., 1.)));
+    }
+    builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_parse_obstacle_kinds() {
+        let level = Level::from_json(
+            r#"{ "obstacles": [
+                { "kind": "box", "position": { "x": 100, "y": 50 }, "size": { "x": 64, "y": 32 } },
+                { "kind": "slope_down", "position": { "x": 0, "y": 0 }, "size": { "x": 10, "y": 10 } }
+            ] }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            level.obstacles[0],
+            ObstacleDef {
+                kind: ObstacleKind::Box,
+                position: Vec2::new(100., 50.),
+                size: Vec2::new(64., 32.),
+            }
+        );
+        assert_eq!(level.obstacles[1].kind, ObstacleKind::SlopeDown);
+
+        assert!(Level::from_json(
+            r#"{ "obstacles": [
+                { "kind": "ramp", "position": { "x": 0, "y": 0 }, "size": { "x": 1, "y": 1 } }
+            ] }"#
+        )
+        .is_err());
+        assert!(Level::from_json(
+            r#"{ "obstacles": [
+                { "kind": "box", "position": { "x": 0, "y": 0 }, "size": { "x": 0, "y": 1 } }
+            ] }"#
+        )
+        .is_err());
+        assert_eq!(Level::from_json("{}").unwrap(), Level::default());
+    }
+}
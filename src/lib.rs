@@ -1,36 +1,52 @@
 use anyhow::Result;
-use data::load_image;
-use shrev::EventChannel;
-use specs::prelude::*;
 use std::cell::RefCell;
 use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
-use wasm_bindgen_futures::spawn_local;
-use web_sys::{console, HtmlElement, HtmlImageElement, WebGlRenderingContext};
+use web_sys::WebGlRenderingContext;
 
-use rand::Rng;
-
-use crate::assets::Images;
-use crate::components::{Position, Velocity};
-use crate::data::Size;
 use crate::game::Game;
-use crate::gl::Image;
-use crate::input_system::{InputEvent, InputSystem};
-use crate::render_system::{DisplayEvent, RenderSystem, Sprite, Transform, WindowSize};
-use std::sync::Mutex;
 use vek::Vec2;
 
 #[macro_use]
 mod utils;
 mod assets;
+pub mod benchmark;
+pub mod camera;
+mod camera_system;
+mod collision;
+mod collision_system;
 mod components;
+mod culling;
 mod data;
+mod debug_draw;
+mod debug_ui;
+mod drag_system;
+pub mod draw_order;
+pub mod font;
+mod frame_stats;
 mod game;
 mod gl;
+mod hierarchy;
 mod input_system;
+pub mod inspector;
+mod js_api;
+mod level;
 mod move_system;
+mod obstacle_system;
+pub mod physics;
+mod picking;
+mod player_system;
+pub mod prefab;
+mod random;
 mod render_system;
+pub mod snapshot;
+mod spatial;
+pub mod sprite_batch;
+pub mod text;
+pub mod tilemap;
+mod tint_system;
+mod transform_system;
 
 fn window() -> web_sys::Window {
     web_sys::window().expect("no global `window` exists")
@@ -42,13 +58,19 @@ fn request_animation_frame(closure: &Closure<dyn FnMut()>) {
         .expect("should register 'requestAnimationFrame OK'");
 }
 
+/// Starts the demo. Runs with the same `seed` are identical; without one a
+/// seed is picked from the current time. Resolves once the game is running.
 #[wasm_bindgen]
-pub fn start(counter_el: HtmlElement) {
-    spawn_local(async { async_start(counter_el).await.unwrap() })
+pub async fn start(seed: Option<u32>) -> Result<(), JsValue> {
+    let seed = seed.map_or_else(|| js_sys::Date::now() as u64, u64::from);
+    async_start(seed)
+        .await
+        .map_err(|err| JsValue::from_str(&err.to_string()))
 }
 
-pub async fn async_start(counter_el: HtmlElement) -> Result<()> {
+pub async fn async_start(seed: u64) -> Result<()> {
     crate::utils::set_panic_hook();
+    console_log!("Random seed: {}", seed);
 
     let frame = Rc::new(RefCell::new(None));
     let cloned_frame = frame.clone();
@@ -57,7 +79,7 @@ pub async fn async_start(counter_el: HtmlElement) -> Result<()> {
 
     let canvas = doc.get_element_by_id("view").unwrap();
     let canvas: web_sys::HtmlCanvasElement = canvas.dyn_into().unwrap();
-    let mut canvas_size = get_canvas_size(&canvas);
+    let canvas_size = get_canvas_size(&canvas);
 
     let context: WebGlRenderingContext = canvas
         .get_context("webgl")
@@ -67,15 +89,17 @@ pub async fn async_start(counter_el: HtmlElement) -> Result<()> {
         .unwrap();
 
     let win = window();
-    let mut game = Game::init(Rc::new(context), &win, canvas_size).await?;
+    let game = Game::init(Rc::new(context), &win, &canvas, canvas_size, seed).await?;
+    let game = Rc::new(RefCell::new(game));
+    js_api::set_game(game.clone());
 
     *cloned_frame.borrow_mut() = Some(Closure::wrap(Box::new(move || {
         let new_size = get_canvas_size(&canvas);
+        let mut game = game.borrow_mut();
 
         game.process_events();
         game.run_world(new_size);
-
-        counter_el.set_inner_text(&format!("Bunnies: {}", game.bunny_count()));
+        drop(game);
 
         request_animation_frame(frame.borrow().as_ref().unwrap());
     }) as Box<dyn FnMut()>));
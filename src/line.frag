@@ -0,0 +1,5 @@
+varying lowp vec4 tint;
+
+void main(void) {
+    gl_FragColor = tint;
+}
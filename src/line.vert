@@ -0,0 +1,11 @@
+uniform mat4 projection;
+
+attribute vec2 coordinates;
+attribute vec4 color;
+
+varying lowp vec4 tint;
+
+void main(void) {
+    gl_Position = projection * vec4(coordinates, 0.0, 1.0);
+    tint = color;
+}
@@ -1,51 +1,147 @@
-use crate::assets::Images;
-use crate::components::{Position, Velocity};
-use crate::render_system::{DisplayEvent, Transform, WindowSize};
+use crate::collision::{bounce_off_walls, collision_extents, Collider};
+use crate::components::{AngularVelocity, Position, Rotation, Velocity};
+use crate::drag_system::Dragged;
+use crate::physics::{PhysicsConfig, PhysicsOverride};
+use crate::player_system::Player;
+use crate::random::{Random, RngStream};
+use crate::render_system::{Transform, WindowSize};
 use rand::Rng;
-use shrev::EventChannel;
 use specs::prelude::*;
-use std::rc::Rc;
-use vek::Vec2;
 
 pub struct MoveSystem;
 impl<'a> System<'a> for MoveSystem {
     type SystemData = (
         WriteStorage<'a, Velocity>,
         WriteStorage<'a, Position>,
+        ReadStorage<'a, PhysicsOverride>,
+        ReadStorage<'a, Collider>,
+        ReadStorage<'a, Transform>,
+        Read<'a, PhysicsConfig>,
         Read<'a, WindowSize>,
+        Write<'a, Random>,
+        ReadStorage<'a, Player>,
+        ReadStorage<'a, Dragged>,
+        WriteStorage<'a, Rotation>,
+        ReadStorage<'a, AngularVelocity>,
     );
 
-    fn run(&mut self, (mut velocities, mut positions, window_size): Self::SystemData) {
-        let gravity = 0.75f32;
+    fn run(
+        &mut self,
+        (
+            mut velocities,
+            mut positions,
+            overrides,
+            colliders,
+            transforms,
+            config,
+            window_size,
+            mut random,
+            players,
+            dragged,
+            mut rotations,
+            angular_velocities,
+        ): Self::SystemData,
+    ) {
+        let bounds = config.bounds(window_size.size);
+
+        let rng = random.stream(RngStream::Physics);
+        for (velocity, position, overrides, collider, transform, _, _) in (
+            &mut velocities,
+            &mut positions,
+            overrides.maybe(),
+            colliders.maybe(),
+            transforms.maybe(),
+            !&players,
+            !&dragged,
+        )
+            .join()
+        {
+            let config = config.with_overrides(overrides);
 
-        for (velocity, position) in (&mut velocities, &mut positions).join() {
             position.0 += velocity.0;
-            velocity.0.y -= gravity;
+            velocity.0 += config.gravity;
+
+            let extents = collision_extents(collider, transform);
+            let on_floor =
+                bounce_off_walls(&mut position.0, &mut velocity.0, extents, &bounds, &config);
+            if on_floor && rng.gen_range(0., 1.) < config.kick_probability {
+                // `gen_range` needs a range that isn't empty.
+                velocity.0.y += if config.kick_max > config.kick_min {
+                    rng.gen_range(config.kick_min, config.kick_max)
+                } else {
+                    config.kick_min
+                };
+            }
+        }
+
+        for (rotation, angular_velocity, _) in
+            (&mut rotations, &angular_velocities, !&dragged).join()
+        {
+            rotation.0 = (rotation.0 + angular_velocity.0) % std::f32::consts::TAU;
         }
+    }
+}
 
-        let size: Vec2<f32> = window_size.size.numcast().unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vek::Vec2;
 
-        let mut rng = rand::thread_rng();
-        for (velocity, position) in (&mut velocities, &mut positions).join() {
-            if position.0.x > size.x {
-                velocity.0.x *= -1.;
-                position.0.x = size.x;
-            } else if position.0.x < 0. {
-                velocity.0.x *= -1.;
-                position.0.x = 0.;
-            }
+    #[test]
+    fn fixed_and_overridden_kicks_are_applied() {
+        let mut world = World::new();
+        world.register::<Velocity>();
+        world.register::<Position>();
+        world.register::<PhysicsOverride>();
+        world.register::<Collider>();
+        world.register::<Transform>();
+        world.register::<Player>();
+        world.register::<Dragged>();
+        world.register::<Rotation>();
+        world.register::<AngularVelocity>();
+        world.insert(PhysicsConfig {
+            gravity: Vec2::zero(),
+            restitution: 0.,
+            kick_probability: 1.,
+            kick_min: 5.,
+            kick_max: 5.,
+            ..PhysicsConfig::default()
+        });
+        world.insert(WindowSize {
+            size: Vec2::new(800, 600),
+        });
+        world.insert(Random::from_seed(1));
+        let bunny = world
+            .create_entity()
+            .with(Position::new(100., 1.))
+            .with(Velocity::new(0., -2.))
+            .build();
 
-            if position.0.y > size.y {
-                velocity.0.y = 0.;
-                position.0.y = size.y;
-            } else if position.0.y < 0. {
-                velocity.0.y *= -1.;
-                position.0.y = 0.;
+        // Kicks harder, and never, by its own override.
+        let heavy = world
+            .create_entity()
+            .with(Position::new(200., 1.))
+            .with(Velocity::new(0., -2.))
+            .with(PhysicsOverride {
+                kick_min: Some(8.),
+                kick_max: Some(8.),
+                ..PhysicsOverride::default()
+            })
+            .build();
+        let still = world
+            .create_entity()
+            .with(Position::new(300., 1.))
+            .with(Velocity::new(0., -2.))
+            .with(PhysicsOverride {
+                kick_probability: Some(0.),
+                ..PhysicsOverride::default()
+            })
+            .build();
 
-                if rng.gen_range(0., 1.) > 0.5 {
-                    velocity.0.y -= rng.gen_range(0., 6.);
-                }
-            }
-        }
+        MoveSystem.run_now(&world);
+        let velocities = world.read_storage::<Velocity>();
+        assert_eq!(velocities.get(bunny).unwrap().0, Vec2::new(0., 5.));
+        assert_eq!(velocities.get(heavy).unwrap().0, Vec2::new(0., 8.));
+        assert_eq!(velocities.get(still).unwrap().0, Vec2::zero());
     }
 }
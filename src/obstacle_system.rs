@@ -0,0 +1,382 @@
+use crate::collision::{collision_extents, Collider};
+use crate::components::{Position, Velocity};
+use crate::drag_system::Dragged;
+use crate::physics::{PhysicsConfig, PhysicsOverride};
+use crate::render_system::Transform;
+use serde::{Deserialize, Serialize};
+use specs::prelude::*;
+use specs::{Component, DenseVecStorage, NullStorage};
+use vek::Vec2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ObstacleKind {
+    /// Solid from every side.
+    Box,
+    /// Surface rising from the bottom left corner to the top right one.
+    SlopeUp,
+    /// Surface falling from the top left corner to the bottom right one.
+    SlopeDown,
+    /// Only its top is solid, and only for bodies coming from above.
+    Platform,
+}
+
+/// Immovable level geometry centered on the entity's `Position`.
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+#[storage(DenseVecStorage)]
+pub struct Obstacle {
+    pub kind: ObstacleKind,
+    pub half_extents: Vec2<f32>,
+}
+
+/// Set on bodies standing on top of an obstacle.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq)]
+#[storage(NullStorage)]
+pub struct Grounded;
+
+/// Slower bounces than this come to rest on obstacles instead of jittering.
+const REST_SPEED: f32 = 1.;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Contact {
+    /// Points out of the obstacle.
+    normal: Vec2<f32>,
+    depth: f32,
+}
+
+/// Pushes moving bodies out of obstacles and bounces them off.
+///
+/// Every body is tested against every obstacle, which is fine for hand made
+/// levels with tens of obstacles.
+pub struct ObstacleSystem {
+    obstacles: Vec<(Vec2<f32>, Obstacle)>,
+    grounded: Vec<(Entity, bool)>,
+}
+
+impl ObstacleSystem {
+    pub fn new() -> Self {
+        ObstacleSystem {
+            obstacles: Vec::new(),
+            grounded: Vec::new(),
+        }
+    }
+}
+
+impl Default for ObstacleSystem {
+    fn default() -> Self {
+        ObstacleSystem::new()
+    }
+}
+
+impl<'a> System<'a> for ObstacleSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Obstacle>,
+        WriteStorage<'a, Position>,
+        WriteStorage<'a, Velocity>,
+        WriteStorage<'a, Grounded>,
+        ReadStorage<'a, Collider>,
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, PhysicsOverride>,
+        ReadStorage<'a, Dragged>,
+        Read<'a, PhysicsConfig>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            obstacles,
+            mut positions,
+            mut velocities,
+            mut grounded,
+            colliders,
+            transforms,
+            overrides,
+            dragged,
+            config,
+        ): Self::SystemData,
+    ) {
+        self.obstacles.clear();
+        self.obstacles
+            .extend((&positions, &obstacles).join().map(|(p, o)| (p.0, *o)));
+        if self.obstacles.is_empty() {
+            return;
+        }
+
+        self.grounded.clear();
+        for (entity, position, velocity, collider, transform, overrides, was_grounded, _) in (
+            &entities,
+            &mut positions,
+            &mut velocities,
+            colliders.maybe(),
+            transforms.maybe(),
+            overrides.maybe(),
+            grounded.maybe(),
+            !&dragged,
+        )
+            .join()
+        {
+            let config = config.with_overrides(overrides);
+            let extents = collision_extents(collider, transform);
+
+            let mut on_ground = false;
+            for (center, obstacle) in self.obstacles.iter() {
+                if let Some(contact) = collide(*center, obstacle, position.0, velocity.0, extents) {
+                    position.0 += contact.normal * contact.depth;
+                    bounce(&mut velocity.0, contact.normal, &config);
+                    on_ground |= contact.normal.y > 0.5;
+                }
+            }
+
+            if on_ground != was_grounded.is_some() {
+                self.grounded.push((entity, on_ground));
+            }
+        }
+
+        for (entity, on_ground) in self.grounded.iter() {
+            if *on_ground {
+                grounded.insert(*entity, Grounded).unwrap();
+            } else {
+                grounded.remove(*entity);
+            }
+        }
+    }
+}
+
+fn bounce(velocity: &mut Vec2<f32>, normal: Vec2<f32>, config: &PhysicsConfig) {
+    let speed = velocity.dot(normal);
+    if speed >= 0. {
+        return;
+    }
+    let tangent = *velocity - normal * speed;
+    let bounced = -speed * config.restitution;
+    let bounced = if normal.y > 0.5 && bounced < REST_SPEED {
+        0.
+    } else {
+        bounced
+    };
+    *velocity = tangent * (1. - config.friction) + normal * bounced;
+}
+
+/// Contact between an obstacle and a body with the given half size.
+fn collide(
+    center: Vec2<f32>,
+    obstacle: &Obstacle,
+    position: Vec2<f32>,
+    velocity: Vec2<f32>,
+    extents: Vec2<f32>,
+) -> Option<Contact> {
+    let half = obstacle.half_extents;
+    let offset = position - center;
+    let overlap = half + extents - offset.map(f32::abs);
+    if overlap.x <= 0. || overlap.y <= 0. {
+        return None;
+    }
+
+    match obstacle.kind {
+        ObstacleKind::Box => {
+            let sign = |value: f32| if value < 0. { -1. } else { 1. };
+            Some(if overlap.x < overlap.y {
+                Contact {
+                    normal: Vec2::new(sign(offset.x), 0.),
+                    depth: overlap.x,
+                }
+            } else {
+                Contact {
+                    normal: Vec2::new(0., sign(offset.y)),
+                    depth: overlap.y,
+                }
+            })
+        }
+        ObstacleKind::Platform => {
+            // Only land when the bottom was above the top last frame.
+            let top = half.y;
+            let bottom = offset.y - extents.y;
+            if velocity.y <= 0. && bottom - velocity.y >= top - 0.01 {
+                Some(Contact {
+                    normal: Vec2::unit_y(),
+                    depth: top - bottom,
+                })
+            } else {
+                None
+            }
+        }
+        ObstacleKind::SlopeUp | ObstacleKind::SlopeDown => {
+            // Bodies touch the surface with the bottom corner facing it.
+            let rising = obstacle.kind == ObstacleKind::SlopeUp;
+            let corner = if rising {
+                Vec2::new(offset.x + extents.x, offset.y - extents.y)
+            } else {
+                Vec2::new(offset.x - extents.x, offset.y - extents.y)
+            };
+            let x = corner.x.max(-half.x).min(half.x);
+            let surface = if rising { x } else { -x } * half.y / half.x;
+            if corner.y >= surface || offset.y < -half.y {
+                return None;
+            }
+            let normal = if rising {
+                Vec2::new(-half.y, half.x)
+            } else {
+                Vec2::new(half.y, half.x)
+            }
+            .normalized();
+            Some(Contact {
+                normal,
+                depth: (surface - corner.y) * normal.y,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn obstacle(kind: ObstacleKind) -> Obstacle {
+        Obstacle {
+            kind,
+            half_extents: Vec2::new(50., 50.),
+        }
+    }
+
+    const BODY: Vec2<f32> = Vec2 { x: 10., y: 10. };
+
+    #[test]
+    fn boxes_push_out_of_the_nearest_side() {
+        let contact = collide(
+            Vec2::zero(),
+            &obstacle(ObstacleKind::Box),
+            Vec2::new(0., 58.),
+            Vec2::new(0., -3.),
+            BODY,
+        );
+        assert_eq!(
+            contact,
+            Some(Contact {
+                normal: Vec2::unit_y(),
+                depth: 2.
+            })
+        );
+
+        let contact = collide(
+            Vec2::zero(),
+            &obstacle(ObstacleKind::Box),
+            Vec2::new(-57., 0.),
+            Vec2::new(3., 0.),
+            BODY,
+        );
+        assert_eq!(contact.unwrap().normal, Vec2::new(-1., 0.));
+    }
+
+    #[test]
+    fn platforms_only_stop_bodies_from_above() {
+        let platform = obstacle(ObstacleKind::Platform);
+        let landing = collide(
+            Vec2::zero(),
+            &platform,
+            Vec2::new(0., 58.),
+            Vec2::new(0., -3.),
+            BODY,
+        );
+        assert_eq!(landing.unwrap().depth, 2.);
+
+        let jumping_through = collide(
+            Vec2::zero(),
+            &platform,
+            Vec2::new(0., 58.),
+            Vec2::new(0., 3.),
+            BODY,
+        );
+        assert_eq!(jumping_through, None);
+
+        let inside = collide(
+            Vec2::zero(),
+            &platform,
+            Vec2::new(0., 20.),
+            Vec2::new(0., -3.),
+            BODY,
+        );
+        assert_eq!(inside, None);
+    }
+
+    #[test]
+    fn slopes_push_along_their_normal() {
+        // Surface height at x = 0 is 0, the body's bottom right corner is at
+        // (10, -2).
+        let contact = collide(
+            Vec2::zero(),
+            &obstacle(ObstacleKind::SlopeUp),
+            Vec2::new(0., 8.),
+            Vec2::zero(),
+            BODY,
+        )
+        .unwrap();
+        let diagonal = std::f32::consts::FRAC_1_SQRT_2;
+        assert!((contact.normal - Vec2::new(-diagonal, diagonal)).magnitude() < 1e-5);
+        assert!((contact.depth - 12. * diagonal).abs() < 1e-5);
+
+        let above = collide(
+            Vec2::zero(),
+            &obstacle(ObstacleKind::SlopeDown),
+            Vec2::new(0., 21.),
+            Vec2::zero(),
+            BODY,
+        );
+        assert_eq!(above, None);
+    }
+
+    #[test]
+    fn slow_bodies_come_to_rest_on_top() {
+        let config = PhysicsConfig::default();
+        let mut velocity = Vec2::new(2., -0.5);
+        bounce(&mut velocity, Vec2::unit_y(), &config);
+        assert_eq!(velocity, Vec2::new(2., 0.));
+
+        let mut velocity = Vec2::new(2., -5.);
+        bounce(&mut velocity, Vec2::unit_y(), &config);
+        assert_eq!(velocity, Vec2::new(2., 5.));
+    }
+
+    #[test]
+    fn bodies_landing_on_obstacles_are_grounded() {
+        let mut world = World::new();
+        world.register::<Obstacle>();
+        world.register::<Position>();
+        world.register::<Velocity>();
+        world.register::<Grounded>();
+        world.register::<Collider>();
+        world.register::<Transform>();
+        world.register::<PhysicsOverride>();
+        world.register::<Dragged>();
+        world.insert(PhysicsConfig::default());
+        world
+            .create_entity()
+            .with(Position::new(100., 100.))
+            .with(obstacle(ObstacleKind::Box))
+            .build();
+        let body = world
+            .create_entity()
+            .with(Position::new(100., 159.))
+            .with(Velocity::new(0., -0.5))
+            .with(Collider::Aabb { half_extents: BODY })
+            .build();
+
+        let mut system = ObstacleSystem::new();
+        system.run_now(&world);
+
+        assert!(world.read_storage::<Grounded>().contains(body));
+        assert_eq!(
+            world.read_storage::<Position>().get(body).unwrap().0,
+            Vec2::new(100., 160.)
+        );
+        assert_eq!(
+            world.read_storage::<Velocity>().get(body).unwrap().0,
+            Vec2::zero()
+        );
+
+        world.write_storage::<Position>().get_mut(body).unwrap().0.y = 300.;
+        system.run_now(&world);
+        assert!(!world.read_storage::<Grounded>().contains(body));
+    }
+}
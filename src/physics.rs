@@ -0,0 +1,211 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use specs::{Component, HashMapStorage};
+use vek::Vec2;
+
+/// Tunables of `MoveSystem`, loaded from `assets/physics.json` and editable
+/// from JS at runtime.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PhysicsConfig {
+    /// Added to the velocity every frame.
+    pub gravity: Vec2<f32>,
+    /// Fraction of the velocity kept when bouncing off a wall or the floor.
+    pub restitution: f32,
+    /// Fraction of the horizontal velocity lost when bouncing off the floor.
+    pub friction: f32,
+    /// Chance of an extra upward kick when bouncing off the floor.
+    pub kick_probability: f32,
+    /// Range of the kick's speed, added to the vertical velocity since y
+    /// points up. Equal bounds always kick by that much, and `kick_min` can't
+    /// be above `kick_max`.
+    pub kick_min: f32,
+    pub kick_max: f32,
+    /// Fixed playfield, `None` follows the canvas size.
+    pub bounds: Option<Bounds>,
+    /// Whether bodies bounce off each other, see `CollisionSystem`.
+    pub body_collisions: bool,
+}
+
+/// Set for frames the simulation doesn't run, e.g. while paused from the
+/// debug UI. Systems that always run, because they read input, check it
+/// before moving or spawning anything.
+#[derive(Default, Debug)]
+pub struct Paused(pub bool);
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Bounds {
+    pub min: Vec2<f32>,
+    pub max: Vec2<f32>,
+}
+
+impl Default for PhysicsConfig {
+    fn default() -> Self {
+        PhysicsConfig {
+            gravity: Vec2::new(0., -0.75),
+            restitution: 1.,
+            friction: 0.,
+            kick_probability: 0.5,
+            kick_min: 0.,
+            kick_max: 6.,
+            bounds: None,
+            body_collisions: false,
+        }
+    }
+}
+
+impl PhysicsConfig {
+    pub fn from_json(json: &str) -> Result<Self> {
+        let config: PhysicsConfig = serde_json::from_str(json)?;
+        config.check()?;
+        Ok(config)
+    }
+
+    pub fn to_json(self) -> String {
+        serde_json::to_string(&self).unwrap()
+    }
+
+    /// Replaces the fields present in `json`, leaving the rest untouched.
+    /// Nothing changes if the result isn't valid.
+    pub fn update_from_json(&mut self, json: &str) -> Result<()> {
+        let mut current = serde_json::to_value(*self)?;
+        let update: serde_json::Value = serde_json::from_str(json)?;
+        match (&mut current, update) {
+            (serde_json::Value::Object(current), serde_json::Value::Object(update)) => {
+                current.extend(update);
+            }
+            (_, update) => anyhow::bail!("expected a JSON object, got {}", update),
+        }
+        let config: PhysicsConfig = serde_json::from_value(current)?;
+        config.check()?;
+        *self = config;
+        Ok(())
+    }
+
+    fn check(&self) -> Result<()> {
+        if self.kick_min > self.kick_max {
+            anyhow::bail!(
+                "kick_min {} is above kick_max {}",
+                self.kick_min,
+                self.kick_max
+            );
+        }
+        Ok(())
+    }
+
+    pub fn bounds(&self, window_size: Vec2<i32>) -> Bounds {
+        self.bounds.unwrap_or(Bounds {
+            min: Vec2::zero(),
+            max: window_size.numcast().unwrap(),
+        })
+    }
+
+    /// The configuration with the fields set in `overrides` replaced.
+    pub fn with_overrides(&self, overrides: Option<&PhysicsOverride>) -> PhysicsConfig {
+        let overrides = match overrides {
+            Some(overrides) => overrides,
+            None => return *self,
+        };
+        PhysicsConfig {
+            gravity: overrides.gravity.unwrap_or(self.gravity),
+            restitution: overrides.restitution.unwrap_or(self.restitution),
+            friction: overrides.friction.unwrap_or(self.friction),
+            kick_probability: overrides.kick_probability.unwrap_or(self.kick_probability),
+            kick_min: overrides.kick_min.unwrap_or(self.kick_min),
+            kick_max: overrides.kick_max.unwrap_or(self.kick_max),
+            ..*self
+        }
+    }
+}
+
+/// Per entity replacements for the global `PhysicsConfig`.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[storage(HashMapStorage)]
+pub struct PhysicsOverride {
+    pub gravity: Option<Vec2<f32>>,
+    pub restitution: Option<f32>,
+    pub friction: Option<f32>,
+    pub kick_probability: Option<f32>,
+    pub kick_min: Option<f32>,
+    pub kick_max: Option<f32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_json_updates_only_given_fields() {
+        let mut config = PhysicsConfig::default();
+        config
+            .update_from_json(r#"{ "gravity": { "x": 0.5, "y": -1 }, "friction": 0.25 }"#)
+            .unwrap();
+
+        assert_eq!(config.gravity, Vec2::new(0.5, -1.));
+        assert_eq!(config.friction, 0.25);
+        assert_eq!(config.restitution, 1.);
+
+        assert!(config.update_from_json("[1, 2]").is_err());
+        assert!(config
+            .update_from_json(r#"{ "friction": "a lot" }"#)
+            .is_err());
+        assert_eq!(config.friction, 0.25);
+    }
+
+    #[test]
+    fn kick_ranges_have_to_be_in_order() {
+        let mut config = PhysicsConfig::default();
+        config
+            .update_from_json(r#"{ "kick_min": 3, "kick_max": 3 }"#)
+            .unwrap();
+        assert!(config
+            .update_from_json(r#"{ "kick_min": 4, "kick_max": 2 }"#)
+            .is_err());
+        assert_eq!((config.kick_min, config.kick_max), (3., 3.));
+        assert!(PhysicsConfig::from_json(r#"{ "kick_min": 7 }"#).is_err());
+    }
+
+    #[test]
+    fn config_file_defaults_missing_fields() {
+        let config = PhysicsConfig::from_json(r#"{ "restitution": 0.8 }"#).unwrap();
+        assert_eq!(
+            config,
+            PhysicsConfig {
+                restitution: 0.8,
+                ..PhysicsConfig::default()
+            }
+        );
+        assert_eq!(PhysicsConfig::from_json(&config.to_json()).unwrap(), config);
+    }
+
+    #[test]
+    fn bounds_follow_the_window_unless_fixed() {
+        let mut config = PhysicsConfig::default();
+        assert_eq!(
+            config.bounds(Vec2::new(800, 600)).max,
+            Vec2::new(800., 600.)
+        );
+
+        let fixed = Bounds {
+            min: Vec2::new(-100., 0.),
+            max: Vec2::new(100., 50.),
+        };
+        config.bounds = Some(fixed);
+        assert_eq!(config.bounds(Vec2::new(800, 600)), fixed);
+    }
+
+    #[test]
+    fn overrides_replace_only_set_fields() {
+        let config = PhysicsConfig::default();
+        let overrides = PhysicsOverride {
+            gravity: Some(Vec2::zero()),
+            kick_max: Some(2.),
+            ..PhysicsOverride::default()
+        };
+        let merged = config.with_overrides(Some(&overrides));
+        assert_eq!(merged.gravity, Vec2::zero());
+        assert_eq!(merged.restitution, config.restitution);
+        assert_eq!((merged.kick_min, merged.kick_max), (config.kick_min, 2.));
+        assert_eq!(config.with_overrides(None), config);
+    }
+}
@@ -0,0 +1,194 @@
+use crate::camera::RenderLayers;
+use crate::components::Position;
+use crate::draw_order::DrawKey;
+use crate::render_system::Transform;
+use crate::spatial::SpatialHash;
+use specs::prelude::*;
+use specs::storage::GenericReadStorage;
+use vek::Vec2;
+
+/// Sprite bounds of the current frame, kept up to date by `SpatialIndexSystem`.
+#[derive(Default)]
+pub struct SpatialIndex(pub SpatialHash);
+
+pub struct SpatialIndexSystem;
+impl<'a> System<'a> for SpatialIndexSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Transform>,
+        Write<'a, SpatialIndex>,
+    );
+
+    fn run(&mut self, (entities, positions, transforms, mut index): Self::SystemData) {
+        index.0.clear();
+        for (entity, position, transform) in (&entities, &positions, &transforms).join() {
+            let center = transform.center(position.0);
+            let extents = transform.extents();
+            index.0.insert(entity, center - extents, center + extents);
+        }
+    }
+}
+
+/// The topmost sprite whose quad contains the world space `point`, see
+/// `draw_order::draw_key`. Only sprites on `layers` count, those of the
+/// camera the point was seen through, so e.g. the HUD's sprites at canvas
+/// pixels don't hide what's under them in the world.
+pub fn pick<P, T, L>(
+    point: Vec2<f32>,
+    layers: RenderLayers,
+    index: &SpatialIndex,
+    positions: &P,
+    transforms: &T,
+    render_layers: &L,
+    draw_key: impl Fn(Entity) -> Option<DrawKey>,
+) -> Option<Entity>
+where
+    P: GenericReadStorage<Component = Position>,
+    T: GenericReadStorage<Component = Transform>,
+    L: GenericReadStorage<Component = RenderLayers>,
+{
+    index
+        .0
+        .query_point(point)
+        .iter()
+        .filter_map(|entity| {
+            let on_layers = render_layers.get(*entity).copied().unwrap_or_default();
+            if !on_layers.intersects(layers) {
+                return None;
+            }
+            let position = positions.get(*entity)?;
+            let transform = transforms.get(*entity)?;
+            let key = draw_key(*entity)?;
+            if transform.contains(position.0, point) {
+                Some((key, *entity))
+            } else {
+                None
+            }
+        })
+        .max_by_key(|(order, _)| *order)
+        .map(|(_, entity)| entity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::ImageId;
+    use crate::draw_order::{draw_key, Layer, ZIndex};
+    use crate::render_system::Sprite;
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.register::<Position>();
+        world.register::<Transform>();
+        world.register::<Sprite>();
+        world.register::<Layer>();
+        world.register::<ZIndex>();
+        world.register::<RenderLayers>();
+        world.insert(SpatialIndex::default());
+        world
+    }
+
+    fn spawn(world: &mut World, position: Vec2<f32>, transform: Transform, image: u32) -> Entity {
+        world
+            .create_entity()
+            .with(Position(position))
+            .with(transform)
+            .with(Sprite::from_image(ImageId { id: image }))
+            .build()
+    }
+
+    fn pick(world: &World, point: Vec2<f32>) -> Option<Entity> {
+        pick_on(world, point, RenderLayers::ALL)
+    }
+
+    fn pick_on(world: &World, point: Vec2<f32>, layers: RenderLayers) -> Option<Entity> {
+        SpatialIndexSystem.run_now(world);
+        super::pick(
+            point,
+            layers,
+            &world.fetch::<SpatialIndex>(),
+            &world.read_storage::<Position>(),
+            &world.read_storage::<Transform>(),
+            &world.read_storage::<RenderLayers>(),
+            |entity| {
+                draw_key(
+                    entity,
+                    &world.read_storage::<Sprite>(),
+                    &world.read_storage::<Layer>(),
+                    &world.read_storage::<ZIndex>(),
+                )
+            },
+        )
+    }
+
+    #[test]
+    fn picks_the_topmost_sprite() {
+        let mut world = world();
+        let size = Transform::from_size(Vec2::new(20, 20));
+        let below = spawn(&mut world, Vec2::new(100., 100.), size, 1);
+        let above = spawn(&mut world, Vec2::new(105., 100.), size, 1);
+        let other_image = spawn(&mut world, Vec2::new(200., 100.), size, 2);
+        spawn(&mut world, Vec2::new(200., 100.), size, 0);
+
+        assert_eq!(pick(&world, Vec2::new(102., 100.)), Some(above));
+        assert_eq!(pick(&world, Vec2::new(92., 100.)), Some(below));
+        assert_eq!(pick(&world, Vec2::new(200., 100.)), Some(other_image));
+        assert_eq!(pick(&world, Vec2::new(150., 100.)), None);
+    }
+
+    #[test]
+    fn higher_layers_are_picked_first() {
+        let mut world = world();
+        let size = Transform::from_size(Vec2::new(20, 20));
+        let front = spawn(&mut world, Vec2::new(100., 100.), size, 0);
+        spawn(&mut world, Vec2::new(100., 100.), size, 2);
+        let raised = spawn(&mut world, Vec2::new(100., 100.), size, 1);
+        world.write_storage().insert(front, Layer(1)).unwrap();
+        world.write_storage().insert(raised, ZIndex(1)).unwrap();
+
+        assert_eq!(pick(&world, Vec2::new(100., 100.)), Some(front));
+
+        world.write_storage().insert(raised, Layer(1)).unwrap();
+        assert_eq!(pick(&world, Vec2::new(100., 100.)), Some(raised));
+    }
+
+    #[test]
+    fn only_sprites_the_camera_draws_are_picked() {
+        let mut world = world();
+        let size = Transform::from_size(Vec2::new(20, 20));
+        let bunny = spawn(&mut world, Vec2::new(100., 100.), size, 0);
+        // HUD sprites are at canvas pixels, which can be anywhere in the world.
+        let hud = spawn(&mut world, Vec2::new(100., 100.), size, 1);
+        world
+            .write_storage()
+            .insert(hud, Layer::FOREGROUND)
+            .unwrap();
+        world
+            .write_storage()
+            .insert(hud, RenderLayers::HUD)
+            .unwrap();
+
+        let world_layers = RenderLayers::ALL.without(RenderLayers::HUD);
+        assert_eq!(
+            pick_on(&world, Vec2::new(100., 100.), world_layers),
+            Some(bunny)
+        );
+        assert_eq!(pick(&world, Vec2::new(100., 100.)), Some(hud));
+    }
+
+    #[test]
+    fn picking_respects_rotation() {
+        let mut world = world();
+        let mut transform = Transform::from_size(Vec2::new(100, 10));
+        transform.compose(
+            std::f32::consts::FRAC_PI_2,
+            Vec2::one(),
+            Vec2::broadcast(0.5),
+        );
+        let entity = spawn(&mut world, Vec2::new(100., 100.), transform, 0);
+
+        assert_eq!(pick(&world, Vec2::new(100., 140.)), Some(entity));
+        assert_eq!(pick(&world, Vec2::new(140., 100.)), None);
+    }
+}
@@ -0,0 +1,299 @@
+use crate::collision::{collision_extents, Collider};
+use crate::components::{Position, Velocity};
+use crate::input_system::{Action, BunnyCount, InputEvent};
+use crate::obstacle_system::Grounded;
+use crate::physics::{Paused, PhysicsConfig};
+use crate::render_system::{Transform, WindowSize};
+use crate::snapshot::SaveMarker;
+use shrev::EventChannel;
+use specs::prelude::*;
+use specs::{Component, DenseVecStorage};
+
+/// Marks the entity controlled by the keyboard or a gamepad.
+///
+/// The speeds are in pixels per frame and meant to be tweaked.
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+#[storage(DenseVecStorage)]
+pub struct Player {
+    pub walk_speed: f32,
+    pub jump_speed: f32,
+}
+
+impl Default for Player {
+    fn default() -> Self {
+        Player {
+            walk_speed: 6.,
+            jump_speed: 16.,
+        }
+    }
+}
+
+/// Number of bunnies the player has caught.
+#[derive(Default, Debug)]
+pub struct CollectedCount(pub u32);
+
+pub struct PlayerControlSystem {
+    input_reader: ReaderId<InputEvent>,
+    left: bool,
+    right: bool,
+    jump: bool,
+}
+
+impl PlayerControlSystem {
+    pub fn new(world: &mut World) -> Self {
+        let input_reader = world
+            .fetch_mut::<EventChannel<InputEvent>>()
+            .register_reader();
+        PlayerControlSystem {
+            input_reader,
+            left: false,
+            right: false,
+            jump: false,
+        }
+    }
+}
+
+impl<'a> System<'a> for PlayerControlSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Player>,
+        WriteStorage<'a, Velocity>,
+        WriteStorage<'a, Position>,
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, Collider>,
+        ReadStorage<'a, Grounded>,
+        ReadStorage<'a, SaveMarker>,
+        Read<'a, WindowSize>,
+        Read<'a, PhysicsConfig>,
+        Read<'a, Paused>,
+        Write<'a, BunnyCount>,
+        Write<'a, CollectedCount>,
+        Read<'a, EventChannel<InputEvent>>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            players,
+            mut velocities,
+            mut positions,
+            transforms,
+            colliders,
+            grounded,
+            markers,
+            window_size,
+            config,
+            paused,
+            mut bunny_count,
+            mut collected,
+            input_events,
+        ): Self::SystemData,
+    ) {
+        for event in input_events.read(&mut self.input_reader) {
+            match event {
+                InputEvent::ActionStart(Action::Left) => self.left = true,
+                InputEvent::ActionEnd(Action::Left) => self.left = false,
+                InputEvent::ActionStart(Action::Right) => self.right = true,
+                InputEvent::ActionEnd(Action::Right) => self.right = false,
+                InputEvent::ActionStart(Action::Jump) => self.jump = true,
+                InputEvent::ActionEnd(Action::Jump) => self.jump = false,
+                _ => {}
+            }
+        }
+
+        // Keep track of held keys, but leave the player where it is.
+        if paused.0 {
+            return;
+        }
+
+        let bounds = config.bounds(window_size.size);
+        let direction = self.right as i32 - self.left as i32;
+
+        let mut catchers = Vec::new();
+        for (player, velocity, position, collider, transform, grounded) in (
+            &players,
+            &mut velocities,
+            &mut positions,
+            colliders.maybe(),
+            transforms.maybe(),
+            grounded.maybe(),
+        )
+            .join()
+        {
+            let extents = collision_extents(collider, transform);
+            let on_ground = grounded.is_some() || position.0.y - extents.y <= bounds.min.y;
+
+            velocity.0.x = direction as f32 * player.walk_speed;
+            if self.jump && on_ground {
+                velocity.0.y = player.jump_speed;
+                self.jump = false;
+            }
+
+            position.0 += velocity.0;
+            velocity.0 += config.gravity;
+
+            if position.0.y - extents.y < bounds.min.y {
+                position.0.y = bounds.min.y + extents.y;
+                velocity.0.y = 0.;
+            }
+            position.0.x = position
+                .0
+                .x
+                .max(bounds.min.x + extents.x)
+                .min(bounds.max.x - extents.x);
+
+            catchers.push((position.0, extents));
+        }
+
+        // Bunnies are the saved entities, like `BunnyCount` counts them.
+        for (entity, position, collider, transform, _) in (
+            &entities,
+            &positions,
+            colliders.maybe(),
+            transforms.maybe(),
+            &markers,
+        )
+            .join()
+        {
+            let extents = collision_extents(collider, transform);
+            let caught = catchers.iter().any(|(catcher, catcher_extents)| {
+                let distance = (position.0 - *catcher).map(f32::abs);
+                distance.x < extents.x + catcher_extents.x
+                    && distance.y < extents.y + catcher_extents.y
+            });
+            if caught {
+                entities.delete(entity).unwrap();
+                bunny_count.0 = bunny_count.0.saturating_sub(1);
+                collected.0 += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::Saved;
+    use specs::saveload::{MarkedBuilder, SimpleMarkerAllocator};
+    use vek::Vec2;
+
+    fn world_with_player() -> (World, PlayerControlSystem, Entity) {
+        let mut world = World::new();
+        world.register::<Player>();
+        world.register::<Position>();
+        world.register::<Velocity>();
+        world.register::<Transform>();
+        world.register::<Collider>();
+        world.register::<Grounded>();
+        world.register::<SaveMarker>();
+        world.insert(SimpleMarkerAllocator::<Saved>::default());
+        world.insert(EventChannel::<InputEvent>::new());
+        world.insert(BunnyCount(0));
+        world.insert(CollectedCount(0));
+        world.insert(PhysicsConfig::default());
+        world.insert(Paused::default());
+        world.insert(WindowSize {
+            size: Vec2::new(800, 600),
+        });
+        let system = PlayerControlSystem::new(&mut world);
+        let player = world
+            .create_entity()
+            .with(Player::default())
+            .with(Position::new(400., 20.))
+            .with(Velocity::new(0., 0.))
+            .with(Transform::from_size(Vec2::new(32, 40)))
+            .build();
+        (world, system, player)
+    }
+
+    fn send(world: &World, event: InputEvent) {
+        world
+            .fetch_mut::<EventChannel<InputEvent>>()
+            .single_write(event);
+    }
+
+    fn position(world: &World, entity: Entity) -> Vec2<f32> {
+        world.read_storage::<Position>().get(entity).unwrap().0
+    }
+
+    #[test]
+    fn player_jumps_and_lands_on_the_canvas_bottom() {
+        let (world, mut system, player) = world_with_player();
+
+        system.run_now(&world);
+        assert_eq!(position(&world, player).y, 20.);
+
+        send(&world, InputEvent::ActionStart(Action::Jump));
+        system.run_now(&world);
+        assert!(position(&world, player).y > 20.);
+
+        for _ in 0..100 {
+            system.run_now(&world);
+        }
+        assert_eq!(position(&world, player).y, 20.);
+    }
+
+    #[test]
+    fn player_walks_until_the_canvas_edge() {
+        let (world, mut system, player) = world_with_player();
+
+        send(&world, InputEvent::ActionStart(Action::Right));
+        for _ in 0..200 {
+            system.run_now(&world);
+        }
+        assert_eq!(position(&world, player).x, 800. - 16.);
+    }
+
+    #[test]
+    fn touching_bunnies_are_collected() {
+        let (mut world, mut system, _) = world_with_player();
+        world.insert(BunnyCount(2));
+        let transform = Transform::from_size(Vec2::new(26, 37));
+        let near = world
+            .create_entity()
+            .with(Position::new(410., 30.))
+            .with(Velocity::new(0., 0.))
+            .with(transform)
+            .marked::<SaveMarker>()
+            .build();
+        let far = world
+            .create_entity()
+            .with(Position::new(100., 300.))
+            .with(Velocity::new(0., 0.))
+            .with(transform)
+            .marked::<SaveMarker>()
+            .build();
+        // Moves like a bunny, but isn't one.
+        let ball = world
+            .create_entity()
+            .with(Position::new(390., 20.))
+            .with(Velocity::new(2., 0.))
+            .with(transform)
+            .build();
+
+        system.run_now(&world);
+        world.maintain();
+
+        assert!(!world.is_alive(near));
+        assert!(world.is_alive(far));
+        assert!(world.is_alive(ball));
+        assert_eq!(world.fetch::<CollectedCount>().0, 1);
+        assert_eq!(world.fetch::<BunnyCount>().0, 1);
+    }
+    #[test]
+    fn paused_players_keep_still_but_remember_keys() {
+        let (mut world, mut system, player) = world_with_player();
+        world.insert(Paused(true));
+
+        send(&world, InputEvent::ActionStart(Action::Right));
+        for _ in 0..10 {
+            system.run_now(&world);
+        }
+        assert_eq!(position(&world, player), Vec2::new(400., 20.));
+
+        world.insert(Paused(false));
+        system.run_now(&world);
+        assert!(position(&world, player).x > 400.);
+    }
+}
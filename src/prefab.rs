@@ -0,0 +1,450 @@
+//! Entities described in `assets/prefabs.json` and spawned by name, from
+//! systems with `Prefabs::instance` or anywhere else with `spawn_prefab`.
+//!
+//! Numbers can be fixed or a `[min, max]` range to pick from at random, e.g.
+//!
+//! ```text
+//! "bunny": {
+//!   "image": "/assets/images/bunny.png",
+//!   "velocity": { "angle": [-1.5707964, 0], "speed": [0.1, 10] },
+//!   "angular_velocity": [-0.1, 0.1],
+//!   "color_by_speed": {}
+//! }
+//! ```
+
+use crate::assets::ImageId;
+use crate::collision::Collider;
+use crate::components::{AngularVelocity, Position, Rotation, Velocity};
+use crate::random::{Random, RngStream};
+use crate::render_system::{Sprite, Tint, Transform};
+use crate::snapshot::SaveMarker;
+use crate::tint_system::ColorBySpeed;
+use anyhow::{anyhow, bail, Result};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use specs::prelude::*;
+use specs::saveload::MarkedBuilder;
+use std::collections::HashMap;
+use vek::{Rgba, Vec2};
+
+/// A number in a prefab.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Param {
+    Fixed(f32),
+    /// Anything from the first up to the second.
+    Range([f32; 2]),
+}
+
+impl Param {
+    pub fn sample(&self, rng: &mut impl Rng) -> f32 {
+        match *self {
+            Param::Fixed(value) => value,
+            Param::Range([min, max]) if min < max => rng.gen_range(min, max),
+            Param::Range([min, _]) => min,
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        match *self {
+            Param::Fixed(value) => value.is_finite(),
+            Param::Range([min, max]) => min.is_finite() && max.is_finite() && min <= max,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Vec2Param {
+    pub x: Param,
+    pub y: Param,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum VelocityParam {
+    /// In radians and pixels per frame.
+    Polar {
+        angle: Param,
+        speed: Param,
+    },
+    Cartesian {
+        x: Param,
+        y: Param,
+    },
+}
+
+/// The components of an entity. Everything is optional so the same struct
+/// works for overriding parts of another prefab.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Prefab {
+    /// The name an image was loaded under, for a `Sprite` and a `Transform`
+    /// of the image's size.
+    pub image: Option<String>,
+    /// Makes the `Transform` this size instead.
+    pub size: Option<Vec2<f32>>,
+    /// Collides with the `Transform`'s bounds without one.
+    pub collider: Option<Collider>,
+    pub position: Option<Vec2Param>,
+    pub velocity: Option<VelocityParam>,
+    pub rotation: Option<Param>,
+    pub angular_velocity: Option<Param>,
+    pub tint: Option<Rgba<f32>>,
+    pub color_by_speed: Option<ColorBySpeed>,
+    /// Whether snapshots include it.
+    pub saved: Option<bool>,
+}
+
+impl Prefab {
+    pub fn from_json(json: &str) -> Result<Self> {
+        let prefab: Prefab = serde_json::from_str(json)?;
+        prefab.check()?;
+        Ok(prefab)
+    }
+
+    /// This prefab with the fields `overrides` has replaced.
+    pub fn with(&self, overrides: &Prefab) -> Prefab {
+        Prefab {
+            image: overrides.image.clone().or_else(|| self.image.clone()),
+            size: overrides.size.or(self.size),
+            collider: overrides.collider.or(self.collider),
+            position: overrides.position.or(self.position),
+            velocity: overrides.velocity.or(self.velocity),
+            rotation: overrides.rotation.or(self.rotation),
+            angular_velocity: overrides.angular_velocity.or(self.angular_velocity),
+            tint: overrides.tint.or(self.tint),
+            color_by_speed: overrides.color_by_speed.or(self.color_by_speed),
+            saved: overrides.saved.or(self.saved),
+        }
+    }
+
+    fn check(&self) -> Result<()> {
+        let mut params = Vec::new();
+        if let Some(position) = self.position {
+            params.extend_from_slice(&[position.x, position.y]);
+        }
+        match self.velocity {
+            Some(VelocityParam::Polar { angle, speed }) => {
+                params.extend_from_slice(&[angle, speed])
+            }
+            Some(VelocityParam::Cartesian { x, y }) => params.extend_from_slice(&[x, y]),
+            None => {}
+        }
+        params.extend(self.rotation);
+        params.extend(self.angular_velocity);
+        if let Some(collider) = self.collider {
+            let extents = collider.extents();
+            if !(extents.x > 0. && extents.y > 0. && extents.x.is_finite() && extents.y.is_finite())
+            {
+                bail!("{:?} has to have a size", collider);
+            }
+        }
+        match params.iter().find(|param| !param.is_valid()) {
+            Some(param) => bail!("{:?} isn't a number or a range from low to high", param),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The components picked for one entity from a `Prefab`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Instance {
+    pub position: Option<Position>,
+    pub velocity: Option<Velocity>,
+    pub rotation: Option<Rotation>,
+    pub angular_velocity: Option<AngularVelocity>,
+    pub transform: Option<Transform>,
+    pub collider: Option<Collider>,
+    pub sprite: Option<Sprite>,
+    pub tint: Option<Tint>,
+    pub color_by_speed: Option<ColorBySpeed>,
+    pub saved: bool,
+}
+
+fn maybe_with<B: Builder, C: Component + Send + Sync>(builder: B, component: Option<C>) -> B {
+    match component {
+        Some(component) => builder.with(component),
+        None => builder,
+    }
+}
+
+impl Instance {
+    /// Works with `World::create_entity` as well as
+    /// `LazyUpdate::create_entity` in systems.
+    pub fn build<B: Builder + MarkedBuilder>(self, builder: B) -> Entity {
+        let builder = maybe_with(builder, self.position);
+        let builder = maybe_with(builder, self.velocity);
+        let builder = maybe_with(builder, self.rotation);
+        let builder = maybe_with(builder, self.angular_velocity);
+        let builder = maybe_with(builder, self.transform);
+        let builder = maybe_with(builder, self.collider);
+        let builder = maybe_with(builder, self.sprite);
+        let builder = maybe_with(builder, self.tint);
+        let builder = maybe_with(builder, self.color_by_speed);
+        if self.saved {
+            builder.marked::<SaveMarker>().build()
+        } else {
+            builder.build()
+        }
+    }
+}
+
+/// The prefabs by name, and the images they can use, a resource.
+#[derive(Clone, Debug, Default)]
+pub struct Prefabs {
+    prefabs: HashMap<String, Prefab>,
+    images: HashMap<String, (ImageId, Vec2<u16>)>,
+}
+
+impl Prefabs {
+    /// Parses a JSON object of prefabs by name. Their images have to be
+    /// added with `add_image` before they can be spawned.
+    pub fn from_json(json: &str) -> Result<Self> {
+        let prefabs: HashMap<String, Prefab> = serde_json::from_str(json)?;
+        for (name, prefab) in prefabs.iter() {
+            prefab
+                .check()
+                .map_err(|err| anyhow!("prefab {}: {}", name, err))?;
+        }
+        Ok(Prefabs {
+            prefabs,
+            images: HashMap::new(),
+        })
+    }
+
+    /// `assets/prefabs.json` with `images` numbered in order, for running
+    /// the simulation without a browser.
+    pub fn builtin(images: &[(&str, Vec2<u16>)]) -> Self {
+        let mut prefabs = Prefabs::from_json(include_str!("../assets/prefabs.json"))
+            .expect("assets/prefabs.json is broken");
+        for (id, (name, size)) in images.iter().enumerate() {
+            prefabs.add_image(name, ImageId { id: id as u32 }, *size);
+        }
+        prefabs
+    }
+
+    pub fn add_image(&mut self, name: &str, image_id: ImageId, size: Vec2<u16>) {
+        self.images.insert(name.to_string(), (image_id, size));
+    }
+
+    /// Makes sure every prefab's image has been added.
+    pub fn check_images(&self) -> Result<()> {
+        for (name, prefab) in self.prefabs.iter() {
+            if let Some(image) = &prefab.image {
+                if !self.images.contains_key(image) {
+                    bail!("prefab {} needs {}, which isn't loaded", name, image);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Prefab> {
+        self.prefabs.get(name)
+    }
+
+    /// Picks the components of a `name` with `overrides`, drawing the random
+    /// ones from `rng`.
+    pub fn instance(&self, name: &str, overrides: &Prefab, rng: &mut impl Rng) -> Result<Instance> {
+        let prefab = self
+            .get(name)
+            .ok_or_else(|| anyhow!("no prefab called {}", name))?
+            .with(overrides);
+        let image = match &prefab.image {
+            Some(image) => Some(
+                *self
+                    .images
+                    .get(image)
+                    .ok_or_else(|| anyhow!("no image called {}", image))?,
+            ),
+            None => None,
+        };
+        let transform = match (prefab.size, image) {
+            (Some(size), _) => Some(Transform::new(size)),
+            (None, Some((_, size))) => Some(Transform::from_size(size)),
+            (None, None) => None,
+        };
+
+        Ok(Instance {
+            position: prefab
+                .position
+                .map(|position| Position::new(position.x.sample(rng), position.y.sample(rng))),
+            velocity: prefab.velocity.map(|velocity| match velocity {
+                VelocityParam::Polar { angle, speed } => {
+                    Velocity::from_angle(angle.sample(rng), speed.sample(rng))
+                }
+                VelocityParam::Cartesian { x, y } => Velocity::new(x.sample(rng), y.sample(rng)),
+            }),
+            rotation: prefab
+                .rotation
+                .map(|rotation| Rotation(rotation.sample(rng))),
+            angular_velocity: prefab
+                .angular_velocity
+                .map(|spin| AngularVelocity(spin.sample(rng))),
+            transform,
+            collider: prefab.collider,
+            sprite: image.map(|(image_id, _)| Sprite::from_image(image_id)),
+            tint: prefab.tint.map(Tint),
+            color_by_speed: prefab.color_by_speed,
+            saved: prefab.saved.unwrap_or(false),
+        })
+    }
+}
+
+/// Spawns a `name` from the `Prefabs` resource right away, with randomness
+/// from the spawn stream.
+pub fn spawn_prefab(world: &mut World, name: &str, overrides: &Prefab) -> Result<Entity> {
+    let instance = {
+        let prefabs = world.fetch::<Prefabs>();
+        let mut random = world.fetch_mut::<Random>();
+        prefabs.instance(name, overrides, random.stream(RngStream::Spawn))?
+    };
+    Ok(instance.build(world.create_entity()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::Saved;
+    use rand::SeedableRng;
+    use rand_pcg::Pcg32;
+    use specs::saveload::SimpleMarkerAllocator;
+
+    fn prefabs() -> Prefabs {
+        let mut prefabs = Prefabs::from_json(
+            r#"{
+                "bunny": {
+                    "image": "bunny",
+                    "velocity": { "angle": [-1, 0], "speed": [0.5, 2] },
+                    "rotation": 0,
+                    "angular_velocity": [-0.1, 0.1],
+                    "color_by_speed": { "max_speed": 4 },
+                    "saved": true
+                },
+                "marker": { "position": { "x": 5, "y": [0, 10] }, "tint": { "r": 1, "g": 0, "b": 0, "a": 1 } },
+                "ball": { "collider": { "circle": { "radius": 6 } } }
+            }"#,
+        )
+        .unwrap();
+        prefabs.add_image("carrot", ImageId { id: 0 }, Vec2::new(8, 8));
+        prefabs.add_image("bunny", ImageId { id: 1 }, Vec2::new(26, 37));
+        prefabs
+    }
+
+    #[test]
+    fn prefabs_parse_numbers_and_ranges() {
+        let prefabs = prefabs();
+        let bunny = prefabs.get("bunny").unwrap();
+        assert_eq!(bunny.rotation, Some(Param::Fixed(0.)));
+        assert_eq!(bunny.angular_velocity, Some(Param::Range([-0.1, 0.1])));
+        assert_eq!(
+            bunny.velocity,
+            Some(VelocityParam::Polar {
+                angle: Param::Range([-1., 0.]),
+                speed: Param::Range([0.5, 2.]),
+            })
+        );
+        assert_eq!(bunny.color_by_speed.unwrap().max_speed, 4.);
+        assert!(prefabs.check_images().is_ok());
+
+        assert!(Prefabs::from_json(r#"{ "a": { "rotation": [1, 0] } }"#).is_err());
+        assert!(Prefabs::from_json(r#"{ "a": { "rotaton": 1 } }"#).is_err());
+        assert!(Prefabs::from_json(r#"{ "a": { "image": "hat" } }"#)
+            .unwrap()
+            .check_images()
+            .is_err());
+        assert!(Prefab::from_json(r#"{ "position": { "x": 1, "y": [3, 2] } }"#).is_err());
+        assert_eq!(
+            prefabs.get("ball").unwrap().collider,
+            Some(Collider::Circle { radius: 6. })
+        );
+        assert!(Prefab::from_json(r#"{ "collider": { "circle": { "radius": 0 } } }"#).is_err());
+    }
+
+    #[test]
+    fn instances_pick_from_ranges_and_take_overrides() {
+        let prefabs = prefabs();
+        let mut rng = Pcg32::seed_from_u64(1);
+        for _ in 0..100 {
+            let bunny = prefabs
+                .instance("bunny", &Prefab::default(), &mut rng)
+                .unwrap();
+            let velocity = bunny.velocity.unwrap().0;
+            assert!((0.5..2.).contains(&velocity.magnitude()));
+            assert!(velocity.x >= 0. && velocity.y <= 0.);
+            assert!((-0.1..0.1).contains(&bunny.angular_velocity.unwrap().0));
+            assert_eq!(bunny.rotation, Some(Rotation(0.)));
+            assert_eq!(bunny.sprite, Some(Sprite::from_image(ImageId { id: 1 })));
+            assert_eq!(
+                bunny.transform,
+                Some(Transform::from_size(Vec2::new(26, 37)))
+            );
+            assert_eq!(bunny.position, None);
+            assert!(bunny.saved);
+        }
+
+        let overrides = Prefab::from_json(
+            r#"{ "image": "carrot", "position": { "x": 1, "y": 2 }, "velocity": { "x": 3, "y": 0 } }"#,
+        )
+        .unwrap();
+        let carrot = prefabs.instance("bunny", &overrides, &mut rng).unwrap();
+        assert_eq!(carrot.position, Some(Position::new(1., 2.)));
+        assert_eq!(carrot.velocity, Some(Velocity::new(3., 0.)));
+        assert_eq!(carrot.sprite, Some(Sprite::from_image(ImageId { id: 0 })));
+        assert_eq!(
+            carrot.transform,
+            Some(Transform::from_size(Vec2::new(8, 8)))
+        );
+
+        let hat = Prefab {
+            image: Some("hat".to_string()),
+            ..Prefab::default()
+        };
+        assert!(prefabs.instance("bunny", &hat, &mut rng).is_err());
+        assert!(prefabs
+            .instance("hat", &Prefab::default(), &mut rng)
+            .is_err());
+    }
+
+    #[test]
+    fn spawned_prefabs_have_their_components() {
+        let mut world = World::new();
+        world.register::<Position>();
+        world.register::<Velocity>();
+        world.register::<Rotation>();
+        world.register::<AngularVelocity>();
+        world.register::<Transform>();
+        world.register::<Collider>();
+        world.register::<Sprite>();
+        world.register::<Tint>();
+        world.register::<ColorBySpeed>();
+        world.register::<SaveMarker>();
+        world.insert(SimpleMarkerAllocator::<Saved>::default());
+        world.insert(Random::from_seed(1));
+        world.insert(prefabs());
+
+        let bunny = spawn_prefab(&mut world, "bunny", &Prefab::default()).unwrap();
+        let marker = spawn_prefab(&mut world, "marker", &Prefab::default()).unwrap();
+        let ball = spawn_prefab(&mut world, "ball", &Prefab::default()).unwrap();
+        assert!(spawn_prefab(&mut world, "carrot", &Prefab::default()).is_err());
+
+        assert!(world.read_storage::<Velocity>().contains(bunny));
+        assert!(world.read_storage::<ColorBySpeed>().contains(bunny));
+        assert!(world.read_storage::<SaveMarker>().contains(bunny));
+        assert!(!world.read_storage::<Position>().contains(bunny));
+        assert_eq!(
+            world.read_storage::<Collider>().get(ball),
+            Some(&Collider::Circle { radius: 6. })
+        );
+
+        let position = world.read_storage::<Position>().get(marker).unwrap().0;
+        assert_eq!(position.x, 5.);
+        assert!((0. ..10.).contains(&position.y));
+        assert_eq!(
+            world.read_storage::<Tint>().get(marker),
+            Some(&Tint(Rgba::new(1., 0., 0., 1.)))
+        );
+        assert!(!world.read_storage::<Sprite>().contains(marker));
+        assert!(!world.read_storage::<SaveMarker>().contains(marker));
+    }
+}
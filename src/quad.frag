@@ -1,7 +1,8 @@
 varying highp vec2 uv;
+varying lowp vec4 tint;
 
 uniform sampler2D texture;
 
 void main(void) {
-    gl_FragColor = texture2D(texture, uv);
+    gl_FragColor = texture2D(texture, uv) * tint;
 }
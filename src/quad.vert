@@ -2,10 +2,13 @@ uniform mat4 projection;
 
 attribute vec2 coordinates;
 attribute vec2 texcoord;
+attribute vec4 color;
 
 varying highp vec2 uv;
+varying lowp vec4 tint;
 
 void main(void) {
     gl_Position = projection * vec4(coordinates, 0.0, 1.0);
     uv = texcoord;
+    tint = color;
 }
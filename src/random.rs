@@ -0,0 +1,52 @@
+use rand_pcg::Pcg32;
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_SEED: u64 = 0x853c_49e6_748f_ea9b;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RngStream {
+    Spawn,
+    Physics,
+}
+
+impl RngStream {
+    fn id(self) -> u64 {
+        match self {
+            RngStream::Spawn => 1,
+            RngStream::Physics => 2,
+        }
+    }
+}
+
+/// Seeded random number generators shared by the systems.
+///
+/// Every stream is derived from the same seed, but draws from one stream never
+/// affect the others, so e.g. spawning more bunnies doesn't change how the
+/// existing ones bounce. Snapshots save where every stream is at.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Random {
+    spawn: Pcg32,
+    physics: Pcg32,
+}
+
+impl Random {
+    pub fn from_seed(seed: u64) -> Self {
+        Random {
+            spawn: Pcg32::new(seed, RngStream::Spawn.id()),
+            physics: Pcg32::new(seed, RngStream::Physics.id()),
+        }
+    }
+
+    pub fn stream(&mut self, stream: RngStream) -> &mut Pcg32 {
+        match stream {
+            RngStream::Spawn => &mut self.spawn,
+            RngStream::Physics => &mut self.physics,
+        }
+    }
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Random::from_seed(DEFAULT_SEED)
+    }
+}
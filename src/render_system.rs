@@ -1,17 +1,33 @@
 use crate::assets::{ImageId, Images};
-use crate::components::Position;
-use crate::gl::{Buffer, Gl, Image, Program, Shader, TypedBuffer};
-use shrev::EventChannel;
+use crate::camera::{Camera2D, RenderLayers};
+use crate::components::{Position, Velocity};
+use crate::culling::{self, RenderStats, View};
+use crate::debug_draw::{DebugDraw, LineVertex};
+use crate::draw_order::{self, DrawKey, Layer, ZIndex};
+use crate::font::Font;
+use crate::gl::{Gl, Image, Program, Shader, TypedBuffer};
+use crate::hierarchy::Parent;
+use crate::sprite_batch::{self, BatchItem, SpriteBatch, SpriteQuad, Static, Vertex, VertexData};
+use crate::text::{self, Fonts, Outline, Shadow, Text, TextStyle};
+use crate::tilemap::TileChunk;
+use serde::{Deserialize, Serialize};
 use specs::prelude::*;
-use specs::{SystemData, WriteStorage};
+use specs::storage::ComponentEvent;
+use specs::SystemData;
+use std::ops::Range;
 use std::rc::Rc;
-use vek::column_major::Mat4;
-use vek::{FrustumPlanes, Vec2};
+use vek::{Mat4, Rgba, Vec2};
 use web_sys::WebGlUniformLocation;
 
-static VERT: &'static str = include_str!("./quad.vert");
+static VERT: &str = include_str!("./quad.vert");
 
-static FRAG: &'static str = include_str!("./quad.frag");
+static FRAG: &str = include_str!("./quad.frag");
+
+static SDF_FRAG: &str = include_str!("./sdf.frag");
+
+static LINE_VERT: &str = include_str!("./line.vert");
+
+static LINE_FRAG: &str = include_str!("./line.frag");
 
 #[derive(Clone, Copy, Debug)]
 pub enum DisplayEvent {
@@ -23,7 +39,7 @@ pub struct WindowSize {
     pub size: Vec2<i32>,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Sprite {
     batch_id: u32,
 }
@@ -34,339 +50,970 @@ impl Sprite {
             batch_id: image_id.id,
         }
     }
+
+    pub fn batch_id(&self) -> u32 {
+        self.batch_id
+    }
 }
 
 impl Component for Sprite {
     type Storage = FlaggedStorage<Self, DenseVecStorage<Self>>;
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+/// Color multiplied with the sprite's texture, the alpha makes it see through.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Tint(pub Rgba<f32>);
+
+impl Component for Tint {
+    type Storage = FlaggedStorage<Self, DenseVecStorage<Self>>;
+}
+
+impl Default for Tint {
+    fn default() -> Self {
+        Tint(Rgba::white())
+    }
+}
+
+impl Tint {
+    fn to_bytes(self) -> Rgba<u8> {
+        self.0.map(|c| (c.clamp(0., 1.) * 255.).round() as u8)
+    }
+}
+
+/// The quad drawn for a sprite, relative to its `Position`.
+///
+/// `TransformSystem` rebuilds it from the base size whenever the entity has a
+/// `Rotation`, `Scale` or `Pivot`, and `TransformPropagationSystem` does so
+/// for children, whose `Position` is relative to their parent.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Transform {
+    size: Vec2<f32>,
     up: Vec2<f32>,
     right: Vec2<f32>,
+    offset: Vec2<f32>,
+    parent_offset: Vec2<f32>,
 }
 
 impl Component for Transform {
-    type Storage = DenseVecStorage<Self>;
+    type Storage = FlaggedStorage<Self, DenseVecStorage<Self>>;
 }
 
 impl Transform {
-    pub fn new(up: Vec2<f32>, right: Vec2<f32>) -> Self {
-        Transform { up, right }
+    /// An upright quad of `size` pixels centered on the position.
+    pub fn new(size: Vec2<f32>) -> Self {
+        Transform {
+            size,
+            up: Vec2::new(0., size.y / 2.),
+            right: Vec2::new(size.x / 2., 0.),
+            offset: Vec2::zero(),
+            parent_offset: Vec2::zero(),
+        }
     }
 
     pub fn from_image(image: &Image) -> Self {
-        Transform::new(
-            Vec2::new(0., image.height as f32 / 2.),
-            Vec2::new(image.width as f32 / 2., 0.),
-        )
+        Transform::from_size(Vec2::new(image.width, image.height))
     }
 
-    pub fn rotate(&mut self, radians: f32) {
-        self.up.rotate_z(radians);
-        self.right.rotate_z(radians);
+    pub fn from_size(size: Vec2<u16>) -> Self {
+        Transform::new(size.numcast().unwrap())
     }
-}
 
-#[repr(C)]
-#[derive(Debug)]
-struct Vertex {
-    vertex: Vec2<f32>,
-    texcoord: Vec2<u16>,
-}
+    /// Rebuilds the quad from the base size. The pivot is the point of the
+    /// quad placed on the position, from `(0, 0)` at the bottom left corner
+    /// to `(1, 1)` at the top right one, and the quad is scaled and rotated
+    /// (counter clockwise, in radians) around it.
+    pub fn compose(&mut self, rotation: f32, scale: Vec2<f32>, pivot: Vec2<f32>) {
+        let half = self.size * scale / 2.;
+        let (sin, cos) = rotation.sin_cos();
+        let rotate = |v: Vec2<f32>| Vec2::new(v.x * cos - v.y * sin, v.x * sin + v.y * cos);
+        self.up = rotate(Vec2::new(0., half.y));
+        self.right = rotate(Vec2::new(half.x, 0.));
+        self.offset = rotate((Vec2::broadcast(0.5) - pivot) * self.size * scale);
+    }
 
-#[repr(C)]
-#[derive(Debug)]
-struct VertexData([Vertex; 4]);
+    /// Moves the quad by `offset` away from the entity's `Position`, so
+    /// children are drawn in world space.
+    pub fn set_parent_offset(&mut self, offset: Vec2<f32>) {
+        self.parent_offset = offset;
+    }
 
-impl VertexData {
-    fn new(position: &Vec2<f32>, Transform { up, right }: &Transform) -> Self {
-        let v1 = position - right + up;
-        let v2 = position + right + up;
-        let v3 = position - right - up;
-        let v4 = position + right - up;
-        VertexData([
-            Vertex {
-                vertex: v1,
-                texcoord: Vec2::new(0, 0),
-            },
-            Vertex {
-                vertex: v2,
-                texcoord: Vec2::new(std::u16::MAX, 0),
-            },
-            Vertex {
-                vertex: v3,
-                texcoord: Vec2::new(0, std::u16::MAX),
-            },
-            Vertex {
-                vertex: v4,
-                texcoord: Vec2::new(std::u16::MAX, std::u16::MAX),
-            },
-        ])
+    /// World space position of an entity at the local `position`.
+    pub fn world_position(&self, position: Vec2<f32>) -> Vec2<f32> {
+        position + self.parent_offset
     }
-}
 
-pub struct RenderSystem {
-    gl: Rc<Gl>,
+    /// Center of the quad for an entity at `position`.
+    pub fn center(&self, position: Vec2<f32>) -> Vec2<f32> {
+        self.world_position(position) + self.offset
+    }
 
-    component_reader: ReaderId<ComponentEvent>,
+    /// Half width and half height of the axis aligned box around the quad.
+    pub fn extents(&self) -> Vec2<f32> {
+        Vec2::new(
+            self.right.x.abs() + self.up.x.abs(),
+            self.right.y.abs() + self.up.y.abs(),
+        )
+    }
 
-    batches: Vec<SpriteBatch>,
+    /// Whether `point` is inside the quad of an entity at `position`.
+    pub fn contains(&self, position: Vec2<f32>, point: Vec2<f32>) -> bool {
+        let offset = point - self.center(position);
+        offset.dot(self.right).abs() <= self.right.magnitude_squared()
+            && offset.dot(self.up).abs() <= self.up.magnitude_squared()
+    }
 
-    camera: Mat4<f32>,
-    program: Program,
-    projection_uni: WebGlUniformLocation,
-    texture_uni: WebGlUniformLocation,
-    window_size: Vec2<i32>,
+    /// Corners of the quad of an entity at `position`, top left, top right,
+    /// bottom left and bottom right in texture space.
+    pub fn corners(&self, position: Vec2<f32>) -> [Vec2<f32>; 4] {
+        let center = self.center(position);
+        let (up, right) = (self.up, self.right);
+        [
+            center - right + up,
+            center + right + up,
+            center - right - up,
+            center + right - up,
+        ]
+    }
 }
 
-impl RenderSystem {
-    pub fn new(gl: Rc<Gl>, images: &Images, world: &World, canvas_size: Vec2<i32>) -> Self {
-        let component_reader = WriteStorage::<Sprite>::fetch(&world).register_reader();
+/// Most quads a single draw call can index with 16 bit indices.
+const MAX_QUADS_PER_DRAW: usize = (u16::MAX as usize + 1) / 4;
 
-        let program = {
-            let vert = Shader::compile(gl.clone(), Gl::VERTEX_SHADER, VERT);
-            let frag = Shader::compile(gl.clone(), Gl::FRAGMENT_SHADER, FRAG);
+/// A sprite batch and the vertex buffer it's uploaded to.
+struct GpuBatch {
+    batch: SpriteBatch,
+    buffer: TypedBuffer<VertexData>,
+    capacity: usize,
+}
 
-            Program::compile(gl.clone(), &[vert, frag])
-        };
+impl GpuBatch {
+    fn new(gl: Rc<Gl>, usage: u32) -> Self {
+        GpuBatch {
+            batch: SpriteBatch::default(),
+            buffer: TypedBuffer::new(gl, Gl::ARRAY_BUFFER, usage, 0),
+            capacity: 0,
+        }
+    }
 
-        let projection_uni = program.get_uniform_location("projection").unwrap();
-        let texture_uni = program.get_uniform_location("texture").unwrap();
-        let texcoord_attr = program.get_attrib_location("texcoord");
-        let coordinates_attr = program.get_attrib_location("coordinates");
+    /// Uploads the quads changed since the last upload, returns how many.
+    fn upload(&mut self) -> usize {
+        let dirty = self.batch.take_dirty();
+        let vertex_data = self.batch.vertex_data();
+        if vertex_data.len() > self.capacity {
+            // A new buffer has none of the old contents.
+            self.capacity = vertex_data.len().next_power_of_two();
+            self.buffer.resize(self.capacity as i32);
+            self.buffer.bind().update_sub(vertex_data, 0);
+            return vertex_data.len();
+        }
 
-        let mut batches = Vec::new();
-        for (_, image) in images.images() {
-            let batch =
-                SpriteBatch::new(gl.clone(), image.clone(), coordinates_attr, texcoord_attr);
-            batches.push(batch);
+        let mut buffer = self.buffer.bind();
+        let mut uploaded = 0;
+        for range in dirty {
+            uploaded += range.len();
+            buffer.update_sub(&vertex_data[range.clone()], range.start as i32);
         }
+        uploaded
+    }
+}
 
-        let camera = camera_mat(canvas_size);
+fn sprite_quad(
+    position: &Position,
+    transform: &Transform,
+    tint: Option<&Tint>,
+    layers: Option<&RenderLayers>,
+) -> SpriteQuad {
+    let center = transform.center(position.vector());
+    let extents = transform.extents();
+    SpriteQuad {
+        vertices: VertexData::new(
+            transform.corners(position.vector()),
+            tint.copied().unwrap_or_default().to_bytes(),
+        ),
+        item: BatchItem {
+            layers: layers.copied().unwrap_or_default(),
+            min: center - extents,
+            max: center + extents,
+            material: 0,
+        },
+    }
+}
 
-        RenderSystem {
-            gl,
-            component_reader,
+/// A shader drawing `VertexData` quads, and where its inputs are.
+struct QuadProgram {
+    program: Program,
+    projection_uni: WebGlUniformLocation,
+    texture_uni: WebGlUniformLocation,
+    coordinates_attr: u32,
+    texcoord_attr: u32,
+    color_attr: u32,
+}
 
-            batches,
-            camera,
+impl QuadProgram {
+    fn compile(gl: Rc<Gl>, frag: &str) -> Self {
+        let vert = Shader::compile(gl.clone(), Gl::VERTEX_SHADER, VERT);
+        let frag = Shader::compile(gl.clone(), Gl::FRAGMENT_SHADER, frag);
+        let program = Program::compile(gl, &[vert, frag]);
+
+        QuadProgram {
+            projection_uni: program.get_uniform_location("projection").unwrap(),
+            texture_uni: program.get_uniform_location("texture").unwrap(),
+            coordinates_attr: program.get_attrib_location("coordinates"),
+            texcoord_attr: program.get_attrib_location("texcoord"),
+            color_attr: program.get_attrib_location("color"),
             program,
-            projection_uni,
-            texture_uni,
-            window_size: canvas_size,
         }
     }
 }
 
-fn camera_mat(size: Vec2<i32>) -> Mat4<f32> {
-    Mat4::orthographic_lh_zo(FrustumPlanes {
-        left: 0.0f32,
-        right: size.x as f32,
-        bottom: 0.,
-        top: size.y as f32,
-        near: 1.,
-        far: -1.,
-    })
+/// The untextured shader drawing `DebugDraw` lines.
+struct LineProgram {
+    program: Program,
+    projection_uni: WebGlUniformLocation,
+    coordinates_attr: u32,
+    color_attr: u32,
 }
 
-impl RenderSystem {
-    pub fn resize(&mut self, size: Vec2<i32>) {
-        if self.window_size == size {
-            return;
-        }
-        self.window_size = size;
+impl LineProgram {
+    fn compile(gl: Rc<Gl>) -> Self {
+        let vert = Shader::compile(gl.clone(), Gl::VERTEX_SHADER, LINE_VERT);
+        let frag = Shader::compile(gl.clone(), Gl::FRAGMENT_SHADER, LINE_FRAG);
+        let program = Program::compile(gl, &[vert, frag]);
 
-        self.gl.viewport(0, 0, size.x, size.y);
-        self.camera = camera_mat(size);
+        LineProgram {
+            projection_uni: program.get_uniform_location("projection").unwrap(),
+            coordinates_attr: program.get_attrib_location("coordinates"),
+            color_attr: program.get_attrib_location("color"),
+            program,
+        }
     }
 }
 
-impl<'a> System<'a> for RenderSystem {
-    type SystemData = (
-        Entities<'a>,
-        ReadStorage<'a, Sprite>,
-        ReadStorage<'a, Transform>,
-        ReadStorage<'a, Position>,
-        Read<'a, WindowSize>,
-    );
+/// The extra inputs of `sdf.frag`.
+struct SdfUniforms {
+    px_range: WebGlUniformLocation,
+    outline_width: WebGlUniformLocation,
+    outline_color: WebGlUniformLocation,
+    shadow_offset: WebGlUniformLocation,
+    shadow_softness: WebGlUniformLocation,
+    shadow_color: WebGlUniformLocation,
+}
 
-    fn run(
-        &mut self,
-        (entities, mut sprites, transforms, positions, mut window_size): Self::SystemData,
-    ) {
-        {
-            let events = sprites.channel().read(&mut self.component_reader);
-            for event in events {
-                match event {
-                    ComponentEvent::Inserted(id) => {
-                        let entity = entities.entity(*id);
-                        let sprite = sprites.get(entity).unwrap();
-                        let mut batch = &mut self.batches[sprite.batch_id as usize];
-                        batch.add(*id);
-                    }
-                    ComponentEvent::Removed(id) => {
-                        let entity = entities.entity(*id);
-                        let sprite = sprites.get(entity).unwrap();
-                        let mut batch = &mut self.batches[sprite.batch_id as usize];
-                        batch.remove(*id);
-                    }
-                    _ => {}
-                }
-            }
+impl SdfUniforms {
+    fn new(program: &Program) -> Self {
+        let location = |name| program.get_uniform_location(name).unwrap();
+        SdfUniforms {
+            px_range: location("px_range"),
+            outline_width: location("outline_width"),
+            outline_color: location("outline_color"),
+            shadow_offset: location("shadow_offset"),
+            shadow_softness: location("shadow_softness"),
+            shadow_color: location("shadow_color"),
         }
+    }
+}
 
-        self.resize(window_size.size);
+/// How glyphs of a distance field font are drawn. Each one in use this frame
+/// is a material, numbered from 1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct SdfMaterial {
+    distance_range: f32,
+    scale: f32,
+    atlas_size: Vec2<f32>,
+    outline: Option<Outline>,
+    shadow: Option<Shadow>,
+}
 
-        self.gl.disable(Gl::DEPTH_TEST);
-        self.gl.enable(Gl::BLEND);
-        self.gl.blend_func(Gl::SRC_ALPHA, Gl::ONE_MINUS_SRC_ALPHA);
-        self.gl.clear(Gl::COLOR_BUFFER_BIT);
+impl SdfMaterial {
+    fn new(font: &Font, style: &TextStyle) -> Option<Self> {
+        Some(SdfMaterial {
+            distance_range: font.distance_range?,
+            scale: style.scale,
+            atlas_size: font.atlas_size,
+            outline: style.outline,
+            shadow: style.shadow,
+        })
+    }
+}
 
-        self.program.use_program();
+/// Events of the components a static sprite's quad is built from.
+struct ContentReaders {
+    positions: ReaderId<ComponentEvent>,
+    transforms: ReaderId<ComponentEvent>,
+    tints: ReaderId<ComponentEvent>,
+    render_layers: ReaderId<ComponentEvent>,
+}
 
-        self.gl.uniform_matrix4fv_with_f32_array(
-            Some(&self.projection_uni),
-            false,
-            self.camera.as_col_slice(),
-        );
+/// Events of the components a sprite's place in the draw order comes from.
+struct OrderReaders {
+    sprites: ReaderId<ComponentEvent>,
+    layers: ReaderId<ComponentEvent>,
+    z_indices: ReaderId<ComponentEvent>,
+    chunks: ReaderId<ComponentEvent>,
+}
 
-        for batch in self.batches.iter_mut() {
-            batch.render(&self.texture_uni, &transforms, &positions);
-        }
+fn event_id(event: &ComponentEvent) -> u32 {
+    match event {
+        ComponentEvent::Inserted(id)
+        | ComponentEvent::Modified(id)
+        | ComponentEvent::Removed(id) => *id,
     }
 }
 
-struct SpriteBatch {
+/// Draws every sprite in `draw_order`, switching textures between runs of
+/// sprites with the same image, once for every `Camera2D` in their order.
+/// Without a camera the canvas is drawn as plain pixels.
+///
+/// Sprites without a `Velocity` or a `Parent`, and those marked `Static`, go
+/// into a static batch that's only uploaded again where they changed, see
+/// `sprite_batch`. So do the tiles of every `TileChunk`. The rest are culled
+/// and rebuilt every frame, along with the glyphs of every `Text`.
+/// `RenderStats` has the numbers, counting glyphs and tiles as sprites.
+///
+/// Glyphs of distance field fonts are drawn with `sdf.frag` instead, in runs
+/// split by their material.
+///
+/// `DebugDraw` lines go on top of each camera's sprites, and are cleared once
+/// every camera has drawn them.
+pub struct RenderSystem {
     gl: Rc<Gl>,
-    image: Image,
-    len: i32,
-    new_len: i32,
 
-    coordinates_attr: u32,
-    texcoord_attr: u32,
+    textures: Vec<Image>,
 
-    entities: BitSet,
+    static_batch: GpuBatch,
+    dynamic_batch: GpuBatch,
+    static_entities: BitSet,
+    next_static_entities: BitSet,
+    moved: BitSet,
+    content_readers: ContentReaders,
+    order_readers: OrderReaders,
 
-    vertex_data_buffer: TypedBuffer<VertexData>,
+    cameras: Vec<(i32, u32, Camera2D)>,
+    views: Vec<View>,
+    stats: RenderStats,
     index_buffer: TypedBuffer<[u16; 6]>,
-
-    vertex_data: Vec<VertexData>,
-    index_data: Vec<[u16; 6]>,
+    index_capacity: usize,
+
+    sprite_program: QuadProgram,
+    sdf_program: QuadProgram,
+    sdf_uniforms: SdfUniforms,
+    sdf_materials: Vec<SdfMaterial>,
+
+    line_program: LineProgram,
+    line_buffer: TypedBuffer<LineVertex>,
+    /// Vertices in `line_buffer` this frame, and who draws them.
+    line_vertices: usize,
+    line_layers: RenderLayers,
 }
 
-impl SpriteBatch {
-    pub fn new(gl: Rc<Gl>, image: Image, coordinates_attr: u32, texcoord_attr: u32) -> Self {
-        SpriteBatch {
-            gl: gl.clone(),
-            image,
-            len: -1,
-            new_len: 0,
-
-            coordinates_attr,
-            texcoord_attr,
-
-            entities: BitSet::new(),
+impl RenderSystem {
+    pub fn new(gl: Rc<Gl>, images: &Images, world: &mut World) -> Self {
+        let sprite_program = QuadProgram::compile(gl.clone(), FRAG);
+        let sdf_program = QuadProgram::compile(gl.clone(), SDF_FRAG);
+        let sdf_uniforms = SdfUniforms::new(&sdf_program.program);
+        let line_program = LineProgram::compile(gl.clone());
+
+        let textures = images
+            .images()
+            .into_iter()
+            .map(|(_, image)| image.clone())
+            .collect();
+
+        let content_readers = ContentReaders {
+            positions: world.write_storage::<Position>().register_reader(),
+            transforms: world.write_storage::<Transform>().register_reader(),
+            tints: world.write_storage::<Tint>().register_reader(),
+            render_layers: world.write_storage::<RenderLayers>().register_reader(),
+        };
+        let order_readers = OrderReaders {
+            sprites: world.write_storage::<Sprite>().register_reader(),
+            layers: world.write_storage::<Layer>().register_reader(),
+            z_indices: world.write_storage::<ZIndex>().register_reader(),
+            chunks: world.write_storage::<TileChunk>().register_reader(),
+        };
 
-            vertex_data_buffer: TypedBuffer::new(gl.clone(), Gl::ARRAY_BUFFER, Gl::DYNAMIC_DRAW, 0),
+        RenderSystem {
+            gl: gl.clone(),
+            textures,
+
+            static_batch: GpuBatch::new(gl.clone(), Gl::STATIC_DRAW),
+            dynamic_batch: GpuBatch::new(gl.clone(), Gl::DYNAMIC_DRAW),
+            static_entities: BitSet::new(),
+            next_static_entities: BitSet::new(),
+            moved: BitSet::new(),
+            content_readers,
+            order_readers,
+
+            cameras: Vec::new(),
+            views: Vec::new(),
+            stats: RenderStats::default(),
             index_buffer: TypedBuffer::new(
                 gl.clone(),
                 Gl::ELEMENT_ARRAY_BUFFER,
-                Gl::DYNAMIC_DRAW,
+                Gl::STATIC_DRAW,
                 0,
             ),
-            vertex_data: Vec::new(),
-            index_data: Vec::new(),
+            index_capacity: 0,
+
+            sprite_program,
+            sdf_program,
+            sdf_uniforms,
+            sdf_materials: Vec::new(),
+
+            line_program,
+            line_buffer: TypedBuffer::new(gl, Gl::ARRAY_BUFFER, Gl::STREAM_DRAW, 0),
+            line_vertices: 0,
+            line_layers: RenderLayers::DEFAULT,
         }
     }
+}
 
-    pub fn render(
-        &mut self,
-        texture_uni: &WebGlUniformLocation,
-        transforms: &ReadStorage<Transform>,
-        positions: &ReadStorage<Position>,
-    ) {
-        self.resize_buffers();
-
-        let mut vertexdata = self.vertex_data_buffer.bind();
-        self.vertex_data.clear();
-        self.vertex_data.reserve(self.len as usize);
+impl RenderSystem {
+    /// Grows the index buffer to fit the larger batch. It's shared by all
+    /// draw calls, so it never needs more than one call's worth.
+    fn reserve_indices(&mut self) {
+        let len = self
+            .static_batch
+            .capacity
+            .max(self.dynamic_batch.capacity)
+            .min(MAX_QUADS_PER_DRAW);
+        if len <= self.index_capacity {
+            return;
+        }
+        self.index_capacity = len;
+
+        let indices: Vec<[u16; 6]> = (0..len)
+            .map(|i| {
+                let n = (i * 4) as u16;
+                [n, n + 1, n + 2, n + 2, n + 3, n + 1]
+            })
+            .collect();
+        self.index_buffer.resize(len as i32);
+        self.index_buffer.bind().update_sub(&indices, 0);
+    }
 
-        let vertices = (&self.entities, transforms, positions)
-            .join()
-            .map(|(_, transform, position)| VertexData::new(&position.vector(), transform));
+    /// Draws the sprites on `camera`'s layers into its viewport.
+    fn draw_camera(&mut self, camera: &Camera2D, canvas_size: Vec2<i32>) {
+        let (min, size) = camera.viewport.pixels(canvas_size);
+        self.gl.viewport(min.x, min.y, size.x, size.y);
+
+        if let Some(color) = camera.clear_color {
+            // Clearing ignores the viewport, the scissor keeps it inside.
+            self.gl.enable(Gl::SCISSOR_TEST);
+            self.gl.scissor(min.x, min.y, size.x, size.y);
+            self.gl.clear_color(color.r, color.g, color.b, color.a);
+            self.gl.clear(Gl::COLOR_BUFFER_BIT);
+            self.gl.disable(Gl::SCISSOR_TEST);
+        }
 
-        self.vertex_data.extend(vertices);
+        let projection = camera.view_projection(canvas_size);
+        for program in [&self.sprite_program, &self.sdf_program].iter() {
+            program.program.use_program();
+            self.gl.uniform_matrix4fv_with_f32_array(
+                Some(&program.projection_uni),
+                false,
+                projection.as_col_slice(),
+            );
+        }
 
-        vertexdata.update_sub(&self.vertex_data, 0);
+        let view = View::new(camera, canvas_size);
+        let lists = [
+            self.static_batch.batch.items(),
+            self.dynamic_batch.batch.items(),
+        ];
+        let runs: Vec<_> = draw_order::merged_runs(lists, |item| item.is_seen_by(&view))
+            .flat_map(|(list, image, quads)| {
+                sprite_batch::material_runs(lists[list], quads)
+                    .map(move |(material, quads)| (list, image, material, quads))
+            })
+            .collect();
+        for (list, image, material, quads) in runs {
+            self.draw(list, image, material, quads, camera.zoom);
+        }
 
-        self.draw(texture_uni);
+        if self.line_vertices > 0 && camera.layers.intersects(self.line_layers) {
+            self.draw_lines(&projection);
+        }
     }
 
-    fn resize_buffers(&mut self) {
-        if self.len >= self.new_len {
-            return;
+    fn draw_lines(&mut self, projection: &Mat4<f32>) {
+        // The quad programs leave their attributes pointing into the sprite
+        // batches, which would be out of range here.
+        for program in [&self.sprite_program, &self.sdf_program].iter() {
+            let attrs = [
+                program.coordinates_attr,
+                program.texcoord_attr,
+                program.color_attr,
+            ];
+            for attr in attrs.iter() {
+                self.gl.disable_vertex_attrib_array(*attr);
+            }
         }
-        self.len = self.new_len;
 
-        self.vertex_data_buffer.resize(self.len);
-        self.index_buffer.resize(self.len);
+        let program = &self.line_program;
+        program.program.use_program();
+        self.gl.uniform_matrix4fv_with_f32_array(
+            Some(&program.projection_uni),
+            false,
+            projection.as_col_slice(),
+        );
+        self.line_buffer.bind();
+        let stride = std::mem::size_of::<LineVertex>() as i32;
+        self.gl.vertex_attrib_pointer_with_i32(
+            program.coordinates_attr,
+            2,
+            Gl::FLOAT,
+            false,
+            stride,
+            0,
+        );
+        self.gl.enable_vertex_attrib_array(program.coordinates_attr);
+        self.gl.vertex_attrib_pointer_with_i32(
+            program.color_attr,
+            4,
+            Gl::UNSIGNED_BYTE,
+            true,
+            stride,
+            std::mem::size_of::<Vec2<f32>>() as i32,
+        );
+        self.gl.enable_vertex_attrib_array(program.color_attr);
+
+        self.gl.draw_arrays(Gl::LINES, 0, self.line_vertices as i32);
+        self.stats.draw_calls += 1;
+    }
 
-        self.index_data.clear();
-        self.index_data.reserve(self.len as usize);
+    /// Sets up `sdf.frag` for a material, as drawn at `zoom`.
+    fn use_sdf_material(&self, material: &SdfMaterial, zoom: f32) {
+        let uniforms = &self.sdf_uniforms;
+        let range = material.distance_range;
+        let px_range = range * material.scale * zoom;
+        self.gl.uniform1f(Some(&uniforms.px_range), px_range);
 
-        let iter = (0..self.len).map(|i| {
-            let n = (i * 4) as u16;
-            [n, n + 1, n + 2, n + 2, n + 3, n + 1]
+        let outline = material.outline.unwrap_or(Outline {
+            width: 0.,
+            color: Rgba::zero(),
         });
-        self.index_data.extend(iter);
+        let color = outline.color;
+        self.gl
+            .uniform1f(Some(&uniforms.outline_width), outline.width / range);
+        self.gl.uniform4f(
+            Some(&uniforms.outline_color),
+            color.r,
+            color.g,
+            color.b,
+            color.a,
+        );
 
-        let mut indices = self.index_buffer.bind();
-        indices.update_sub(&self.index_data, 0);
+        let shadow = material.shadow.unwrap_or(Shadow {
+            offset: Vec2::zero(),
+            softness: 0.,
+            color: Rgba::zero(),
+        });
+        // The atlas is upside down compared to the world.
+        let offset = Vec2::new(shadow.offset.x, -shadow.offset.y) / material.atlas_size;
+        let color = shadow.color;
+        self.gl
+            .uniform2f(Some(&uniforms.shadow_offset), offset.x, offset.y);
+        self.gl
+            .uniform1f(Some(&uniforms.shadow_softness), shadow.softness / range);
+        self.gl.uniform4f(
+            Some(&uniforms.shadow_color),
+            color.r,
+            color.g,
+            color.b,
+            color.a,
+        );
     }
 
-    fn add(&mut self, entity: u32) {
-        if !self.entities.add(entity) {
-            self.new_len += 1;
+    /// Draws `quads` of the static (0) or dynamic (1) batch.
+    fn draw(&mut self, list: usize, image: u32, material: u32, quads: Range<usize>, zoom: f32) {
+        let texture = match self.textures.get(image as usize) {
+            Some(texture) => texture,
+            None => return,
+        };
+        let program = match material {
+            0 => &self.sprite_program,
+            _ => &self.sdf_program,
+        };
+        program.program.use_program();
+        if let Some(index) = material.checked_sub(1) {
+            self.use_sdf_material(&self.sdf_materials[index as usize], zoom);
+        }
+        self.gl.active_texture(Gl::TEXTURE0);
+        self.gl.bind_texture(Gl::TEXTURE_2D, Some(texture));
+        self.gl.uniform1i(Some(&program.texture_uni), 0);
+
+        let batch = match list {
+            0 => &mut self.static_batch,
+            _ => &mut self.dynamic_batch,
+        };
+        batch.buffer.bind();
+        self.index_buffer.bind();
+
+        let stride = std::mem::size_of::<Vertex>() as i32;
+        let mut start = quads.start;
+        while start < quads.end {
+            let len = (quads.end - start).min(MAX_QUADS_PER_DRAW);
+            // Point the attributes at the first quad so the shared indices
+            // start from zero.
+            let base = (start * std::mem::size_of::<VertexData>()) as i32;
+
+            self.gl.vertex_attrib_pointer_with_i32(
+                program.coordinates_attr,
+                2,
+                Gl::FLOAT,
+                false,
+                stride,
+                base,
+            );
+            self.gl.enable_vertex_attrib_array(program.coordinates_attr);
+
+            self.gl.vertex_attrib_pointer_with_i32(
+                program.texcoord_attr,
+                2,
+                Gl::UNSIGNED_SHORT,
+                true,
+                stride,
+                base + std::mem::size_of::<Vec2<f32>>() as i32,
+            );
+            self.gl.enable_vertex_attrib_array(program.texcoord_attr);
+
+            self.gl.vertex_attrib_pointer_with_i32(
+                program.color_attr,
+                4,
+                Gl::UNSIGNED_BYTE,
+                true,
+                stride,
+                base + (std::mem::size_of::<Vec2<f32>>() + std::mem::size_of::<Vec2<u16>>()) as i32,
+            );
+            self.gl.enable_vertex_attrib_array(program.color_attr);
+
+            self.gl
+                .draw_elements_with_i32(Gl::TRIANGLES, len as i32 * 6, Gl::UNSIGNED_SHORT, 0);
+            self.stats.draw_calls += 1;
+            start += len;
         }
     }
+}
+
+#[derive(SystemData)]
+pub struct SpriteData<'a> {
+    entities: Entities<'a>,
+    sprites: ReadStorage<'a, Sprite>,
+    transforms: ReadStorage<'a, Transform>,
+    positions: ReadStorage<'a, Position>,
+    tints: ReadStorage<'a, Tint>,
+    layers: ReadStorage<'a, Layer>,
+    z_indices: ReadStorage<'a, ZIndex>,
+    render_layers: ReadStorage<'a, RenderLayers>,
+    velocities: ReadStorage<'a, Velocity>,
+    parents: ReadStorage<'a, Parent>,
+    statics: ReadStorage<'a, Static>,
+    texts: ReadStorage<'a, Text>,
+    chunks: ReadStorage<'a, TileChunk>,
+    fonts: Read<'a, Fonts>,
+}
+
+impl<'a> SpriteData<'a> {
+    /// The key and quad of a sprite entity, if it still is one.
+    fn sprite(&self, entity: Entity) -> Option<(DrawKey, SpriteQuad)> {
+        let key = draw_order::draw_key(entity, &self.sprites, &self.layers, &self.z_indices)?;
+        let quad = sprite_quad(
+            self.positions.get(entity)?,
+            self.transforms.get(entity)?,
+            self.tints.get(entity),
+            self.render_layers.get(entity),
+        );
+        Some((key, quad))
+    }
+
+    /// Keys and quads of a text entity's glyphs. They share the entity's key
+    /// apart from the image, which is the glyph's atlas page.
+    fn glyphs(
+        &self,
+        entity: Entity,
+        text: &Text,
+        position: &Position,
+        material: u32,
+    ) -> Vec<(DrawKey, SpriteQuad)> {
+        let atlas = match self.fonts.get(text.font) {
+            Some(atlas) => atlas,
+            None => return Vec::new(),
+        };
+        let layer = self.layers.get(entity).copied().unwrap_or_default();
+        let z = self.z_indices.get(entity).copied().unwrap_or_default();
+        let color = self
+            .tints
+            .get(entity)
+            .copied()
+            .unwrap_or_default()
+            .to_bytes();
+        let layers = self.render_layers.get(entity).copied().unwrap_or_default();
+        text::layout(&atlas.font, &text.text, &text.style)
+            .into_iter()
+            .filter_map(|glyph| {
+                let key = DrawKey {
+                    layer: layer.0,
+                    z: z.0,
+                    image: atlas.pages.get(glyph.page as usize)?.id,
+                    entity: entity.id(),
+                };
+                let quad = SpriteQuad {
+                    vertices: VertexData::textured(
+                        glyph.corners(position.vector()),
+                        glyph.uv_min,
+                        glyph.uv_max,
+                        color,
+                    ),
+                    item: BatchItem {
+                        layers,
+                        min: position.vector() + glyph.min,
+                        max: position.vector() + glyph.max,
+                        material,
+                    },
+                };
+                Some((key, quad))
+            })
+            .collect()
+    }
+
+    /// Keys and quads of a `TileChunk`'s tiles. They all get the box around
+    /// the chunk, so it's culled as a whole.
+    fn tiles(&self, entity: Entity) -> Vec<(DrawKey, SpriteQuad)> {
+        let (chunk, position) = match (self.chunks.get(entity), self.positions.get(entity)) {
+            (Some(chunk), Some(position)) => (chunk, position.vector()),
+            _ => return Vec::new(),
+        };
+        let key = DrawKey {
+            layer: self.layers.get(entity).copied().unwrap_or_default().0,
+            z: self.z_indices.get(entity).copied().unwrap_or_default().0,
+            image: chunk.image.id,
+            entity: entity.id(),
+        };
+        let color = self
+            .tints
+            .get(entity)
+            .copied()
+            .unwrap_or_default()
+            .to_bytes();
+        let item = BatchItem {
+            layers: self.render_layers.get(entity).copied().unwrap_or_default(),
+            min: position + chunk.min,
+            max: position + chunk.max,
+            material: 0,
+        };
+        chunk
+            .quads
+            .iter()
+            .map(|quad| {
+                let vertices =
+                    VertexData::textured(quad.corners(position), quad.uv_min, quad.uv_max, color);
+                (key, SpriteQuad { vertices, item })
+            })
+            .collect()
+    }
+}
+
+impl RenderSystem {
+    /// Brings the static batch up to date with this frame's static sprites.
+    fn update_static(&mut self, data: &SpriteData) {
+        self.next_static_entities.clear();
+        for (entity, _, _, _, velocity, parent, marker) in (
+            &data.entities,
+            &data.sprites,
+            &data.transforms,
+            &data.positions,
+            data.velocities.maybe(),
+            data.parents.maybe(),
+            data.statics.maybe(),
+        )
+            .join()
+        {
+            if parent.is_none() && (velocity.is_none() || marker.is_some()) {
+                self.next_static_entities.add(entity.id());
+            }
+        }
+        for (entity, _, _) in (&data.entities, &data.chunks, &data.positions).join() {
+            self.next_static_entities.add(entity.id());
+        }
+        let mut rebuild = self.next_static_entities != self.static_entities;
+        std::mem::swap(&mut self.static_entities, &mut self.next_static_entities);
+
+        // Every reader is drained each frame, even when rebuilding anyway.
+        let statics = &self.static_entities;
+        let readers = &mut self.order_readers;
+        let order_events = data
+            .sprites
+            .channel()
+            .read(&mut readers.sprites)
+            .chain(data.layers.channel().read(&mut readers.layers))
+            .chain(data.z_indices.channel().read(&mut readers.z_indices))
+            .chain(data.chunks.channel().read(&mut readers.chunks));
+        for event in order_events {
+            rebuild |= statics.contains(event_id(event));
+        }
+        let readers = &mut self.content_readers;
+        let content_events = data
+            .positions
+            .channel()
+            .read(&mut readers.positions)
+            .chain(data.transforms.channel().read(&mut readers.transforms))
+            .chain(data.tints.channel().read(&mut readers.tints))
+            .chain(
+                data.render_layers
+                    .channel()
+                    .read(&mut readers.render_layers),
+            );
+        for event in content_events {
+            let id = event_id(event);
+            if statics.contains(id) {
+                self.moved.add(id);
+            }
+        }
 
-    fn remove(&mut self, entity: u32) {
-        if self.entities.remove(entity) {
-            self.new_len -= 1;
+        let batch = &mut self.static_batch.batch;
+        if !rebuild {
+            for id in (&self.moved).join() {
+                let entity = data.entities.entity(id);
+                // Chunks aren't sprites, so changing one rebuilds the batch.
+                match data.sprite(entity) {
+                    Some((key, quad)) if batch.update(key, quad) => {}
+                    _ => {
+                        rebuild = true;
+                        break;
+                    }
+                }
+            }
+        }
+        self.moved.clear();
+        if rebuild {
+            batch.rebuild(
+                (&data.entities, &self.static_entities)
+                    .join()
+                    .flat_map(|(entity, _)| {
+                        data.sprite(entity).into_iter().chain(data.tiles(entity))
+                    }),
+            );
         }
     }
 
-    fn draw(&mut self, texture_uni: &WebGlUniformLocation) {
-        self.gl.active_texture(Gl::TEXTURE0);
-        self.gl.bind_texture(Gl::TEXTURE_2D, Some(&self.image));
-        self.gl.uniform1i(Some(texture_uni.as_ref()), 0);
+    /// Rebuilds the dynamic batch from the sprites and glyphs some camera can
+    /// see.
+    fn update_dynamic(&mut self, data: &SpriteData) {
+        let views = &self.views;
+        let stats = &mut self.stats;
+        let materials = &mut self.sdf_materials;
+        materials.clear();
+        let glyphs = (&data.entities, &data.texts, &data.positions)
+            .join()
+            .flat_map(|(entity, text, position)| {
+                let material = data
+                    .fonts
+                    .get(text.font)
+                    .and_then(|atlas| SdfMaterial::new(&atlas.font, &text.style))
+                    .map_or(0, |material| {
+                        let index = materials.iter().position(|m| *m == material);
+                        let index = index.unwrap_or_else(|| {
+                            materials.push(material);
+                            materials.len() - 1
+                        });
+                        index as u32 + 1
+                    });
+                data.glyphs(entity, text, position, material)
+            });
+        let sprites = (&data.entities, &data.sprites, !&self.static_entities)
+            .join()
+            .filter_map(|(entity, _, _)| data.sprite(entity))
+            .chain(glyphs)
+            .filter(|(_, quad)| {
+                stats.sprites += 1;
+                let item = &quad.item;
+                let visible = culling::is_visible(views, item.layers, item.min, item.max);
+                if visible {
+                    stats.drawn += 1;
+                } else {
+                    stats.culled += 1;
+                }
+                visible
+            });
+        self.dynamic_batch.batch.rebuild(sprites);
+    }
+}
 
-        self.vertex_data_buffer.bind();
+impl<'a> System<'a> for RenderSystem {
+    type SystemData = (
+        SpriteData<'a>,
+        ReadStorage<'a, Camera2D>,
+        Read<'a, WindowSize>,
+        Write<'a, RenderStats>,
+        Write<'a, DebugDraw>,
+    );
 
-        self.gl.vertex_attrib_pointer_with_i32(
-            self.coordinates_attr,
-            2,
-            Gl::FLOAT,
-            false,
-            std::mem::size_of::<Vertex>() as i32,
-            0,
+    fn run(
+        &mut self,
+        (data, cameras, window_size, mut render_stats, mut debug_draw): Self::SystemData,
+    ) {
+        self.cameras.clear();
+        self.cameras.extend(
+            (&data.entities, &cameras)
+                .join()
+                .map(|(entity, camera)| (camera.order, entity.id(), *camera)),
         );
-        self.gl.enable_vertex_attrib_array(self.coordinates_attr);
-
-        self.gl.vertex_attrib_pointer_with_i32(
-            self.texcoord_attr,
-            2,
-            Gl::UNSIGNED_SHORT,
-            true,
-            std::mem::size_of::<Vertex>() as i32,
-            std::mem::size_of::<Vec2<f32>>() as i32,
+        if self.cameras.is_empty() {
+            let camera = Camera2D::for_canvas(window_size.size);
+            self.cameras.push((0, 0, camera));
+        }
+        self.cameras
+            .sort_unstable_by_key(|(order, entity, _)| (*order, *entity));
+        let canvas_size = window_size.size;
+        self.views.clear();
+        self.views.extend(
+            self.cameras
+                .iter()
+                .map(|(_, _, camera)| View::new(camera, canvas_size)),
         );
-        self.gl.enable_vertex_attrib_array(self.texcoord_attr);
 
-        self.index_buffer.bind();
+        self.stats = RenderStats::default();
+        self.update_static(&data);
+        self.update_dynamic(&data);
+
+        // Static sprites stay in the batch when off screen, they're just
+        // skipped when drawing.
+        let views = &self.views;
+        let static_items = self.static_batch.batch.items();
+        let static_drawn = static_items
+            .iter()
+            .filter(|(_, item)| culling::is_visible(views, item.layers, item.min, item.max))
+            .count() as u32;
+        self.stats.static_sprites = static_items.len() as u32;
+        self.stats.sprites += self.stats.static_sprites;
+        self.stats.drawn += static_drawn;
+        self.stats.culled += self.stats.static_sprites - static_drawn;
+
+        let uploaded = self.static_batch.upload() + self.dynamic_batch.upload();
+        self.stats.uploaded = uploaded as u32;
+        self.reserve_indices();
+
+        let lines = debug_draw.vertices();
+        self.line_vertices = lines.len();
+        self.line_layers = debug_draw.layers;
+        if !lines.is_empty() {
+            self.line_buffer.bind().update(lines);
+        }
 
-        self.gl
-            .draw_elements_with_i32(Gl::TRIANGLES, self.len * 6, Gl::UNSIGNED_SHORT, 0);
+        self.gl.disable(Gl::DEPTH_TEST);
+        self.gl.enable(Gl::BLEND);
+        self.gl.blend_func(Gl::SRC_ALPHA, Gl::ONE_MINUS_SRC_ALPHA);
+        // Cameras without a clear color, and the canvas outside every
+        // viewport, show this instead of the last frame.
+        self.gl.clear_color(0., 0., 0., 0.);
+        self.gl.clear(Gl::COLOR_BUFFER_BIT);
+
+        for i in 0..self.cameras.len() {
+            let (_, _, camera) = self.cameras[i];
+            self.draw_camera(&camera, canvas_size);
+        }
+        debug_draw.clear();
+        *render_stats = self.stats;
     }
 }
@@ -0,0 +1,39 @@
+precision mediump float;
+
+varying highp vec2 uv;
+varying lowp vec4 tint;
+
+uniform sampler2D texture;
+// The atlas' distance range in canvas pixels, as drawn.
+uniform float px_range;
+// Distances are in fractions of the distance range, positive inside.
+uniform float outline_width;
+uniform lowp vec4 outline_color;
+uniform highp vec2 shadow_offset;
+uniform float shadow_softness;
+uniform lowp vec4 shadow_color;
+
+// Single channel fields are grey, so the median works for them too.
+float distance_at(highp vec2 at) {
+    vec3 field = texture2D(texture, at).rgb;
+    return max(min(field.r, field.g), min(max(field.r, field.g), field.b)) - 0.5;
+}
+
+float coverage(float edge, float blur) {
+    return clamp(edge * px_range / (1.0 + blur * px_range) + 0.5, 0.0, 1.0);
+}
+
+vec4 over(vec4 top, vec4 bottom) {
+    float alpha = top.a + bottom.a * (1.0 - top.a);
+    vec3 color = top.rgb * top.a + bottom.rgb * bottom.a * (1.0 - top.a);
+    return vec4(color / max(alpha, 0.0001), alpha);
+}
+
+void main(void) {
+    float edge = distance_at(uv);
+    vec4 fill = vec4(tint.rgb, tint.a * coverage(edge, 0.0));
+    vec4 outline = vec4(outline_color.rgb, outline_color.a * coverage(edge + outline_width, 0.0));
+    float shadow_distance = distance_at(uv - shadow_offset) + outline_width;
+    vec4 shadow = vec4(shadow_color.rgb, shadow_color.a * coverage(shadow_distance, shadow_softness));
+    gl_FragColor = over(fill, over(outline, shadow));
+}
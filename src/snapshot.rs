@@ -0,0 +1,363 @@
+//! Saving the bunnies to JSON or bincode and loading them back, e.g. to
+//! profile the same stress scene over and over.
+//!
+//! Only entities with a `SaveMarker` are saved, which bunnies get when
+//! they're spawned. The level, the player, cameras and the HUD come from the
+//! assets and are left alone, so the player stays where it is when a
+//! snapshot loads. The random streams are saved, so bunnies spawned after
+//! loading are the same as after saving. Sprites are saved by the name of
+//! their image, since `ImageId`s depend on the order images were loaded in.
+
+use crate::assets::{ImageId, ImageNames};
+use crate::collision::Collider;
+use crate::components::{AngularVelocity, Position, Rotation, Velocity};
+use crate::input_system::BunnyCount;
+use crate::physics::PhysicsConfig;
+use crate::player_system::CollectedCount;
+use crate::random::Random;
+use crate::render_system::{Sprite, Tint, Transform};
+use crate::tint_system::ColorBySpeed;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use specs::error::NoError;
+use specs::prelude::*;
+use specs::saveload::{
+    DeserializeComponents, Marker, MarkerAllocator, SerializeComponents, SimpleMarker,
+    SimpleMarkerAllocator,
+};
+
+/// Tells `SaveMarker` apart from other `SimpleMarker`s.
+pub struct Saved;
+
+pub type SaveMarker = SimpleMarker<Saved>;
+
+/// What's saved of each entity, besides the sprite.
+type Components = (
+    Option<Position>,
+    Option<Velocity>,
+    Option<Rotation>,
+    Option<AngularVelocity>,
+    Option<Transform>,
+    Option<Collider>,
+    Option<Tint>,
+    Option<ColorBySpeed>,
+);
+
+#[derive(Serialize, Deserialize)]
+struct SavedEntity {
+    marker: SaveMarker,
+    components: Components,
+    /// The name of the sprite's image.
+    image: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    pub bunny_count: u32,
+    pub collected_count: u32,
+    pub physics: PhysicsConfig,
+    random: Random,
+    entities: Vec<SavedEntity>,
+}
+
+impl Snapshot {
+    pub fn save(world: &World) -> Result<Self> {
+        let (
+            entities,
+            markers,
+            positions,
+            velocities,
+            rotations,
+            angular_velocities,
+            transforms,
+            colliders,
+            tints,
+            colors,
+            sprites,
+            names,
+        ) = world.system_data::<(
+            Entities,
+            ReadStorage<SaveMarker>,
+            ReadStorage<Position>,
+            ReadStorage<Velocity>,
+            ReadStorage<Rotation>,
+            ReadStorage<AngularVelocity>,
+            ReadStorage<Transform>,
+            ReadStorage<Collider>,
+            ReadStorage<Tint>,
+            ReadStorage<ColorBySpeed>,
+            ReadStorage<Sprite>,
+            Read<ImageNames>,
+        )>();
+        let storages = (
+            &positions,
+            &velocities,
+            &rotations,
+            &angular_velocities,
+            &transforms,
+            &colliders,
+            &tints,
+            &colors,
+        );
+
+        // `SerializeComponents::serialize` doesn't say how many entities it
+        // writes, which bincode needs to know up front.
+        let mut saved = Vec::new();
+        for (entity, marker) in (&entities, &markers).join() {
+            let components = SerializeComponents::<NoError, SaveMarker>::serialize_entity(
+                &storages,
+                entity,
+                |entity| markers.get(entity).copied(),
+            )?;
+            let image = match sprites.get(entity) {
+                Some(sprite) => {
+                    let id = ImageId {
+                        id: sprite.batch_id(),
+                    };
+                    let name = names
+                        .name(id)
+                        .ok_or_else(|| anyhow!("image {} has no name", id.id))?;
+                    Some(name.to_string())
+                }
+                None => None,
+            };
+            saved.push(SavedEntity {
+                marker: *marker,
+                components,
+                image,
+            });
+        }
+
+        Ok(Snapshot {
+            bunny_count: world.fetch::<BunnyCount>().0,
+            collected_count: world.fetch::<CollectedCount>().0,
+            physics: *world.fetch::<PhysicsConfig>(),
+            random: (*world.fetch::<Random>()).clone(),
+            entities: saved,
+        })
+    }
+
+    /// Deletes the saved entities in `world` and puts the snapshot's in
+    /// their place. Nothing changes if an image is missing.
+    pub fn restore(self, world: &mut World) -> Result<()> {
+        let sprites = {
+            let names = world.fetch::<ImageNames>();
+            self.entities
+                .iter()
+                .map(|saved| match &saved.image {
+                    Some(name) => names
+                        .find(name)
+                        .map(|id| Some(Sprite::from_image(id)))
+                        .ok_or_else(|| anyhow!("no image called {}", name)),
+                    None => Ok(None),
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+
+        {
+            let entities = world.entities();
+            let markers = world.read_storage::<SaveMarker>();
+            for (entity, _) in (&entities, &markers).join() {
+                entities.delete(entity)?;
+            }
+        }
+        world.maintain();
+        *world.write_resource::<SimpleMarkerAllocator<Saved>>() = Default::default();
+
+        {
+            let (
+                entities,
+                mut markers,
+                mut allocator,
+                mut positions,
+                mut velocities,
+                mut rotations,
+                mut angular_velocities,
+                mut transforms,
+                mut colliders,
+                mut tints,
+                mut colors,
+                mut sprite_storage,
+            ) = world.system_data::<(
+                Entities,
+                WriteStorage<SaveMarker>,
+                Write<SimpleMarkerAllocator<Saved>>,
+                WriteStorage<Position>,
+                WriteStorage<Velocity>,
+                WriteStorage<Rotation>,
+                WriteStorage<AngularVelocity>,
+                WriteStorage<Transform>,
+                WriteStorage<Collider>,
+                WriteStorage<Tint>,
+                WriteStorage<ColorBySpeed>,
+                WriteStorage<Sprite>,
+            )>();
+            let mut storages = (
+                &mut positions,
+                &mut velocities,
+                &mut rotations,
+                &mut angular_velocities,
+                &mut transforms,
+                &mut colliders,
+                &mut tints,
+                &mut colors,
+            );
+
+            for (saved, sprite) in self.entities.into_iter().zip(sprites) {
+                let entity = allocator.retrieve_entity(saved.marker, &mut markers, &entities);
+                DeserializeComponents::<NoError, SaveMarker>::deserialize_entity(
+                    &mut storages,
+                    entity,
+                    saved.components,
+                    |marker| allocator.retrieve_entity_internal(marker.id()),
+                )?;
+                if let Some(sprite) = sprite {
+                    sprite_storage.insert(entity, sprite)?;
+                }
+            }
+        }
+
+        world.fetch_mut::<BunnyCount>().0 = self.bunny_count;
+        world.fetch_mut::<CollectedCount>().0 = self.collected_count;
+        *world.fetch_mut::<PhysicsConfig>() = self.physics;
+        *world.fetch_mut::<Random>() = self.random;
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// A lot smaller than the JSON, for big scenes.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::init_world;
+    use crate::input_system::{InputSystem, SpawnRequest};
+    use crate::move_system::MoveSystem;
+    use crate::prefab::Prefabs;
+    use vek::Vec2;
+
+    const BUNNY: &str = "/assets/images/bunny.png";
+
+    /// A world with `names` for images, which spawns `bunnies` more and
+    /// moves them along a bit.
+    fn world(seed: u64, names: &[&str], bunnies: u32) -> World {
+        let images: Vec<_> = names
+            .iter()
+            .map(|name| (*name, Vec2::new(26, 37)))
+            .collect();
+        let mut world = init_world(Vec2::new(800, 600), seed, Prefabs::builtin(&images)).unwrap();
+        world.insert(ImageNames(
+            names.iter().map(|name| name.to_string()).collect(),
+        ));
+        let mut input_system = InputSystem::new(&mut world);
+        world.fetch_mut::<SpawnRequest>().0 = bunnies;
+        for _ in 0..10 {
+            input_system.run_now(&world);
+            MoveSystem.run_now(&world);
+            world.maintain();
+        }
+        world
+    }
+
+    /// The saved entities by marker, with their sprite's image name.
+    fn saved(world: &World) -> Vec<(u64, Position, Velocity, Option<String>)> {
+        let names = world.fetch::<ImageNames>();
+        let mut saved: Vec<_> = (
+            &world.read_storage::<SaveMarker>(),
+            &world.read_storage::<Position>(),
+            &world.read_storage::<Velocity>(),
+            world.read_storage::<Sprite>().maybe(),
+        )
+            .join()
+            .map(|(marker, position, velocity, sprite)| {
+                let image = sprite.map(|sprite| {
+                    let id = ImageId {
+                        id: sprite.batch_id(),
+                    };
+                    names.name(id).unwrap().to_string()
+                });
+                (marker.id(), *position, *velocity, image)
+            })
+            .collect();
+        saved.sort_by_key(|saved| saved.0);
+        saved
+    }
+
+    #[test]
+    fn round_trips_through_json_and_bincode() {
+        let original = world(1, &[BUNNY], 20);
+        let expected = saved(&original);
+        assert_eq!(expected.len(), 23);
+        let snapshot = Snapshot::save(&original).unwrap();
+
+        let json = Snapshot::from_json(&snapshot.to_json().unwrap()).unwrap();
+        let bytes = Snapshot::from_bytes(&snapshot.to_bytes().unwrap()).unwrap();
+        for snapshot in [json, bytes] {
+            // Images loaded in a different order, and different bunnies.
+            let mut restored = world(2, &["hat", BUNNY], 5);
+            snapshot.restore(&mut restored).unwrap();
+            assert_eq!(saved(&restored), expected);
+            assert_eq!(restored.fetch::<BunnyCount>().0, 23);
+
+            let sprites = restored.read_storage::<Sprite>();
+            assert!((&sprites).join().all(|sprite| sprite.batch_id() == 1));
+        }
+    }
+
+    #[test]
+    fn later_bunnies_get_new_markers() {
+        let snapshot = Snapshot::save(&world(1, &[BUNNY], 20)).unwrap();
+        let mut restored = world(2, &[BUNNY], 0);
+        snapshot.restore(&mut restored).unwrap();
+
+        let mut input_system = InputSystem::new(&mut restored);
+        restored.fetch_mut::<SpawnRequest>().0 = 5;
+        input_system.run_now(&restored);
+        restored.maintain();
+
+        let ids: Vec<_> = saved(&restored).iter().map(|saved| saved.0).collect();
+        assert_eq!(ids, (0..28).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn bunnies_spawned_after_loading_match() {
+        let mut original = world(1, &[BUNNY], 20);
+        let snapshot = Snapshot::save(&original).unwrap();
+        let mut restored = world(2, &[BUNNY], 0);
+        snapshot.restore(&mut restored).unwrap();
+
+        for world in [&mut original, &mut restored] {
+            let mut input_system = InputSystem::new(world);
+            world.fetch_mut::<SpawnRequest>().0 = 5;
+            input_system.run_now(world);
+            world.maintain();
+        }
+        assert_eq!(saved(&restored), saved(&original));
+    }
+
+    #[test]
+    fn missing_images_leave_the_world_alone() {
+        let snapshot = Snapshot::save(&world(1, &[BUNNY], 20)).unwrap();
+        let mut restored = world(2, &[BUNNY], 0);
+        restored.insert(ImageNames(vec!["carrot".to_string()]));
+        let before = saved(&restored);
+
+        assert!(snapshot.restore(&mut restored).is_err());
+        assert_eq!(saved(&restored), before);
+    }
+}
@@ -0,0 +1,111 @@
+use specs::Entity;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::BuildHasherDefault;
+use vek::Vec2;
+
+/// Uniform grid bucketing items, usually entities, by their axis aligned
+/// bounds.
+///
+/// Cells are only allocated where something is inserted, so the grid doesn't
+/// need to know the size of the world. The hasher isn't randomly seeded, so
+/// cells are visited in the same order on every run.
+pub struct SpatialHash<T = Entity> {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<T>, BuildHasherDefault<DefaultHasher>>,
+}
+
+impl<T: Copy> SpatialHash<T> {
+    pub fn new(cell_size: f32) -> Self {
+        SpatialHash {
+            cell_size,
+            cells: HashMap::default(),
+        }
+    }
+
+    /// Empties the cells. The ones used since the last clear keep their
+    /// allocations for the next frame, the others are dropped, so cells
+    /// bodies have left don't pile up.
+    pub fn clear(&mut self) {
+        self.cells.retain(|_, cell| {
+            let used = !cell.is_empty();
+            cell.clear();
+            used
+        });
+    }
+
+    pub fn insert(&mut self, item: T, min: Vec2<f32>, max: Vec2<f32>) {
+        let (from, to) = (self.cell(min), self.cell(max));
+        for x in from.0..=to.0 {
+            for y in from.1..=to.1 {
+                self.cells.entry((x, y)).or_default().push(item);
+            }
+        }
+    }
+
+    /// Items whose bounds may contain `point`.
+    pub fn query_point(&self, point: Vec2<f32>) -> &[T] {
+        self.cells
+            .get(&self.cell(point))
+            .map(|cell| cell.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Calls `f` with the coordinates and items of every non-empty cell.
+    pub fn for_each_cell(&self, mut f: impl FnMut((i32, i32), &[T])) {
+        for (cell, items) in self.cells.iter() {
+            if !items.is_empty() {
+                f(*cell, items);
+            }
+        }
+    }
+
+    pub fn cell(&self, point: Vec2<f32>) -> (i32, i32) {
+        let cell = (point / self.cell_size).floor();
+        (cell.x as i32, cell.y as i32)
+    }
+}
+
+impl<T: Copy> Default for SpatialHash<T> {
+    fn default() -> Self {
+        SpatialHash::new(64.)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use specs::prelude::*;
+
+    #[test]
+    fn entities_are_found_in_every_cell_they_overlap() {
+        let mut world = World::new();
+        let a = world.create_entity().build();
+        let b = world.create_entity().build();
+
+        let mut hash = SpatialHash::new(10.);
+        hash.insert(a, Vec2::new(5., 5.), Vec2::new(25., 15.));
+        hash.insert(b, Vec2::new(-5., -5.), Vec2::new(-1., -1.));
+
+        assert_eq!(hash.query_point(Vec2::new(21., 11.)), &[a]);
+        assert_eq!(hash.query_point(Vec2::new(-3., -3.)), &[b]);
+        assert!(hash.query_point(Vec2::new(35., 5.)).is_empty());
+
+        hash.clear();
+        assert!(hash.query_point(Vec2::new(21., 11.)).is_empty());
+    }
+
+    #[test]
+    fn cells_left_empty_are_dropped() {
+        let mut hash = SpatialHash::new(10.);
+        hash.insert(1, Vec2::new(5., 5.), Vec2::new(25., 15.));
+        hash.clear();
+        assert_eq!(hash.cells.len(), 6);
+
+        hash.insert(1, Vec2::new(5., 5.), Vec2::new(6., 6.));
+        hash.clear();
+        assert_eq!(hash.cells.len(), 1);
+        hash.clear();
+        assert!(hash.cells.is_empty());
+    }
+}
@@ -0,0 +1,266 @@
+//! Sprite vertices kept in drawing order, so they can be uploaded as one
+//! buffer and drawn in texture runs.
+//!
+//! `RenderSystem` keeps two batches. The dynamic one is rebuilt from scratch
+//! every frame. The static one holds sprites that rarely move and is only
+//! rebuilt when sprites join or leave it or their draw order changes; moving
+//! a static sprite just marks its quad dirty, and only dirty ranges are
+//! uploaded again.
+
+use crate::camera::RenderLayers;
+use crate::culling::View;
+use crate::draw_order::{self, DrawKey};
+use specs::{Component, NullStorage};
+use std::ops::Range;
+use vek::{Rgba, Vec2};
+
+/// Keeps a sprite in the static batch even though it has a `Velocity`, e.g.
+/// a body that has come to rest. Sprites without a `Velocity` or a `Parent`
+/// are static anyway.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq)]
+#[storage(NullStorage)]
+pub struct Static;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vertex {
+    vertex: Vec2<f32>,
+    texcoord: Vec2<u16>,
+    color: Rgba<u8>,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VertexData([Vertex; 4]);
+
+impl VertexData {
+    /// A textured quad, corners in the order of `Transform::corners`.
+    pub fn new(corners: [Vec2<f32>; 4], color: Rgba<u8>) -> Self {
+        VertexData::textured(corners, Vec2::zero(), Vec2::one(), color)
+    }
+
+    /// A quad showing part of the texture, from `uv_min` at the top left
+    /// corner to `uv_max` at the bottom right, e.g. a glyph in a font atlas.
+    pub fn textured(
+        corners: [Vec2<f32>; 4],
+        uv_min: Vec2<f32>,
+        uv_max: Vec2<f32>,
+        color: Rgba<u8>,
+    ) -> Self {
+        let [v1, v2, v3, v4] = corners;
+        let vertex = |vertex, u: f32, v: f32| Vertex {
+            vertex,
+            texcoord: Vec2::new(u, v).map(|c| (c.clamp(0., 1.) * u16::MAX as f32).round() as u16),
+            color,
+        };
+        VertexData([
+            vertex(v1, uv_min.x, uv_min.y),
+            vertex(v2, uv_max.x, uv_min.y),
+            vertex(v3, uv_min.x, uv_max.y),
+            vertex(v4, uv_max.x, uv_max.y),
+        ])
+    }
+}
+
+/// What's needed to tell which cameras see a quad.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BatchItem {
+    pub layers: RenderLayers,
+    /// Axis aligned box around the quad.
+    pub min: Vec2<f32>,
+    pub max: Vec2<f32>,
+    /// Which shader settings the quad is drawn with, 0 for plain sprites.
+    pub material: u32,
+}
+
+impl BatchItem {
+    pub fn is_seen_by(&self, view: &View) -> bool {
+        view.sees(self.layers, self.min, self.max)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpriteQuad {
+    pub vertices: VertexData,
+    pub item: BatchItem,
+}
+
+#[derive(Default)]
+pub struct SpriteBatch {
+    sorted: Vec<(DrawKey, SpriteQuad)>,
+    items: Vec<(DrawKey, BatchItem)>,
+    vertex_data: Vec<VertexData>,
+    /// Quads changed since the last `take_dirty`.
+    dirty: Vec<usize>,
+    all_dirty: bool,
+}
+
+impl SpriteBatch {
+    /// Replaces the contents with `sprites`, and marks all of it dirty.
+    pub fn rebuild(&mut self, sprites: impl IntoIterator<Item = (DrawKey, SpriteQuad)>) {
+        self.sorted.clear();
+        self.sorted.extend(sprites);
+        draw_order::sort(&mut self.sorted);
+
+        self.items.clear();
+        self.vertex_data.clear();
+        for (key, quad) in self.sorted.iter() {
+            self.items.push((*key, quad.item));
+            self.vertex_data.push(quad.vertices);
+        }
+        self.dirty.clear();
+        self.all_dirty = true;
+    }
+
+    /// Replaces the quad of the sprite with `key` in place. Returns `false`
+    /// if there's no such sprite, e.g. because its draw order changed, in
+    /// which case the batch needs a rebuild.
+    pub fn update(&mut self, key: DrawKey, quad: SpriteQuad) -> bool {
+        let index = match self.items.binary_search_by_key(&key, |(key, _)| *key) {
+            Ok(index) => index,
+            Err(_) => return false,
+        };
+        self.items[index].1 = quad.item;
+        if self.vertex_data[index] != quad.vertices {
+            self.vertex_data[index] = quad.vertices;
+            self.dirty.push(index);
+        }
+        true
+    }
+
+    /// Ranges of quads changed since the last call, in order and merged
+    /// where they touch.
+    pub fn take_dirty(&mut self) -> Vec<Range<usize>> {
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        if std::mem::take(&mut self.all_dirty) {
+            self.dirty.clear();
+            if !self.is_empty() {
+                ranges.push(0..self.len());
+            }
+            return ranges;
+        }
+
+        self.dirty.sort_unstable();
+        self.dirty.dedup();
+        for index in self.dirty.drain(..) {
+            match ranges.last_mut() {
+                Some(range) if range.end == index => range.end += 1,
+                _ => ranges.push(index..index + 1),
+            }
+        }
+        ranges
+    }
+
+    pub fn items(&self) -> &[(DrawKey, BatchItem)] {
+        &self.items
+    }
+
+    pub fn vertex_data(&self) -> &[VertexData] {
+        &self.vertex_data
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+/// Splits a texture run further where the material changes.
+pub fn material_runs(
+    items: &[(DrawKey, BatchItem)],
+    quads: Range<usize>,
+) -> impl Iterator<Item = (u32, Range<usize>)> + '_ {
+    let mut start = quads.start;
+    std::iter::from_fn(move || {
+        let material = items[start..quads.end].first()?.1.material;
+        let len = items[start..quads.end]
+            .iter()
+            .take_while(|(_, item)| item.material == material)
+            .count();
+        start += len;
+        Some((material, start - len..start))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sprite(entity: u32, image: u32, x: f32) -> (DrawKey, SpriteQuad) {
+        let key = DrawKey {
+            layer: 0,
+            z: 0,
+            image,
+            entity,
+        };
+        let corners = [
+            Vec2::new(x, 1.),
+            Vec2::new(x + 1., 1.),
+            Vec2::new(x, 0.),
+            Vec2::new(x + 1., 0.),
+        ];
+        let quad = SpriteQuad {
+            vertices: VertexData::new(corners, Rgba::white()),
+            item: BatchItem {
+                layers: RenderLayers::DEFAULT,
+                min: Vec2::new(x, 0.),
+                max: Vec2::new(x + 1., 1.),
+                material: 0,
+            },
+        };
+        (key, quad)
+    }
+
+    #[test]
+    fn rebuilding_sorts_and_dirties_everything() {
+        let mut batch = SpriteBatch::default();
+        batch.rebuild(vec![sprite(0, 1, 0.), sprite(1, 0, 1.), sprite(2, 0, 2.)]);
+
+        let entities: Vec<_> = batch.items().iter().map(|(key, _)| key.entity).collect();
+        assert_eq!(entities, vec![1, 2, 0]);
+        assert_eq!(batch.vertex_data()[0], sprite(1, 0, 1.).1.vertices);
+        let dirty = batch.take_dirty();
+        assert_eq!(dirty.len(), 1);
+        assert_eq!(dirty[0], 0..3);
+        assert!(batch.take_dirty().is_empty());
+    }
+
+    #[test]
+    fn updates_only_dirty_what_changed() {
+        let mut batch = SpriteBatch::default();
+        batch.rebuild((0..6).map(|entity| sprite(entity, 0, entity as f32)));
+        batch.take_dirty();
+
+        for entity in [4, 1, 2, 5].iter() {
+            let (key, quad) = sprite(*entity, 0, 10. + *entity as f32);
+            assert!(batch.update(key, quad));
+        }
+        // Unchanged quads aren't uploaded again.
+        let (key, quad) = sprite(0, 0, 0.);
+        assert!(batch.update(key, quad));
+        assert_eq!(batch.take_dirty(), vec![1..3, 4..6]);
+
+        // A different image means a different place in the draw order.
+        let (key, quad) = sprite(3, 1, 3.);
+        assert!(!batch.update(key, quad));
+    }
+
+    #[test]
+    fn runs_split_where_the_material_changes() {
+        let items: Vec<_> = [0, 0, 1, 1, 2, 0]
+            .iter()
+            .enumerate()
+            .map(|(entity, material)| {
+                let (key, mut quad) = sprite(entity as u32, 0, 0.);
+                quad.item.material = *material;
+                (key, quad.item)
+            })
+            .collect();
+        let runs: Vec<_> = material_runs(&items, 1..6).collect();
+        assert_eq!(runs, vec![(0, 1..2), (1, 2..4), (2, 4..5), (0, 5..6)]);
+        assert_eq!(material_runs(&items, 3..3).count(), 0);
+    }
+}
@@ -0,0 +1,308 @@
+//! Text drawn with bitmap fonts. Strings are laid out into glyph quads, which
+//! `RenderSystem` draws like sprites, one quad per glyph.
+//!
+//! Fonts with a signed distance field stay sharp when zoomed and can have an
+//! outline and a shadow. They're laid out the same way, so switching a `Text`
+//! over is just a matter of its `font`.
+
+use crate::assets::{ImageId, Images};
+use crate::data::load_text;
+use crate::font::Font;
+use anyhow::Result;
+use specs::{Component, DenseVecStorage};
+use vek::{Rgba, Vec2};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FontId(u32);
+
+/// A font and the images of its pages.
+pub struct FontAtlas {
+    pub font: Font,
+    pub pages: Vec<ImageId>,
+}
+
+/// Fonts `Text` can be drawn with.
+#[derive(Default)]
+pub struct Fonts {
+    fonts: Vec<FontAtlas>,
+}
+
+impl Fonts {
+    pub fn add(&mut self, atlas: FontAtlas) -> FontId {
+        self.fonts.push(atlas);
+        FontId(self.fonts.len() as u32 - 1)
+    }
+
+    pub fn get(&self, id: FontId) -> Option<&FontAtlas> {
+        self.fonts.get(id.0 as usize)
+    }
+}
+
+/// Loads a `.fnt` file, text or XML, and the page images next to it. A
+/// `.json` file is read as msdf-atlas-gen output, with the atlas in a `.png`
+/// of the same name.
+pub async fn load_font(images: &mut Images, path: &str) -> Result<FontAtlas> {
+    let text = load_text(path).await?;
+    let dir = &path[..path.rfind('/').map_or(0, |i| i + 1)];
+    let font = match path.strip_suffix(".json") {
+        Some(stem) => Font::from_msdf_json(&text, &format!("{}.png", &stem[dir.len()..]))?,
+        None => Font::parse(&text)?,
+    };
+    let mut pages = Vec::new();
+    for file in font.pages.iter() {
+        pages.push(images.load(&format!("{}{}", dir, file)).await?);
+    }
+    Ok(FontAtlas { font, pages })
+}
+
+/// Where lines go relative to the entity's `Position`: left aligned lines
+/// start there, centered ones are centered on it and right aligned ones end
+/// there.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextStyle {
+    pub scale: f32,
+    pub align: Align,
+    /// Lines longer than this are wrapped between words. Words longer than
+    /// it get a line of their own.
+    pub max_width: Option<f32>,
+    /// Only drawn for distance field fonts, like the shadow.
+    pub outline: Option<Outline>,
+    pub shadow: Option<Shadow>,
+}
+
+/// Sizes are in font pixels. The outline and shadow have to fit in the
+/// padding around each glyph, half the font's `distance_range`, or they're
+/// cut off at the glyph's quad.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Outline {
+    pub width: f32,
+    pub color: Rgba<f32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Shadow {
+    /// How far the shadow is from the glyphs, y up.
+    pub offset: Vec2<f32>,
+    /// How far the shadow's edge is blurred.
+    pub softness: f32,
+    pub color: Rgba<f32>,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        TextStyle {
+            scale: 1.,
+            align: Align::Left,
+            max_width: None,
+            outline: None,
+            shadow: None,
+        }
+    }
+}
+
+/// A string drawn at the entity's `Position`, which is the top of the first
+/// line. The color comes from its `Tint`.
+#[derive(Component, Clone, Debug, PartialEq)]
+#[storage(DenseVecStorage)]
+pub struct Text {
+    pub text: String,
+    pub font: FontId,
+    pub style: TextStyle,
+}
+
+impl Text {
+    pub fn new(text: impl Into<String>, font: FontId) -> Self {
+        Text {
+            text: text.into(),
+            font,
+            style: TextStyle::default(),
+        }
+    }
+}
+
+/// A glyph placed relative to the text's position, y up, with the part of
+/// its atlas page to draw from, in texture coordinates from the top left.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GlyphQuad {
+    pub min: Vec2<f32>,
+    pub max: Vec2<f32>,
+    pub uv_min: Vec2<f32>,
+    pub uv_max: Vec2<f32>,
+    pub page: u32,
+}
+
+impl GlyphQuad {
+    /// Corners in the order of `Transform::corners`, for a text at
+    /// `position`.
+    pub fn corners(&self, position: Vec2<f32>) -> [Vec2<f32>; 4] {
+        let (min, max) = (position + self.min, position + self.max);
+        [Vec2::new(min.x, max.y), max, min, Vec2::new(max.x, min.y)]
+    }
+}
+
+/// Width of a single line, unscaled. Characters the font doesn't have are
+/// left out.
+pub fn line_width(font: &Font, line: &str) -> f32 {
+    let mut width = 0.;
+    let mut previous = None;
+    for c in line.chars() {
+        if let Some(glyph) = font.glyph(c) {
+            width += previous.map_or(0., |previous| font.kerning(previous, c)) + glyph.advance;
+            previous = Some(c);
+        }
+    }
+    width
+}
+
+/// Splits `text` into lines at line breaks, and between words where a line
+/// would get wider than `max_width`.
+pub fn wrap<'a>(font: &Font, text: &'a str, max_width: Option<f32>) -> Vec<&'a str> {
+    let mut lines = Vec::new();
+    for paragraph in text.split('\n') {
+        let max_width = match max_width {
+            Some(max_width) => max_width,
+            None => {
+                lines.push(paragraph);
+                continue;
+            }
+        };
+        // The current line is `paragraph[start..end]`.
+        let (mut start, mut end) = (0, 0);
+        let mut word_start = 0;
+        for word in paragraph.split(' ') {
+            let word_end = word_start + word.len();
+            if end > start && line_width(font, &paragraph[start..word_end]) > max_width {
+                lines.push(&paragraph[start..end]);
+                start = word_start;
+            }
+            end = word_end;
+            word_start = word_end + 1;
+        }
+        lines.push(&paragraph[start..end]);
+    }
+    lines
+}
+
+/// Lays `text` out into glyph quads, see `Text` for where they go.
+pub fn layout(font: &Font, text: &str, style: &TextStyle) -> Vec<GlyphQuad> {
+    let max_width = style.max_width.map(|width| width / style.scale);
+    let mut quads = Vec::new();
+    for (number, line) in wrap(font, text, max_width).into_iter().enumerate() {
+        let width = line_width(font, line);
+        let mut pen = Vec2::new(
+            match style.align {
+                Align::Left => 0.,
+                Align::Center => -width / 2.,
+                Align::Right => -width,
+            },
+            -(number as f32) * font.line_height,
+        );
+        let mut previous = None;
+        for c in line.chars() {
+            let glyph = match font.glyph(c) {
+                Some(glyph) => glyph,
+                None => continue,
+            };
+            pen.x += previous.map_or(0., |previous| font.kerning(previous, c));
+            previous = Some(c);
+            if glyph.size.x > 0. && glyph.size.y > 0. {
+                let top_left = Vec2::new(pen.x + glyph.offset.x, pen.y - glyph.offset.y);
+                quads.push(GlyphQuad {
+                    min: Vec2::new(top_left.x, top_left.y - glyph.size.y) * style.scale,
+                    max: Vec2::new(top_left.x + glyph.size.x, top_left.y) * style.scale,
+                    uv_min: glyph.position / font.atlas_size,
+                    uv_max: (glyph.position + glyph.size) / font.atlas_size,
+                    page: glyph.page,
+                });
+            }
+            pen.x += glyph.advance;
+        }
+    }
+    quads
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font::TEST_FONT;
+
+    fn font() -> Font {
+        Font::parse(TEST_FONT).unwrap()
+    }
+
+    #[test]
+    fn glyphs_are_placed_from_the_top_of_the_line() {
+        let quads = layout(&font(), "Aa", &TextStyle::default());
+        assert_eq!(quads.len(), 2);
+        assert_eq!(quads[0].min, Vec2::new(1., -10.));
+        assert_eq!(quads[0].max, Vec2::new(7., -2.));
+        assert_eq!(quads[0].uv_min, Vec2::new(0.1, 0.));
+        assert_eq!(quads[0].uv_max, Vec2::new(0.16, 0.16));
+        // After A's advance, `a` sits lower on the line.
+        assert_eq!(quads[1].min, Vec2::new(8., -10.));
+        assert_eq!(quads[1].max, Vec2::new(13., -5.));
+
+        let scaled = layout(
+            &font(),
+            "Aa",
+            &TextStyle {
+                scale: 2.,
+                ..TextStyle::default()
+            },
+        );
+        assert_eq!(scaled[1].min, Vec2::new(16., -20.));
+        assert_eq!(scaled[1].uv_min, quads[1].uv_min);
+    }
+
+    #[test]
+    fn kerned_pairs_move_closer() {
+        let font = font();
+        assert_eq!(line_width(&font, "AA"), 16.);
+        assert_eq!(line_width(&font, "AV"), 14.);
+        let quads = layout(&font, "AV", &TextStyle::default());
+        assert_eq!(quads[1].min.x, 7.);
+    }
+
+    #[test]
+    fn lines_are_aligned_on_the_position() {
+        let font = font();
+        let align = |align| {
+            let style = TextStyle {
+                align,
+                ..TextStyle::default()
+            };
+            layout(&font, "AA\nA", &style)
+                .iter()
+                .map(|quad| quad.min.x)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(align(Align::Left), vec![1., 9., 1.]);
+        assert_eq!(align(Align::Center), vec![-7., 1., -3.]);
+        assert_eq!(align(Align::Right), vec![-15., -7., -7.]);
+    }
+
+    #[test]
+    fn long_lines_wrap_between_words() {
+        let font = font();
+        // "aa aa" is 24 wide.
+        assert_eq!(wrap(&font, "aa aa aa", Some(24.)), vec!["aa aa", "aa"]);
+        assert_eq!(wrap(&font, "aa aa aa", Some(23.)), vec!["aa", "aa", "aa"]);
+        assert_eq!(wrap(&font, "aaaaaaa a", Some(10.)), vec!["aaaaaaa", "a"]);
+        assert_eq!(wrap(&font, "aa\n\naa aa", None), vec!["aa", "", "aa aa"]);
+
+        let style = TextStyle {
+            scale: 2.,
+            max_width: Some(46.),
+            ..TextStyle::default()
+        };
+        let quads = layout(&font, "aa aa", &style);
+        assert_eq!(quads[2].min, Vec2::new(0., -40.));
+    }
+}
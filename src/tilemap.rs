@@ -0,0 +1,1001 @@
+//! Maps made with Tiled and saved as JSON, with embedded tilesets and tile
+//! layers in the CSV format.
+//!
+//! Tile layers are cut into chunks of `CHUNK_TILES` squared tiles per
+//! tileset, each an entity with a `TileChunk` that `RenderSystem` puts in its
+//! static batch and culls as a whole. Collision shapes drawn on tiles in the
+//! tileset editor, and tiles with a `solid` property, become `Obstacle`s,
+//! with their `kind` property picking the `ObstacleKind`. Full width shapes
+//! of neighbouring tiles are merged into one obstacle.
+//!
+//! Objects spawn the prefab named by their type, or an obstacle with the type
+//! `obstacle`. Tile objects that don't spawn a prefab are drawn as a chunk of
+//! their own. Other objects without a type are left out. Properties besides
+//! `solid` and `kind` aren't kept.
+
+use crate::assets::ImageId;
+use crate::components::Position;
+use crate::draw_order::{Layer, ZIndex};
+use crate::obstacle_system::{Obstacle, ObstacleKind};
+use crate::prefab::{spawn_prefab, Param, Prefab, Vec2Param};
+use crate::render_system::Tint;
+use anyhow::{anyhow, bail, Result};
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+use serde_json::Value;
+use specs::prelude::*;
+use specs::{Component, DenseVecStorage, FlaggedStorage};
+use std::collections::BTreeMap;
+use vek::{Rgba, Vec2};
+
+/// Chunks are this many tiles wide and high.
+pub const CHUNK_TILES: u32 = 16;
+
+const FLIPPED_HORIZONTALLY: u32 = 0x8000_0000;
+const FLIPPED_VERTICALLY: u32 = 0x4000_0000;
+const FLIPPED_DIAGONALLY: u32 = 0x2000_0000;
+const HEXAGONAL_ROTATION: u32 = 0x1000_0000;
+const GID_MASK: u32 =
+    !(FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY | FLIPPED_DIAGONALLY | HEXAGONAL_ROTATION);
+
+#[derive(Deserialize)]
+struct MapJson {
+    width: u32,
+    height: u32,
+    tilewidth: u32,
+    tileheight: u32,
+    #[serde(default)]
+    orientation: Option<String>,
+    #[serde(default)]
+    infinite: bool,
+    #[serde(default)]
+    layers: Vec<LayerJson>,
+    #[serde(default)]
+    tilesets: Vec<TilesetJson>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum LayerJson {
+    TileLayer(TileLayerJson),
+    ObjectGroup(ObjectGroupJson),
+    Group {
+        #[serde(default)]
+        layers: Vec<LayerJson>,
+    },
+    /// Image layers.
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct TileLayerJson {
+    #[serde(default)]
+    name: String,
+    width: u32,
+    data: TileData,
+    #[serde(default = "visible")]
+    visible: bool,
+    #[serde(default = "opaque")]
+    opacity: f32,
+    #[serde(default)]
+    offsetx: f32,
+    #[serde(default)]
+    offsety: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TileData {
+    Csv(Vec<u32>),
+    /// Base64, maybe compressed.
+    Encoded(IgnoredAny),
+}
+
+#[derive(Default, Deserialize)]
+struct ObjectGroupJson {
+    #[serde(default)]
+    objects: Vec<ObjectJson>,
+    #[serde(default = "visible")]
+    visible: bool,
+}
+
+#[derive(Deserialize)]
+struct ObjectJson {
+    #[serde(default)]
+    id: u32,
+    /// Tiled 1.9 calls it the class.
+    #[serde(default, rename = "type", alias = "class")]
+    kind: String,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    /// Tile objects show a tile, and are placed by their bottom left corner.
+    #[serde(default)]
+    gid: Option<u32>,
+    #[serde(default)]
+    polygon: Option<Vec<Vec2<f32>>>,
+    #[serde(default)]
+    polyline: Option<Vec<Vec2<f32>>>,
+    #[serde(default)]
+    properties: Vec<PropertyJson>,
+}
+
+#[derive(Deserialize)]
+struct PropertyJson {
+    name: String,
+    value: Value,
+}
+
+#[derive(Deserialize)]
+struct TilesetJson {
+    firstgid: u32,
+    /// External tilesets only have this.
+    #[serde(default)]
+    source: Option<String>,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    image: Option<String>,
+    #[serde(default)]
+    imagewidth: u32,
+    #[serde(default)]
+    imageheight: u32,
+    #[serde(default)]
+    tilewidth: u32,
+    #[serde(default)]
+    tileheight: u32,
+    #[serde(default)]
+    columns: u32,
+    #[serde(default)]
+    margin: u32,
+    #[serde(default)]
+    spacing: u32,
+    #[serde(default)]
+    tiles: Vec<TileJson>,
+}
+
+#[derive(Deserialize)]
+struct TileJson {
+    id: u32,
+    #[serde(default)]
+    properties: Vec<PropertyJson>,
+    #[serde(default)]
+    objectgroup: Option<ObjectGroupJson>,
+}
+
+fn visible() -> bool {
+    true
+}
+
+fn opaque() -> f32 {
+    1.
+}
+
+fn property<'a>(properties: &'a [PropertyJson], name: &str) -> Option<&'a Value> {
+    properties
+        .iter()
+        .find(|property| property.name == name)
+        .map(|property| &property.value)
+}
+
+fn obstacle_kind(properties: &[PropertyJson]) -> Result<Option<ObstacleKind>> {
+    match property(properties, "kind") {
+        Some(kind) => Ok(Some(serde_json::from_value(kind.clone())?)),
+        None => Ok(None),
+    }
+}
+
+/// A box in Tiled's pixels, where y points down.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Rect {
+    min: Vec2<f32>,
+    max: Vec2<f32>,
+}
+
+impl Rect {
+    fn new(position: Vec2<f32>, size: Vec2<f32>) -> Self {
+        Rect {
+            min: position,
+            max: position + size,
+        }
+    }
+
+    fn around(points: impl Iterator<Item = Vec2<f32>>) -> Option<Self> {
+        points.fold(None, |rect: Option<Rect>, point| match rect {
+            Some(rect) => Some(Rect {
+                min: Vec2::partial_min(rect.min, point),
+                max: Vec2::partial_max(rect.max, point),
+            }),
+            None => Some(Rect {
+                min: point,
+                max: point,
+            }),
+        })
+    }
+
+    fn offset(self, offset: Vec2<f32>) -> Self {
+        Rect {
+            min: self.min + offset,
+            max: self.max + offset,
+        }
+    }
+}
+
+impl ObjectJson {
+    /// The box around the object's shape.
+    fn bounds(&self) -> Rect {
+        let position = Vec2::new(self.x, self.y);
+        let size = Vec2::new(self.width, self.height);
+        if self.gid.is_some() {
+            return Rect::new(position - Vec2::new(0., size.y), size);
+        }
+        let points = self.polygon.as_ref().or(self.polyline.as_ref());
+        match points.and_then(|points| Rect::around(points.iter().copied())) {
+            Some(rect) => rect.offset(position),
+            // Rectangles, ellipses and points.
+            None => Rect::new(position, size),
+        }
+    }
+}
+
+/// What collides in a tile, relative to the top left of its image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct TileShape {
+    rect: Rect,
+    kind: ObstacleKind,
+}
+
+struct Tileset {
+    firstgid: u32,
+    image: String,
+    image_size: Vec2<f32>,
+    tile_size: Vec2<f32>,
+    columns: u32,
+    margin: u32,
+    spacing: u32,
+    shapes: BTreeMap<u32, Vec<TileShape>>,
+}
+
+impl Tileset {
+    fn from_json(json: TilesetJson, base: &str) -> Result<Self> {
+        if let Some(source) = json.source {
+            bail!("tileset {} is external, embed it in the map", source);
+        }
+        let image = json
+            .image
+            .as_ref()
+            .ok_or_else(|| anyhow!("tileset {} isn't a single image", json.name))?;
+        if json.columns == 0 || json.tilewidth == 0 || json.tileheight == 0 {
+            bail!("tileset {} has no tiles", json.name);
+        }
+        let tile_size = Vec2::new(json.tilewidth as f32, json.tileheight as f32);
+
+        let mut shapes = BTreeMap::new();
+        for tile in json.tiles.iter() {
+            let kind = obstacle_kind(&tile.properties)?;
+            let mut tile_shapes = Vec::new();
+            for object in tile
+                .objectgroup
+                .iter()
+                .flat_map(|group| group.objects.iter())
+            {
+                tile_shapes.push(TileShape {
+                    rect: object.bounds(),
+                    kind: obstacle_kind(&object.properties)?
+                        .or(kind)
+                        .unwrap_or(ObstacleKind::Box),
+                });
+            }
+            let solid = property(&tile.properties, "solid") == Some(&Value::Bool(true));
+            if tile_shapes.is_empty() && solid {
+                tile_shapes.push(TileShape {
+                    rect: Rect::new(Vec2::zero(), tile_size),
+                    kind: kind.unwrap_or(ObstacleKind::Box),
+                });
+            }
+            if !tile_shapes.is_empty() {
+                shapes.insert(tile.id, tile_shapes);
+            }
+        }
+
+        Ok(Tileset {
+            firstgid: json.firstgid,
+            image: resolve(base, image),
+            image_size: Vec2::new(json.imagewidth as f32, json.imageheight as f32),
+            tile_size,
+            columns: json.columns,
+            margin: json.margin,
+            spacing: json.spacing,
+            shapes,
+        })
+    }
+
+    /// Texture coordinates of a tile, from its top left to its bottom right,
+    /// swapped where `gid` flips it.
+    fn uv(&self, id: u32, gid: u32) -> (Vec2<f32>, Vec2<f32>) {
+        let cell = Vec2::new(id % self.columns, id / self.columns);
+        let min = cell.map(|c| (self.margin + c * self.spacing) as f32)
+            + cell.numcast().unwrap() * self.tile_size;
+        let (mut uv_min, mut uv_max) = (
+            min / self.image_size,
+            (min + self.tile_size) / self.image_size,
+        );
+        if gid & FLIPPED_HORIZONTALLY != 0 {
+            std::mem::swap(&mut uv_min.x, &mut uv_max.x);
+        }
+        if gid & FLIPPED_VERTICALLY != 0 {
+            std::mem::swap(&mut uv_min.y, &mut uv_max.y);
+        }
+        (uv_min, uv_max)
+    }
+}
+
+/// Joins `path` to the directory of the file at `base`, like a relative URL.
+fn resolve(base: &str, path: &str) -> String {
+    if path.starts_with('/') {
+        return path.to_string();
+    }
+    let mut segments: Vec<&str> = base.split('/').collect();
+    segments.pop();
+    for segment in path.split('/') {
+        match segment {
+            "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+    segments.join("/")
+}
+
+/// A tile drawn by a `TileChunk`, relative to the chunk's `Position`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TileQuad {
+    pub min: Vec2<f32>,
+    pub max: Vec2<f32>,
+    /// At the top left corner and the bottom right one, swapped for flipped
+    /// tiles.
+    pub uv_min: Vec2<f32>,
+    pub uv_max: Vec2<f32>,
+}
+
+impl TileQuad {
+    /// In the order of `Transform::corners`.
+    pub fn corners(&self, position: Vec2<f32>) -> [Vec2<f32>; 4] {
+        let (min, max) = (position + self.min, position + self.max);
+        [Vec2::new(min.x, max.y), max, min, Vec2::new(max.x, min.y)]
+    }
+}
+
+/// Part of a tile layer using one tileset.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TileChunk {
+    pub image: ImageId,
+    pub quads: Vec<TileQuad>,
+    /// Box around the quads, relative to the `Position`.
+    pub min: Vec2<f32>,
+    pub max: Vec2<f32>,
+}
+
+impl Component for TileChunk {
+    type Storage = FlaggedStorage<Self, DenseVecStorage<Self>>;
+}
+
+impl TileChunk {
+    fn new(image: ImageId, quads: Vec<TileQuad>) -> Self {
+        let min = quads
+            .iter()
+            .fold(Vec2::broadcast(f32::INFINITY), |min, quad| {
+                Vec2::partial_min(min, quad.min)
+            });
+        let max = quads
+            .iter()
+            .fold(Vec2::broadcast(f32::NEG_INFINITY), |max, quad| {
+                Vec2::partial_max(max, quad.max)
+            });
+        TileChunk {
+            image,
+            quads,
+            min,
+            max,
+        }
+    }
+}
+
+/// A parsed Tiled map.
+pub struct TileMap {
+    size: Vec2<u32>,
+    tile_size: Vec2<f32>,
+    layers: Vec<LayerJson>,
+    tilesets: Vec<Tileset>,
+}
+
+impl TileMap {
+    /// `path` is where the map was loaded from, tileset images are relative
+    /// to it.
+    pub fn from_json(json: &str, path: &str) -> Result<Self> {
+        let map: MapJson = serde_json::from_str(json)?;
+        match map.orientation.as_deref() {
+            None | Some("orthogonal") => {}
+            Some(orientation) => bail!("{} maps aren't supported", orientation),
+        }
+        if map.infinite {
+            bail!("infinite maps aren't supported");
+        }
+        let mut tilesets = map
+            .tilesets
+            .into_iter()
+            .map(|tileset| Tileset::from_json(tileset, path))
+            .collect::<Result<Vec<_>>>()?;
+        tilesets.sort_by_key(|tileset| tileset.firstgid);
+
+        Ok(TileMap {
+            size: Vec2::new(map.width, map.height),
+            tile_size: Vec2::new(map.tilewidth as f32, map.tileheight as f32),
+            layers: map.layers,
+            tilesets,
+        })
+    }
+
+    /// The tileset images, which have to be loaded before spawning.
+    pub fn image_paths(&self) -> Vec<String> {
+        let mut paths: Vec<_> = self
+            .tilesets
+            .iter()
+            .map(|tileset| tileset.image.clone())
+            .collect();
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+
+    /// Size in pixels.
+    pub fn pixel_size(&self) -> Vec2<f32> {
+        self.size.numcast::<f32>().unwrap() * self.tile_size
+    }
+
+    /// The tileset a gid is from, and the tile's id in it.
+    fn tile(&self, gid: u32) -> Option<(usize, u32)> {
+        let gid = gid & GID_MASK;
+        let index = self
+            .tilesets
+            .iter()
+            .rposition(|tileset| tileset.firstgid <= gid)?;
+        Some((index, gid - self.tilesets[index].firstgid))
+    }
+
+    /// From Tiled's pixels, with y pointing down from the top of the map, to
+    /// the world, with the bottom left corner of the map at the origin.
+    fn to_world(&self, point: Vec2<f32>) -> Vec2<f32> {
+        Vec2::new(point.x, self.pixel_size().y - point.y)
+    }
+
+    /// Spawns the map's chunks, obstacles and objects, with `image` giving
+    /// the id of a tileset image by path. Prefabs come from the `Prefabs`
+    /// resource.
+    pub fn spawn(
+        &self,
+        world: &mut World,
+        image: impl Fn(&str) -> Option<ImageId>,
+    ) -> Result<Vec<Entity>> {
+        let images = self
+            .tilesets
+            .iter()
+            .map(|tileset| {
+                image(&tileset.image).ok_or_else(|| anyhow!("{} isn't loaded", tileset.image))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut tile_layers = Vec::new();
+        let mut object_groups = Vec::new();
+        flatten(&self.layers, &mut tile_layers, &mut object_groups);
+
+        // Tile objects go over the tile layers.
+        let object_z = tile_layers.len() as i32;
+        let mut entities = Vec::new();
+        let mut solids = Vec::new();
+        for (z, layer) in tile_layers.into_iter().enumerate() {
+            let chunks = self.layer_chunks(layer, &mut solids)?;
+            for ((_, _, tileset), chunk) in chunks {
+                let chunk = TileChunk::new(images[tileset], chunk);
+                let mut builder = world
+                    .create_entity()
+                    .with(Position::new(layer.offsetx, -layer.offsety))
+                    .with(chunk)
+                    .with(Layer::BACKGROUND)
+                    .with(ZIndex(z as i32));
+                if layer.opacity < 1. {
+                    builder = builder.with(Tint(Rgba::new(1., 1., 1., layer.opacity)));
+                }
+                entities.push(builder.build());
+            }
+        }
+
+        for solid in merge_runs(solids) {
+            entities.push(self.spawn_obstacle(world, solid.rect, solid.kind));
+        }
+
+        for object in object_groups.iter().flat_map(|group| group.objects.iter()) {
+            let rect = object.bounds();
+            match object.kind.as_str() {
+                "" | "obstacle" => {
+                    if let Some(tile) = self.spawn_tile_object(world, object, &images, object_z)? {
+                        entities.push(tile);
+                    }
+                    if object.kind == "obstacle" {
+                        let kind = obstacle_kind(&object.properties)?.unwrap_or(ObstacleKind::Box);
+                        entities.push(self.spawn_obstacle(world, rect, kind));
+                    }
+                }
+                prefab => {
+                    let center = self.to_world((rect.min + rect.max) / 2.);
+                    let overrides = Prefab {
+                        position: Some(Vec2Param {
+                            x: Param::Fixed(center.x),
+                            y: Param::Fixed(center.y),
+                        }),
+                        ..Prefab::default()
+                    };
+                    let entity = spawn_prefab(world, prefab, &overrides)
+                        .map_err(|err| anyhow!("object {}: {}", object.id, err))?;
+                    entities.push(entity);
+                }
+            }
+        }
+        Ok(entities)
+    }
+
+    /// The quads of a tile layer by chunk row, column and tileset, adding
+    /// the boxes of solid tiles to `solids`.
+    #[allow(clippy::type_complexity)]
+    fn layer_chunks(
+        &self,
+        layer: &TileLayerJson,
+        solids: &mut Vec<TileShape>,
+    ) -> Result<BTreeMap<(u32, u32, usize), Vec<TileQuad>>> {
+        let data = match &layer.data {
+            TileData::Csv(data) => data,
+            TileData::Encoded(_) => bail!(
+                "layer {} isn't saved as CSV, change its tile layer format",
+                layer.name
+            ),
+        };
+        let width = layer.width.max(1);
+        let offset = Vec2::new(layer.offsetx, layer.offsety);
+
+        let mut chunks: BTreeMap<_, Vec<TileQuad>> = BTreeMap::new();
+        for (i, gid) in data.iter().enumerate() {
+            let (tileset_index, id) = match self.tile(*gid) {
+                Some(tile) if *gid & GID_MASK != 0 => tile,
+                _ => continue,
+            };
+            if gid & FLIPPED_DIAGONALLY != 0 {
+                bail!(
+                    "layer {} has rotated tiles, which aren't supported",
+                    layer.name
+                );
+            }
+            let tileset = &self.tilesets[tileset_index];
+            let cell = Vec2::new(i as u32 % width, i as u32 / width);
+
+            // Tiles sit on the bottom left corner of their cell, whatever
+            // their size.
+            let bottom_left =
+                cell.numcast::<f32>().unwrap() * self.tile_size + Vec2::new(0., self.tile_size.y);
+            let top_left = bottom_left - Vec2::new(0., tileset.tile_size.y);
+            let (uv_min, uv_max) = tileset.uv(id, *gid);
+            let min = self.to_world(bottom_left);
+            chunks
+                .entry((cell.y / CHUNK_TILES, cell.x / CHUNK_TILES, tileset_index))
+                .or_default()
+                .push(TileQuad {
+                    min,
+                    max: min + tileset.tile_size,
+                    uv_min,
+                    uv_max,
+                });
+
+            for shape in tileset.shapes.get(&id).into_iter().flatten() {
+                let mut rect = shape.rect;
+                let mut kind = shape.kind;
+                if gid & FLIPPED_HORIZONTALLY != 0 {
+                    rect.min.x = tileset.tile_size.x - shape.rect.max.x;
+                    rect.max.x = tileset.tile_size.x - shape.rect.min.x;
+                    kind = match kind {
+                        ObstacleKind::SlopeUp => ObstacleKind::SlopeDown,
+                        ObstacleKind::SlopeDown => ObstacleKind::SlopeUp,
+                        kind => kind,
+                    };
+                }
+                if gid & FLIPPED_VERTICALLY != 0 {
+                    rect.min.y = tileset.tile_size.y - shape.rect.max.y;
+                    rect.max.y = tileset.tile_size.y - shape.rect.min.y;
+                }
+                solids.push(TileShape {
+                    rect: rect.offset(top_left + offset),
+                    kind,
+                });
+            }
+        }
+        Ok(chunks)
+    }
+
+    /// A chunk with the tile of a tile object, stretched over the object
+    /// like Tiled does. `None` if the object has no tile.
+    fn spawn_tile_object(
+        &self,
+        world: &mut World,
+        object: &ObjectJson,
+        images: &[ImageId],
+        z: i32,
+    ) -> Result<Option<Entity>> {
+        let gid = object.gid.unwrap_or(0);
+        let (tileset_index, tile_id) = match self.tile(gid) {
+            Some(tile) if gid & GID_MASK != 0 => tile,
+            _ => return Ok(None),
+        };
+        if gid & FLIPPED_DIAGONALLY != 0 {
+            bail!(
+                "object {} is a rotated tile, which isn't supported",
+                object.id
+            );
+        }
+        let rect = object.bounds();
+        let (uv_min, uv_max) = self.tilesets[tileset_index].uv(tile_id, gid);
+        let min = self.to_world(Vec2::new(rect.min.x, rect.max.y));
+        let quad = TileQuad {
+            min: Vec2::zero(),
+            max: rect.max - rect.min,
+            uv_min,
+            uv_max,
+        };
+        let tile = world
+            .create_entity()
+            .with(Position(min))
+            .with(TileChunk::new(images[tileset_index], vec![quad]))
+            .with(Layer::BACKGROUND)
+            .with(ZIndex(z))
+            .build();
+        Ok(Some(tile))
+    }
+
+    fn spawn_obstacle(&self, world: &mut World, rect: Rect, kind: ObstacleKind) -> Entity {
+        let center = self.to_world((rect.min + rect.max) / 2.);
+        world
+            .create_entity()
+            .with(Position(center))
+            .with(Obstacle {
+                kind,
+                half_extents: (rect.max - rect.min) / 2.,
+            })
+            .build()
+    }
+}
+
+fn flatten<'a>(
+    layers: &'a [LayerJson],
+    tile_layers: &mut Vec<&'a TileLayerJson>,
+    object_groups: &mut Vec<&'a ObjectGroupJson>,
+) {
+    for layer in layers {
+        match layer {
+            LayerJson::TileLayer(layer) if layer.visible => tile_layers.push(layer),
+            LayerJson::ObjectGroup(group) if group.visible => object_groups.push(group),
+            LayerJson::Group { layers } => flatten(layers, tile_layers, object_groups),
+            _ => {}
+        }
+    }
+}
+
+/// Joins boxes in a row that touch and have the same height and kind, so a
+/// floor of tiles is one obstacle instead of one per tile. Slopes are never
+/// joined.
+fn merge_runs(mut shapes: Vec<TileShape>) -> Vec<TileShape> {
+    shapes.sort_by(|a, b| {
+        (a.rect.min.y, a.rect.min.x)
+            .partial_cmp(&(b.rect.min.y, b.rect.min.x))
+            .unwrap()
+    });
+    let mut merged: Vec<TileShape> = Vec::new();
+    for shape in shapes {
+        if let Some(last) = merged.last_mut() {
+            let joinable = matches!(shape.kind, ObstacleKind::Box | ObstacleKind::Platform);
+            if joinable
+                && last.kind == shape.kind
+                && last.rect.min.y == shape.rect.min.y
+                && last.rect.max.y == shape.rect.max.y
+                && last.rect.max.x == shape.rect.min.x
+            {
+                last.rect.max.x = shape.rect.max.x;
+                continue;
+            }
+        }
+        merged.push(shape);
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{AngularVelocity, Rotation, Velocity};
+    use crate::prefab::Prefabs;
+    use crate::random::Random;
+    use crate::render_system::{Sprite, Transform};
+    use crate::snapshot::{SaveMarker, Saved};
+    use crate::tint_system::ColorBySpeed;
+    use serde_json::json;
+    use specs::saveload::SimpleMarkerAllocator;
+
+    /// A 20 by 3 map of 10 pixel tiles, with a floor of blocks along the
+    /// bottom, a flipped slope and a platform above them.
+    fn map_json() -> Value {
+        let mut floor = vec![0; 60];
+        for tile in floor[40..].iter_mut() {
+            *tile = 1;
+        }
+        floor[21] = 2 | FLIPPED_HORIZONTALLY;
+        floor[2] = 3;
+        json!({
+            "width": 20, "height": 3, "tilewidth": 10, "tileheight": 10,
+            "orientation": "orthogonal", "infinite": false,
+            "layers": [
+                { "type": "tilelayer", "name": "ground", "width": 20, "height": 3, "data": floor, "opacity": 0.5 },
+                { "type": "group", "layers": [
+                    { "type": "objectgroup", "objects": [
+                        { "id": 1, "type": "bunny", "x": 50, "y": 5, "width": 0, "height": 0, "point": true },
+                        { "id": 2, "class": "obstacle", "x": 100, "y": 0, "width": 20, "height": 10,
+                          "properties": [{ "name": "kind", "type": "string", "value": "platform" }] },
+                        { "id": 3, "x": 0, "y": 0, "width": 5, "height": 5 },
+                        { "id": 4, "gid": 3 | FLIPPED_HORIZONTALLY, "x": 150, "y": 10, "width": 20, "height": 10 }
+                    ] }
+                ] },
+                { "type": "imagelayer", "image": "sky.png" }
+            ],
+            "tilesets": [{
+                "firstgid": 1, "name": "tiles", "image": "../images/tiles.png",
+                "imagewidth": 32, "imageheight": 10, "tilewidth": 10, "tileheight": 10,
+                "columns": 3, "spacing": 1, "margin": 0, "tilecount": 3,
+                "tiles": [
+                    { "id": 0, "properties": [{ "name": "solid", "type": "bool", "value": true }] },
+                    { "id": 1, "properties": [{ "name": "kind", "type": "string", "value": "slope_up" }],
+                      "objectgroup": { "objects": [{ "id": 1, "x": 0, "y": 0, "width": 10, "height": 10 }] } },
+                    { "id": 2, "objectgroup": { "objects": [{ "id": 1, "x": 0, "y": 0, "width": 10, "height": 2,
+                      "properties": [{ "name": "kind", "type": "string", "value": "platform" }] }] } }
+                ]
+            }]
+        })
+    }
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.register::<Position>();
+        world.register::<Velocity>();
+        world.register::<TileChunk>();
+        world.register::<Obstacle>();
+        world.register::<Layer>();
+        world.register::<ZIndex>();
+        world.register::<Tint>();
+        world.insert(Random::from_seed(1));
+        world.insert(
+            Prefabs::from_json(r#"{ "bunny": { "velocity": { "x": 1, "y": 0 } } }"#).unwrap(),
+        );
+        world
+    }
+
+    fn images(path: &str) -> Option<ImageId> {
+        match path {
+            "/assets/images/tiles.png" => Some(ImageId { id: 7 }),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn the_map_spawns() {
+        let mut world = world();
+        world.insert(Prefabs::builtin(&[(
+            "/assets/images/bunny.png",
+            Vec2::new(26, 37),
+        )]));
+        world.register::<Sprite>();
+        world.register::<Transform>();
+        world.register::<Rotation>();
+        world.register::<AngularVelocity>();
+        world.register::<ColorBySpeed>();
+        world.register::<SaveMarker>();
+        world.insert(SimpleMarkerAllocator::<Saved>::default());
+
+        let map = TileMap::from_json(
+            include_str!("../assets/maps/map.json"),
+            "/assets/maps/map.json",
+        )
+        .unwrap();
+        let paths = map.image_paths();
+        assert_eq!(
+            paths,
+            vec!["/assets/images/block.png", "/assets/images/platform.png"]
+        );
+        map.spawn(&mut world, |path| {
+            paths
+                .iter()
+                .position(|p| p == path)
+                .map(|id| ImageId { id: id as u32 })
+        })
+        .unwrap();
+        // A ledge and a row of platforms.
+        assert_eq!((&world.read_storage::<Obstacle>()).join().count(), 2);
+        assert_eq!((&world.read_storage::<SaveMarker>()).join().count(), 2);
+    }
+
+    #[test]
+    fn tileset_images_are_relative_to_the_map() {
+        let map = TileMap::from_json(&map_json().to_string(), "/assets/maps/test.json").unwrap();
+        assert_eq!(map.image_paths(), vec!["/assets/images/tiles.png"]);
+
+        let mut json = map_json();
+        let tileset = json["tilesets"][0].clone();
+        for (firstgid, image) in [(10, "sky.png"), (20, "../images/tiles.png")] {
+            let mut other = tileset.clone();
+            other["firstgid"] = json!(firstgid);
+            other["image"] = json!(image);
+            json["tilesets"].as_array_mut().unwrap().push(other);
+        }
+        let map = TileMap::from_json(&json.to_string(), "/assets/maps/test.json").unwrap();
+        assert_eq!(
+            map.image_paths(),
+            vec!["/assets/images/tiles.png", "/assets/maps/sky.png"]
+        );
+        assert_eq!(map.pixel_size(), Vec2::new(200., 30.));
+        assert_eq!(resolve("/a/b.json", "./c/d.png"), "/a/c/d.png");
+        assert_eq!(resolve("/a/b.json", "/e.png"), "/e.png");
+    }
+
+    #[test]
+    fn unsupported_maps_are_rejected() {
+        let parse = |change: fn(&mut Value)| {
+            let mut json = map_json();
+            change(&mut json);
+            TileMap::from_json(&json.to_string(), "/map.json")
+        };
+        assert!(parse(|_| {}).is_ok());
+        assert!(parse(|json| json["orientation"] = json!("isometric")).is_err());
+        assert!(parse(|json| json["infinite"] = json!(true)).is_err());
+        assert!(
+            parse(|json| json["tilesets"] = json!([{ "firstgid": 1, "source": "tiles.tsx" }]))
+                .is_err()
+        );
+
+        let mut world = world();
+        let map = parse(|json| json["layers"][0]["data"] = json!("AAAA")).unwrap();
+        assert!(map.spawn(&mut world, images).is_err());
+        let map =
+            parse(|json| json["layers"][0]["data"][0] = json!(1 | FLIPPED_DIAGONALLY)).unwrap();
+        assert!(map.spawn(&mut world, images).is_err());
+        let map =
+            parse(|json| json["layers"][1]["layers"][0]["objects"][0]["type"] = json!("carrot"))
+                .unwrap();
+        assert!(map.spawn(&mut world, images).is_err());
+        let map = parse(|_| {}).unwrap();
+        assert!(map.spawn(&mut world, |_| None).is_err());
+    }
+
+    #[test]
+    fn tile_layers_become_chunks() {
+        let mut world = world();
+        let map = TileMap::from_json(&map_json().to_string(), "/assets/maps/test.json").unwrap();
+        map.spawn(&mut world, images).unwrap();
+
+        let chunks = world.read_storage::<TileChunk>();
+        let positions = world.read_storage::<Position>();
+        let tints = world.read_storage::<Tint>();
+        let mut chunks: Vec<_> = (&chunks, &positions, &tints).join().collect();
+        chunks.sort_by(|a, b| a.0.min.x.partial_cmp(&b.0.min.x).unwrap());
+        // The map is 20 tiles wide, so the chunks split at 16.
+        assert_eq!(chunks.len(), 2);
+        let (left, position, tint) = chunks[0];
+        assert_eq!(position.0, Vec2::zero());
+        assert_eq!(tint.0.a, 0.5);
+        assert_eq!(left.image, ImageId { id: 7 });
+        assert_eq!(left.quads.len(), 16 + 2);
+        assert_eq!((left.min, left.max), (Vec2::zero(), Vec2::new(160., 30.)));
+        assert_eq!(chunks[1].0.quads.len(), 4);
+
+        let quad = |min: Vec2<f32>| *left.quads.iter().find(|quad| quad.min == min).unwrap();
+        // Second tile of the bottom row, the image has a pixel between tiles.
+        let block = quad(Vec2::new(10., 0.));
+        assert_eq!(block.max, Vec2::new(20., 10.));
+        assert_eq!(
+            (block.uv_min, block.uv_max),
+            (Vec2::zero(), Vec2::new(10. / 32., 1.))
+        );
+        let slope = quad(Vec2::new(10., 10.));
+        assert_eq!(slope.uv_min, Vec2::new(21. / 32., 0.));
+        assert_eq!(slope.uv_max, Vec2::new(11. / 32., 1.));
+        assert_eq!(quad(Vec2::new(20., 20.)).uv_min, Vec2::new(22. / 32., 0.));
+        assert_eq!(
+            block.corners(Vec2::new(100., 0.)),
+            [
+                Vec2::new(110., 10.),
+                Vec2::new(120., 10.),
+                Vec2::new(110., 0.),
+                Vec2::new(120., 0.),
+            ]
+        );
+    }
+
+    #[test]
+    fn tile_objects_are_drawn() {
+        let mut world = world();
+        let map = TileMap::from_json(&map_json().to_string(), "/assets/maps/test.json").unwrap();
+        map.spawn(&mut world, images).unwrap();
+
+        let chunks = world.read_storage::<TileChunk>();
+        let positions = world.read_storage::<Position>();
+        let z_indices = world.read_storage::<ZIndex>();
+        let tiles: Vec<_> = (&chunks, &positions, &z_indices)
+            .join()
+            .filter(|(_, _, z)| **z == ZIndex(1))
+            .collect();
+        assert_eq!(tiles.len(), 1);
+        let (tile, position, _) = tiles[0];
+        // Placed by its bottom left corner, 10 pixels below the top.
+        assert_eq!(position.0, Vec2::new(150., 20.));
+        assert_eq!(
+            tile.quads,
+            vec![TileQuad {
+                min: Vec2::zero(),
+                max: Vec2::new(20., 10.),
+                uv_min: Vec2::new(32. / 32., 0.),
+                uv_max: Vec2::new(22. / 32., 1.),
+            }]
+        );
+    }
+
+    #[test]
+    fn shapes_become_obstacles_and_objects_prefabs() {
+        let mut world = world();
+        let map = TileMap::from_json(&map_json().to_string(), "/assets/maps/test.json").unwrap();
+        map.spawn(&mut world, images).unwrap();
+
+        let positions = world.read_storage::<Position>();
+        let mut obstacles: Vec<_> = (&world.read_storage::<Obstacle>(), &positions)
+            .join()
+            .map(|(obstacle, position)| (obstacle.kind, position.0, obstacle.half_extents))
+            .collect();
+        obstacles.sort_by(|a, b| a.1.x.partial_cmp(&b.1.x).unwrap());
+        assert_eq!(
+            obstacles,
+            vec![
+                // The slope was flipped.
+                (
+                    ObstacleKind::SlopeDown,
+                    Vec2::new(15., 15.),
+                    Vec2::new(5., 5.)
+                ),
+                (
+                    ObstacleKind::Platform,
+                    Vec2::new(25., 29.),
+                    Vec2::new(5., 1.)
+                ),
+                // The whole floor.
+                (ObstacleKind::Box, Vec2::new(100., 5.), Vec2::new(100., 5.)),
+                (
+                    ObstacleKind::Platform,
+                    Vec2::new(110., 25.),
+                    Vec2::new(10., 5.)
+                ),
+            ]
+        );
+
+        let bunnies: Vec<_> = (&world.read_storage::<Velocity>(), &positions)
+            .join()
+            .map(|(_, position)| position.0)
+            .collect();
+        assert_eq!(bunnies, vec![Vec2::new(50., 25.)]);
+    }
+}
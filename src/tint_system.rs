@@ -0,0 +1,89 @@
+use crate::components::Velocity;
+use crate::render_system::Tint;
+use serde::{Deserialize, Serialize};
+use specs::prelude::*;
+use specs::{Component, DenseVecStorage};
+use vek::{Lerp, Rgba};
+
+/// Tints the entity from `slow` when still to `fast` at `max_speed` pixels
+/// per frame and above.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[storage(DenseVecStorage)]
+#[serde(default)]
+pub struct ColorBySpeed {
+    pub slow: Rgba<f32>,
+    pub fast: Rgba<f32>,
+    pub max_speed: f32,
+}
+
+impl Default for ColorBySpeed {
+    fn default() -> Self {
+        ColorBySpeed {
+            slow: Rgba::white(),
+            fast: Rgba::new(1., 0.3, 0.2, 1.),
+            max_speed: 15.,
+        }
+    }
+}
+
+impl ColorBySpeed {
+    pub fn color(&self, speed: f32) -> Rgba<f32> {
+        Lerp::lerp(self.slow, self.fast, speed / self.max_speed)
+    }
+}
+
+pub struct ColorBySpeedSystem;
+
+impl<'a> System<'a> for ColorBySpeedSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Velocity>,
+        ReadStorage<'a, ColorBySpeed>,
+        WriteStorage<'a, Tint>,
+    );
+
+    fn run(&mut self, (entities, velocities, colors, mut tints): Self::SystemData) {
+        for (entity, velocity, color) in (&entities, &velocities, &colors).join() {
+            let tint = Tint(color.color(velocity.0.magnitude()));
+            tints.insert(entity, tint).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn color_is_clamped_at_max_speed() {
+        let color = ColorBySpeed {
+            slow: Rgba::new(1., 1., 1., 1.),
+            fast: Rgba::new(1., 0., 0., 0.5),
+            max_speed: 10.,
+        };
+        assert_eq!(color.color(0.), color.slow);
+        assert_eq!(color.color(5.), Rgba::new(1., 0.5, 0.5, 0.75));
+        assert_eq!(color.color(50.), color.fast);
+    }
+
+    #[test]
+    fn system_tints_moving_entities() {
+        let mut world = World::new();
+        world.register::<Velocity>();
+        world.register::<ColorBySpeed>();
+        world.register::<Tint>();
+        let moving = world
+            .create_entity()
+            .with(Velocity::new(30., 40.))
+            .with(ColorBySpeed::default())
+            .build();
+        let plain = world.create_entity().with(Velocity::new(30., 40.)).build();
+
+        ColorBySpeedSystem.run_now(&world);
+
+        let tints = world.read_storage::<Tint>();
+        let difference = tints.get(moving).unwrap().0 - ColorBySpeed::default().fast;
+        assert!(difference.map(f32::abs).reduce_partial_max() < 1e-5);
+        assert_eq!(tints.get(plain), None);
+    }
+}
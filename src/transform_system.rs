@@ -0,0 +1,447 @@
+use crate::components::{Pivot, Position, Rotation, Scale};
+use crate::hierarchy::{despawn_recursive, Children, Parent};
+use crate::render_system::Transform;
+use specs::prelude::*;
+use vek::Vec2;
+
+/// Combines `Rotation`, `Scale` and `Pivot` into the `Transform` used for
+/// rendering, picking and collisions.
+///
+/// Entities without any of them keep the upright, centered quad they were
+/// created with. Children are left to `TransformPropagationSystem`.
+pub struct TransformSystem;
+
+impl<'a> System<'a> for TransformSystem {
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, Transform>,
+        ReadStorage<'a, Rotation>,
+        ReadStorage<'a, Scale>,
+        ReadStorage<'a, Pivot>,
+        ReadStorage<'a, Parent>,
+    );
+
+    fn run(
+        &mut self,
+        (entities, mut transforms, rotations, scales, pivots, parents): Self::SystemData,
+    ) {
+        let composed = rotations.mask() | scales.mask() | pivots.mask();
+        for (entity, _, _) in (&entities, &composed, !&parents).join() {
+            let mut transform = match transforms.get(entity) {
+                Some(transform) => *transform,
+                None => continue,
+            };
+            let rotation = rotations.get(entity).copied().unwrap_or_default();
+            let scale = scales.get(entity).copied().unwrap_or_default();
+            let pivot = pivots.get(entity).copied().unwrap_or_default();
+            transform.compose(rotation.0, scale.0, pivot.0);
+            // Writing flags the transform as changed, so leave still ones be.
+            if transforms.get(entity) != Some(&transform) {
+                transforms.insert(entity, transform).unwrap();
+            }
+        }
+    }
+}
+
+/// World space placement of an entity.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Pose {
+    position: Vec2<f32>,
+    rotation: f32,
+    scale: Vec2<f32>,
+}
+
+impl Pose {
+    /// Placement of a child at the given local position, rotation and scale.
+    ///
+    /// Scales are simply multiplied, so a rotated child of a non uniformly
+    /// scaled parent isn't skewed.
+    fn child(&self, position: Vec2<f32>, rotation: f32, scale: Vec2<f32>) -> Pose {
+        Pose {
+            position: self.position + (position * self.scale).rotated_z(self.rotation),
+            rotation: self.rotation + rotation,
+            scale: self.scale * scale,
+        }
+    }
+}
+
+/// Places children relative to their parents, walking down from every root
+/// of a hierarchy. Runs after `TransformSystem` so roots are already
+/// composed.
+///
+/// Children whose parent was deleted are deleted too, unless their `Parent`
+/// says otherwise, in which case they become roots where they were.
+pub struct TransformPropagationSystem {
+    orphans: Vec<(Entity, Parent)>,
+    stack: Vec<(Entity, Pose)>,
+}
+
+impl TransformPropagationSystem {
+    pub fn new() -> Self {
+        TransformPropagationSystem {
+            orphans: Vec::new(),
+            stack: Vec::new(),
+        }
+    }
+}
+
+impl Default for TransformPropagationSystem {
+    fn default() -> Self {
+        TransformPropagationSystem::new()
+    }
+}
+
+impl<'a> System<'a> for TransformPropagationSystem {
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, Parent>,
+        WriteStorage<'a, Children>,
+        WriteStorage<'a, Position>,
+        WriteStorage<'a, Transform>,
+        ReadStorage<'a, Rotation>,
+        ReadStorage<'a, Scale>,
+        ReadStorage<'a, Pivot>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            mut parents,
+            mut children,
+            mut positions,
+            mut transforms,
+            rotations,
+            scales,
+            pivots,
+        ): Self::SystemData,
+    ) {
+        self.orphans.clear();
+        self.orphans.extend(
+            (&entities, &parents)
+                .join()
+                .filter(|(_, parent)| !entities.is_alive(parent.entity))
+                .map(|(entity, parent)| (entity, *parent)),
+        );
+        for (entity, parent) in self.orphans.iter() {
+            if parent.despawn_with_parent {
+                despawn_recursive(&entities, &children, *entity);
+                continue;
+            }
+            parents.remove(*entity);
+            if let (Some(position), Some(transform)) =
+                (positions.get_mut(*entity), transforms.get_mut(*entity))
+            {
+                position.0 = transform.world_position(position.0);
+                transform.set_parent_offset(Vec2::zero());
+            }
+        }
+
+        for children in (&mut children).join() {
+            children.retain(|entity| entities.is_alive(*entity));
+        }
+
+        let local = |entity: Entity| {
+            let rotation = rotations.get(entity).copied().unwrap_or_default();
+            let scale = scales.get(entity).copied().unwrap_or_default();
+            (rotation.0, scale.0)
+        };
+
+        self.stack.clear();
+        for (entity, position, _, _) in (&entities, &positions, &children, !&parents).join() {
+            let (rotation, scale) = local(entity);
+            self.stack.push((
+                entity,
+                Pose {
+                    position: position.0,
+                    rotation,
+                    scale,
+                },
+            ));
+        }
+
+        while let Some((entity, pose)) = self.stack.pop() {
+            let children = match children.get(entity) {
+                Some(children) => children,
+                None => continue,
+            };
+            for child in children.iter() {
+                let position = match positions.get(*child) {
+                    Some(position) => position.0,
+                    None => continue,
+                };
+                let (rotation, scale) = local(*child);
+                let child_pose = pose.child(position, rotation, scale);
+                if let Some(transform) = transforms.get_mut(*child) {
+                    let pivot = pivots.get(*child).copied().unwrap_or_default();
+                    transform.compose(child_pose.rotation, child_pose.scale, pivot.0);
+                    transform.set_parent_offset(child_pose.position - position);
+                }
+                self.stack.push((*child, child_pose));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hierarchy::set_parent;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn assert_close(actual: Vec2<f32>, expected: Vec2<f32>) {
+        assert!(
+            (actual - expected).magnitude() < 1e-4,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    fn corners(transform: &Transform) -> [Vec2<f32>; 4] {
+        transform.corners(Vec2::new(100., 100.))
+    }
+
+    #[test]
+    fn default_composition_is_an_upright_centered_quad() {
+        let mut transform = Transform::new(Vec2::new(20., 10.));
+        let upright = transform;
+        transform.compose(0., Vec2::one(), Vec2::broadcast(0.5));
+        assert_eq!(transform, upright);
+        assert_eq!(
+            corners(&transform),
+            [
+                Vec2::new(90., 105.),
+                Vec2::new(110., 105.),
+                Vec2::new(90., 95.),
+                Vec2::new(110., 95.),
+            ]
+        );
+    }
+
+    #[test]
+    fn quads_rotate_counter_clockwise() {
+        let mut transform = Transform::new(Vec2::new(20., 10.));
+        transform.compose(FRAC_PI_2, Vec2::one(), Vec2::broadcast(0.5));
+
+        // The top left corner ends up at the bottom left.
+        let [top_left, top_right, ..] = corners(&transform);
+        assert_close(top_left, Vec2::new(95., 90.));
+        assert_close(top_right, Vec2::new(95., 110.));
+        assert_close(transform.extents(), Vec2::new(5., 10.));
+    }
+
+    #[test]
+    fn scale_stretches_and_mirrors() {
+        let mut transform = Transform::new(Vec2::new(20., 10.));
+        transform.compose(0., Vec2::new(-2., 3.), Vec2::broadcast(0.5));
+
+        let [top_left, .., bottom_right] = corners(&transform);
+        assert_close(top_left, Vec2::new(120., 115.));
+        assert_close(bottom_right, Vec2::new(80., 85.));
+        assert_close(transform.extents(), Vec2::new(20., 15.));
+    }
+
+    #[test]
+    fn quads_rotate_around_the_pivot() {
+        let mut transform = Transform::new(Vec2::new(20., 10.));
+        transform.compose(0., Vec2::one(), Vec2::new(0.5, 0.));
+        assert_close(
+            transform.center(Vec2::new(100., 100.)),
+            Vec2::new(100., 105.),
+        );
+
+        // Standing on its bottom center and tipped over to the left.
+        transform.compose(FRAC_PI_2, Vec2::new(2., 2.), Vec2::new(0.5, 0.));
+        assert_close(
+            transform.center(Vec2::new(100., 100.)),
+            Vec2::new(90., 100.),
+        );
+        assert!(transform.contains(Vec2::new(100., 100.), Vec2::new(81., 100.)));
+        assert!(!transform.contains(Vec2::new(100., 100.), Vec2::new(101., 100.)));
+    }
+
+    #[test]
+    fn system_only_touches_entities_with_transform_components() {
+        let mut world = World::new();
+        world.register::<Position>();
+        world.register::<Transform>();
+        world.register::<Rotation>();
+        world.register::<Scale>();
+        world.register::<Pivot>();
+        world.register::<Parent>();
+        let base = Transform::new(Vec2::new(20., 10.));
+        let plain = world.create_entity().with(base).build();
+        let scaled = world
+            .create_entity()
+            .with(base)
+            .with(Scale(Vec2::new(2., 2.)))
+            .build();
+
+        TransformSystem.run_now(&world);
+
+        let transforms = world.read_storage::<Transform>();
+        assert_eq!(transforms.get(plain), Some(&base));
+        assert_close(
+            transforms.get(scaled).unwrap().extents(),
+            Vec2::new(20., 10.),
+        );
+    }
+
+    #[test]
+    fn unchanged_transforms_are_not_flagged() {
+        let mut world = World::new();
+        world.register::<Transform>();
+        world.register::<Rotation>();
+        world.register::<Scale>();
+        world.register::<Pivot>();
+        world.register::<Parent>();
+        let entity = world
+            .create_entity()
+            .with(Transform::new(Vec2::new(20., 10.)))
+            .with(Rotation(1.))
+            .build();
+        let mut reader = world.write_storage::<Transform>().register_reader();
+        let mut changes = |world: &World| {
+            TransformSystem.run_now(world);
+            world
+                .read_storage::<Transform>()
+                .channel()
+                .read(&mut reader)
+                .count()
+        };
+
+        assert_eq!(changes(&world), 1);
+        assert_eq!(changes(&world), 0);
+        world.write_storage().insert(entity, Rotation(2.)).unwrap();
+        assert_eq!(changes(&world), 1);
+    }
+
+    fn hierarchy_world() -> World {
+        let mut world = World::new();
+        world.register::<Position>();
+        world.register::<Transform>();
+        world.register::<Rotation>();
+        world.register::<Scale>();
+        world.register::<Pivot>();
+        world.register::<Parent>();
+        world.register::<Children>();
+        world
+    }
+
+    fn attach(world: &World, child: Entity, parent: Parent) {
+        set_parent(
+            &mut world.write_storage(),
+            &mut world.write_storage(),
+            child,
+            parent,
+        )
+        .unwrap();
+    }
+
+    fn run(world: &mut World) {
+        TransformSystem.run_now(world);
+        TransformPropagationSystem::new().run_now(world);
+        world.maintain();
+    }
+
+    fn center(world: &World, entity: Entity) -> Vec2<f32> {
+        let position = world.read_storage::<Position>().get(entity).unwrap().0;
+        world
+            .read_storage::<Transform>()
+            .get(entity)
+            .unwrap()
+            .center(position)
+    }
+
+    #[test]
+    fn children_follow_rotated_and_scaled_parents() {
+        let mut world = hierarchy_world();
+        let size = Transform::new(Vec2::new(10., 10.));
+        let bunny = world
+            .create_entity()
+            .with(Position::new(100., 100.))
+            .with(Rotation(FRAC_PI_2))
+            .with(Scale(Vec2::new(2., 2.)))
+            .with(size)
+            .build();
+        let hat = world
+            .create_entity()
+            .with(Position::new(0., 10.))
+            .with(size)
+            .build();
+        let feather = world
+            .create_entity()
+            .with(Position::new(5., 0.))
+            .with(Rotation(FRAC_PI_2))
+            .with(size)
+            .build();
+        attach(&world, hat, Parent::new(bunny));
+        attach(&world, feather, Parent::new(hat));
+
+        run(&mut world);
+
+        // Above the bunny in its own space, so to its left once rotated.
+        assert_close(center(&world, hat), Vec2::new(80., 100.));
+        assert_close(center(&world, feather), Vec2::new(80., 110.));
+        let transforms = world.read_storage::<Transform>();
+        assert_close(
+            transforms.get(feather).unwrap().extents(),
+            Vec2::new(10., 10.),
+        );
+        assert!(transforms
+            .get(feather)
+            .unwrap()
+            .contains(Vec2::new(5., 0.), Vec2::new(89., 110.)));
+    }
+
+    #[test]
+    fn orphans_are_despawned_or_left_in_place() {
+        let mut world = hierarchy_world();
+        let size = Transform::new(Vec2::new(10., 10.));
+        let bunny = world
+            .create_entity()
+            .with(Position::new(100., 100.))
+            .with(size)
+            .build();
+        let hat = world
+            .create_entity()
+            .with(Position::new(0., 10.))
+            .with(size)
+            .build();
+        let feather = world
+            .create_entity()
+            .with(Position::new(0., 5.))
+            .with(size)
+            .build();
+        let shadow = world
+            .create_entity()
+            .with(Position::new(0., -10.))
+            .with(size)
+            .build();
+        attach(&world, hat, Parent::new(bunny));
+        attach(&world, feather, Parent::new(hat));
+        attach(
+            &world,
+            shadow,
+            Parent {
+                entity: bunny,
+                despawn_with_parent: false,
+            },
+        );
+        run(&mut world);
+
+        world.delete_entity(bunny).unwrap();
+        run(&mut world);
+
+        assert!(!world.is_alive(hat));
+        assert!(!world.is_alive(feather));
+        assert!(world.is_alive(shadow));
+        assert!(!world.read_storage::<Parent>().contains(shadow));
+        assert_eq!(
+            world.read_storage::<Position>().get(shadow).unwrap().0,
+            Vec2::new(100., 90.)
+        );
+        assert_close(center(&world, shadow), Vec2::new(100., 90.));
+    }
+}